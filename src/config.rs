@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use crate::{
+    congestion::{INITIAL_CONGESTION_WINDOW, MAX_CONGESTION_WINDOW},
+    connection::{MAX_EXPIRATIONS, MIN_EXPIRATION, TIMING_INTERVAL},
+    core::{
+        channel::MAX_PACKET_SIZE,
+        path_mtu::{IPV4_MAX_PAYLOAD, PMTU_INTERVAL},
+        KEEP_ALIVE_INTERVAL, SYN_INTERVAL,
+    },
+    packet::{control::handshake::FLOW_CONTROL, DATA_HEADER_SIZE},
+};
+
+pub use crate::core::{recv::recv_buffer::Overflow, send::send_buffer::Scheduling};
+pub use crate::packet::control::handshake::{
+    CAPABILITIES, CAP_BUNDLE, CAP_DATAGRAM, CAP_PMTU, CAP_TIMING, MIN_WIRE_VERSION, WIRE_VERSION,
+};
+
+//What Read and Write on a NeonStream carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamMode {
+    #[default]
+    Message, //one write is one read, the io traits refuse
+    Bytes,   //no boundaries, small writes are packed into full packets like tcp
+}
+
+pub const SEND_BUFFER_PACKETS: usize = 8192;
+pub const SEND_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+pub const RECV_BUFFER_PACKETS: usize = 8192;
+pub const RECV_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+pub const RECV_BUFFER_MESSAGES: usize = 8192;
+//datagrams per connection waiting either to go out or to be read, past this the oldest are thrown away
+pub const DATAGRAM_BACKLOG: usize = 256;
+pub const LINGER: Duration = Duration::from_secs(5);
+pub const BACKLOG: usize = 128;
+
+/*
+    Per core settings, every connection made on a core inherits these
+    The defaults match the old compile time constants so NeonConfig::default() behaves like before
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct NeonConfig {
+    pub(crate) mss: u16,                      //largest payload we offer / accept before discovery
+    pub(crate) max_mss: Option<u16>,          //path mtu search ceiling, none uses the ip family limit
+    pub(crate) pmtu_interval: Duration,       //how long a settled path mtu is trusted
+    pub(crate) timing_interval: Duration,     //how often the timestamp epoch is resynced with the partner
+    pub(crate) flow_window: u16,              //advertised in the handshake
+    pub(crate) ack_interval: Duration,        //the syn interval, drives acks and ack squares
+    pub(crate) keep_alive_interval: Duration, //how often the state thread wakes up
+    pub(crate) min_expiration: Duration,      //lower bound on the keep alive expiration
+    pub(crate) max_expirations: usize,        //expirations before a connection is unhealthy
+    pub(crate) congestion_window: usize,      //packets
+    pub(crate) max_congestion_window: usize,  //packets, leaves slow start past this
+    pub(crate) send_buffer: usize,            //packets held per connection waiting for an ack
+    pub(crate) send_bytes: usize,             //payload bytes held per connection, whichever runs out first blocks writes
+    pub(crate) recv_buffer: usize,            //packets held per connection waiting for a read
+    pub(crate) recv_bytes: usize,             //payload bytes held per connection waiting for a read
+    pub(crate) recv_messages: usize,          //messages held per connection, finished or not
+    pub(crate) overflow: Overflow,            //what happens to new data once any of the receive limits is hit
+    pub(crate) linger: Duration,              //how long a close waits for the send buffer to be acked
+    pub(crate) backlog: usize,                //connections waiting on accept before handshakes are refused
+    pub(crate) scheduling: Scheduling,        //how fresh messages are ordered on the way out
+    pub(crate) mode: StreamMode,              //picked up by every stream connected or accepted on the core
+    pub(crate) coalesce: Option<Duration>,    //how long small messages wait to share a packet, none sends each alone
+    pub(crate) capabilities: u32,             //features offered in the handshake, see CAP_*
+    pub(crate) wire_version: u16,             //the newest version offered in the handshake
+}
+
+impl Default for NeonConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NeonConfig {
+    pub fn new() -> Self {
+        Self {
+            mss: MAX_PACKET_SIZE,
+            max_mss: None,
+            pmtu_interval: PMTU_INTERVAL,
+            timing_interval: TIMING_INTERVAL,
+            flow_window: FLOW_CONTROL,
+            ack_interval: SYN_INTERVAL,
+            keep_alive_interval: KEEP_ALIVE_INTERVAL,
+            min_expiration: MIN_EXPIRATION,
+            max_expirations: MAX_EXPIRATIONS,
+            congestion_window: INITIAL_CONGESTION_WINDOW,
+            max_congestion_window: MAX_CONGESTION_WINDOW,
+            send_buffer: SEND_BUFFER_PACKETS,
+            send_bytes: SEND_BUFFER_BYTES,
+            recv_buffer: RECV_BUFFER_PACKETS,
+            recv_bytes: RECV_BUFFER_BYTES,
+            recv_messages: RECV_BUFFER_MESSAGES,
+            overflow: Overflow::Refuse,
+            linger: LINGER,
+            backlog: BACKLOG,
+            scheduling: Scheduling::Weighted,
+            mode: StreamMode::Message,
+            coalesce: None,
+            capabilities: CAPABILITIES,
+            wire_version: WIRE_VERSION,
+        }
+    }
+    pub fn mss(mut self, mss: u16) -> Self {
+        self.mss = mss;
+        self
+    }
+    //jumbo frames or a known smaller path, setting this to the mss turns discovery off
+    pub fn max_mss(mut self, max_mss: u16) -> Self {
+        self.max_mss = Some(max_mss);
+        self
+    }
+    pub fn pmtu_interval(mut self, pmtu_interval: Duration) -> Self {
+        self.pmtu_interval = pmtu_interval;
+        self
+    }
+    //past the 71 minutes it takes a timestamp to wrap the partner can't tell old ones from new
+    pub fn timing_interval(mut self, timing_interval: Duration) -> Self {
+        self.timing_interval = timing_interval;
+        self
+    }
+    pub fn flow_window(mut self, flow_window: u16) -> Self {
+        self.flow_window = flow_window;
+        self
+    }
+    pub fn ack_interval(mut self, ack_interval: Duration) -> Self {
+        self.ack_interval = ack_interval;
+        self
+    }
+    pub fn keep_alive_interval(mut self, keep_alive_interval: Duration) -> Self {
+        self.keep_alive_interval = keep_alive_interval;
+        self
+    }
+    pub fn expiration(mut self, min_expiration: Duration, max_expirations: usize) -> Self {
+        self.min_expiration = min_expiration;
+        self.max_expirations = max_expirations;
+        self
+    }
+    pub fn congestion_window(mut self, initial: usize, max: usize) -> Self {
+        self.congestion_window = initial;
+        self.max_congestion_window = max.max(initial);
+        self
+    }
+    pub fn buffers(mut self, send_packets: usize, recv_packets: usize) -> Self {
+        self.send_buffer = send_packets;
+        self.recv_buffer = recv_packets;
+        self
+    }
+    //streams can change it for their own connection with set_send_limits
+    pub fn send_bytes(mut self, send_bytes: usize) -> Self {
+        self.send_bytes = send_bytes;
+        self
+    }
+    //on top of the packet limit in buffers, the partner is only told about the room that's left
+    pub fn recv_limits(mut self, recv_bytes: usize, recv_messages: usize) -> Self {
+        self.recv_bytes = recv_bytes;
+        self.recv_messages = recv_messages;
+        self
+    }
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+    //zero drops anything unacknowledged on close
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+    pub fn backlog(mut self, backlog: usize) -> Self {
+        self.backlog = backlog;
+        self
+    }
+    //deadline ignores priorities, it's for traffic where a late message is worthless
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+    pub fn mode(mut self, mode: StreamMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    //small messages on a channel wait up to the delay for company, a full packet goes straight away
+    pub fn coalesce(mut self, delay: Duration) -> Self {
+        self.coalesce = Some(delay);
+        self
+    }
+    //the partner only gets the features both sides offer
+    pub fn capabilities(mut self, capabilities: u32) -> Self {
+        self.capabilities = capabilities & CAPABILITIES;
+        self
+    }
+    //version 1 holds every connection to 15 bit seqs and 13 bit msg nos on the wire
+    pub fn wire_version(mut self, version: u16) -> Self {
+        self.wire_version = version.clamp(MIN_WIRE_VERSION, WIRE_VERSION);
+        self
+    }
+    //What a connection runs with once the partner's features are known
+    pub(crate) fn agreed(mut self, features: u32) -> Self {
+        if features & CAP_BUNDLE == 0 {
+            self.coalesce = None;
+        }
+        self
+    }
+    //the receive buffer has to fit the largest probe either side could send
+    pub(crate) fn max_payload(&self) -> u16 {
+        let ceiling = match self.max_mss {
+            Some(max_mss) => max_mss,
+            None => IPV4_MAX_PAYLOAD - DATA_HEADER_SIZE as u16,
+        };
+        ceiling.max(self.mss)
+    }
+}
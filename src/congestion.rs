@@ -1,10 +1,13 @@
 use std::time::{Duration, SystemTime};
 
 use crate::{
-    core::{channel::MAX_PACKET_SIZE, SYN_INTERVAL},
+    config::NeonConfig,
     utils::{self, SequenceNumber},
 };
 
+pub const INITIAL_CONGESTION_WINDOW: usize = 16;
+pub const MAX_CONGESTION_WINDOW: usize = 16;
+
 #[derive(Debug)]
 pub struct CongestionController {
     pkt_send_period: Duration,
@@ -12,7 +15,7 @@ pub struct CongestionController {
     max_congestion_window: usize,
     bandwidth: usize, //packets per second
     mss: usize,
-    cur_send_seq_no: SequenceNumber,
+    cur_send_seq_no: Option<SequenceNumber>, //a wrapping seq has no sentinel, None until it's known
     recv_rate: usize, //packets per second
     rtt: Duration,
     rtt_var: Duration,
@@ -20,31 +23,32 @@ pub struct CongestionController {
     rc_interval: Duration,
     last_rc_time: SystemTime,
     slow_start: bool,
-    last_ack_seq_no: SequenceNumber,
+    last_ack_seq_no: Option<SequenceNumber>,
     loss: bool,
-    last_dec_seq_no: SequenceNumber,
+    last_dec_seq_no: Option<SequenceNumber>,
     last_dec_period: Duration,
     loss_count: usize,
     dec_random: usize,
     avg_loss: usize,
     dec_count: usize,
     pkt_count: usize,
+    syn_interval: Duration,
 }
 
 impl Default for CongestionController {
     fn default() -> Self {
-        Self::new()
+        Self::new(&NeonConfig::default())
     }
 }
 
 impl CongestionController {
-    pub fn new() -> Self {
+    pub fn new(config: &NeonConfig) -> Self {
         let pkt_send_period = Duration::from_micros(1);
-        let congestion_window = 16;
-        let max_congestion_window = 16;
+        let congestion_window = config.congestion_window;
+        let max_congestion_window = config.max_congestion_window;
         let bandwidth = 1;
-        let mss = MAX_PACKET_SIZE as usize;
-        let cur_send_seq_no = SequenceNumber::MAX_SEQ_NO;
+        let mss = config.mss as usize;
+        let cur_send_seq_no = None;
         let recv_rate = 16;
         let rtt = Duration::from_micros(10);
         let rtt_var = Duration::from_micros(1);
@@ -52,15 +56,16 @@ impl CongestionController {
         let rc_interval = Duration::from_micros(10_000);
         let last_rc_time = SystemTime::now();
         let slow_start = true;
-        let last_ack_seq_no = SequenceNumber::MAX_SEQ_NO;
+        let last_ack_seq_no = None;
         let loss = false;
-        let last_dec_seq_no = SequenceNumber::MAX_SEQ_NO;
+        let last_dec_seq_no = None;
         let last_dec_period = Duration::from_micros(1);
         let loss_count = 0;
         let dec_random = 1;
         let avg_loss = 0;
         let dec_count = 1;
         let pkt_count = 0;
+        let syn_interval = config.ack_interval;
         Self {
             pkt_send_period,
             congestion_window,
@@ -83,7 +88,8 @@ impl CongestionController {
             dec_random,
             avg_loss,
             dec_count,
-            pkt_count
+            pkt_count,
+            syn_interval,
         }
     }
     pub fn on_ack(&mut self, ack: SequenceNumber) {
@@ -99,8 +105,15 @@ impl CongestionController {
         }
         self.last_rc_time = SystemTime::now();
         if self.slow_start {
-            self.congestion_window += (ack - self.last_ack_seq_no) as usize;
-            self.last_ack_seq_no = ack;
+            //without a packet sent there's nothing to count from, stale acks add nothing
+            match self.last_ack_seq_no {
+                Some(last_ack) if last_ack.serial_lt(ack) => {
+                    self.congestion_window += (ack - last_ack) as usize;
+                    self.last_ack_seq_no = Some(ack);
+                }
+                Some(_) => {}
+                None => self.last_ack_seq_no = Some(ack),
+            }
             if self.congestion_window > self.max_congestion_window {
                 self.slow_start = false;
                 if self.recv_rate > 0 {
@@ -148,7 +161,7 @@ impl CongestionController {
     pub fn next_ack(&mut self)->Duration{
         self.pkt_count=0;
         if self.ack_interval==0{
-            SYN_INTERVAL
+            self.syn_interval
         }else{
             Duration::from_millis(self.ack_interval as u64) 
         }
@@ -169,14 +182,15 @@ impl CongestionController {
             self.pkt_send_period = Duration::from_micros(self.congestion_window as u64 / (self.rtt + self.rc_interval).as_micros() as u64)
         }
         self.loss = true;
-        if loss_start>self.last_dec_seq_no{
+        //once per round, a loss of something sent before the last decrease doesn't count again
+        if self.last_dec_seq_no.is_none_or(|last_dec| last_dec.serial_lt(loss_start)) {
             self.last_dec_period = self.pkt_send_period;
-            self.pkt_send_period = Duration::from_micros((self.pkt_send_period.as_millis() as f64 * 1.125) as u64);
+            self.pkt_send_period = Duration::from_micros((self.pkt_send_period.as_micros() as f64 * 1.125) as u64);
             self.avg_loss =(self.avg_loss as f64 *0.875+self.loss_count as f64 *0.125) as usize;
             self.loss_count+=1;
             self.dec_count+=1;
             self.last_dec_seq_no = self.cur_send_seq_no;
-            self.dec_random = utils::hash(self.last_dec_seq_no.0 as usize)/usize::MAX;
+            self.dec_random = utils::hash(self.last_dec_seq_no.map_or(0, |seq| seq.0) as usize)/usize::MAX;
             if self.dec_random < 1{
                 self.dec_random = 1;
             }
@@ -192,6 +206,16 @@ impl CongestionController {
             }
         }
     }
+    //The newest seq sent, a decrease covers everything up to it
+    pub fn on_send(&mut self, seq_no: SequenceNumber) {
+        //slow start counts acks from just before the first packet
+        if self.last_ack_seq_no.is_none() {
+            let mut start = seq_no;
+            start.dec();
+            self.last_ack_seq_no = Some(start);
+        }
+        self.cur_send_seq_no = Some(seq_no);
+    }
     pub fn next_time(&self)->Duration{
         self.pkt_send_period
     }
@@ -216,5 +240,8 @@ impl CongestionController {
     pub fn rtt(&self)->(Duration,Duration){
         (self.rtt, self.rtt_var)
     }
+    pub fn window(&self) -> usize {
+        self.congestion_window
+    }
 
 }
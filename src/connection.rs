@@ -1,17 +1,26 @@
 use std::{
     cmp::min,
+    collections::HashMap,
+    io::{Error, ErrorKind},
     net::SocketAddr,
     sync::{Arc, RwLock},
     time::{Duration, SystemTime},
 };
 
 use crate::{
-    core::{NeonStatus, SYN_INTERVAL},
+    config::NeonConfig,
+    core::{path_mtu::PathMtu, NeonStatus},
     packet::{
-        control::{handshake::ReqType, ControlPacket},
+        control::{
+            err::ERR_TIMEOUT,
+            handshake::{ReqType, CAP_PMTU, CAP_TIMING, WIRE_VERSION},
+            timing::Timing,
+            ControlMeta, ControlPacket, ControlPacketInfo, ControlType,
+        },
+        data::DataPacket,
         Packet,
     },
-    utils::{MessageNumber, SequenceNumber, SequenceRange},
+    utils::{self, MessageNumber, SequenceNumber, SequenceRange},
 };
 
 #[derive(Debug)]
@@ -24,11 +33,36 @@ pub struct NeonConnection {
     out_mss: u16,
     in_mss: u16,
     last_update: SystemTime,
-    first_update: SystemTime, //this is for relative time processing
+    first_update: SystemTime, //this is for relative time processing, resynced every timing interval
+    timing: Option<u32>,      //the timestamp on our outstanding timing request
+    offset: i32,              //microseconds the partner's clock reads ahead of ours
+    delay: Duration,          //round trip the offset was measured over
+    version: u16,             //wire version picked in the handshake
+    features: u32,            //capabilities both sides support, see CAP_*
+    narrowed: Option<Narrowed>, //what a version 1 partner's numbers are widened against
     closing: Arc<RwLock<bool>>,
     expiration_counter: usize,
+    path_mtu: PathMtu,
+    write_closed: bool,        //we won't send anything more
+    read_closed: bool,         //we don't want anything more
+    remote_write_closed: bool, //partner won't send anything more
+    claimed: bool,             //a stream has been handed out for this
+    config: NeonConfig,
 }
-const MIN_EXPIRATION: usize = 300000;
+//The last full number seen of each kind, anything from a version 1 partner is taken as the one nearest
+#[derive(Debug)]
+struct Narrowed {
+    in_seq: SequenceNumber,                 //the partner's data
+    out_seq: SequenceNumber,                //ours, coming back in acks and losses
+    in_msgs: HashMap<u16, MessageNumber>,  //the partner's, per channel
+    out_msgs: HashMap<u16, MessageNumber>, //ours, per channel
+}
+//A version 1 partner's seqs only widen right while everything in flight is well inside 15 bits
+pub const NARROW_WINDOW: u16 = 0x2000;
+pub const MIN_EXPIRATION: Duration = Duration::from_micros(300000);
+pub const MAX_EXPIRATIONS: usize = 16;
+//well inside the 71 minutes a microsecond timestamp takes to wrap
+pub const TIMING_INTERVAL: Duration = Duration::from_secs(600);
 
 //This is doing real control work
 impl NeonConnection {
@@ -38,14 +72,16 @@ impl NeonConnection {
         partner_id: u16,
         partner_in_addr: SocketAddr,
         partner_out_addr: SocketAddr,
-        out_mss: u16,
         in_mss: u16,
+        config: &NeonConfig,
     ) -> Self {
+        let out_mss = config.mss;
         let last_update = SystemTime::now();
         let first_update = SystemTime::now();
         let closing = Arc::new(RwLock::new(false));
         let expiration_counter =1;
-        
+        let path_mtu = PathMtu::new(out_mss, config.max_mss, config.pmtu_interval, partner_in_addr);
+
         Self {
             isn,
             status,
@@ -56,8 +92,20 @@ impl NeonConnection {
             in_mss,
             last_update,
             first_update,
+            timing: None,
+            offset: 0,
+            delay: Duration::ZERO,
+            version: 0,
+            features: 0,
+            narrowed: None,
             closing,
-            expiration_counter
+            expiration_counter,
+            path_mtu,
+            write_closed: false,
+            read_closed: false,
+            remote_write_closed: false,
+            claimed: false,
+            config: *config,
         }
     }
     pub fn status(&self) -> NeonStatus {
@@ -74,20 +122,21 @@ impl NeonConnection {
     }
     
     pub fn should_keep_alive(&mut self,rtt:Duration, rtt_var: Duration)->bool{
+        let min_expiration = self.config.min_expiration.as_micros() as usize;
         let mut exp_int = (self.expiration_counter
             * (rtt.as_micros() + 4 * rtt_var.as_micros()) as usize)
-            + SYN_INTERVAL.as_micros() as usize;
-        if exp_int < self.expiration_counter * MIN_EXPIRATION {
-            exp_int = self.expiration_counter * MIN_EXPIRATION
+            + self.config.ack_interval.as_micros() as usize;
+        if exp_int < self.expiration_counter * min_expiration {
+            exp_int = self.expiration_counter * min_expiration
         }
         let next_expiration_time = self.last_update + Duration::from_micros(exp_int as u64);
         self.expiration_counter+=1;
         match next_expiration_time.elapsed() {
             Ok(timeout) => {
                 //if it's dead close the socket
-                if self.expiration_counter > 16 && timeout > Duration::from_micros(500000) {
+                if self.expiration_counter > self.config.max_expirations && timeout > Duration::from_micros(500000) {
                     if let Ok(mut closing) = self.closing.write() { *closing=true }
-                    self.status = NeonStatus::Unhealthy(0x0001);
+                    self.status = NeonStatus::Unhealthy(ERR_TIMEOUT);
                     false
                 }else{
                     true
@@ -99,6 +148,71 @@ impl NeonConnection {
     pub fn sent_packet(&mut self){
         self.last_update = SystemTime::now();
     }
+    //Everything the connection times is relative to this
+    pub fn epoch(&self) -> SystemTime {
+        self.first_update
+    }
+    pub fn timestamp(&self) -> u32 {
+        utils::timestamp(self.first_update)
+    }
+    fn stamped(&self, mut packet: ControlPacket) -> Packet {
+        packet.timestamp = self.timestamp();
+        Packet::Control(packet)
+    }
+    //Restarts the clock, anything measured against the old epoch is forgotten
+    fn resync(&mut self) {
+        self.first_update = SystemTime::now();
+        self.timing = None;
+    }
+    pub fn timing_due(&self) -> bool {
+        if !self.supports(CAP_TIMING) {
+            return false;
+        }
+        match self.status {
+            NeonStatus::Established | NeonStatus::Queued | NeonStatus::Healthy => {}
+            _ => return false,
+        }
+        match self.first_update.elapsed() {
+            Ok(elapsed) => elapsed >= self.config.timing_interval,
+            Err(_) => true, //the wall clock went backwards
+        }
+    }
+    pub fn create_timing(&mut self) -> (SocketAddr, Packet) {
+        self.resync();
+        let packet = self.stamped(ControlPacket::timing(self.partner_id, Timing::request()));
+        self.timing = Some(packet.timestamp());
+        (self.partner_in_addr, packet)
+    }
+    //The partner resynced, follow it and answer with when its request got here
+    pub fn create_timing_reply(&mut self, origin: u32) -> (SocketAddr, Packet) {
+        self.resync();
+        let received = self.timestamp();
+        let packet = self.stamped(ControlPacket::timing(
+            self.partner_id,
+            Timing::response(origin, received),
+        ));
+        (self.partner_in_addr, packet)
+    }
+    /*
+        ntp's offset and delay from the four times, each pair is on one clock so only differences are taken
+        A reply to anything but our last request was measured against an epoch we've since left
+    */
+    pub fn on_timing_reply(&mut self, origin: u32, received: u32, sent: u32) {
+        if self.timing != Some(origin) {
+            return;
+        }
+        self.timing = None;
+        let arrived = self.timestamp();
+        let out = received.wrapping_sub(origin) as i32 as i64;
+        let back = sent.wrapping_sub(arrived) as i32 as i64;
+        self.offset = ((out + back) / 2) as i32;
+        let held = sent.wrapping_sub(received);
+        let delay = arrived.wrapping_sub(origin).saturating_sub(held);
+        self.delay = Duration::from_micros(delay.into());
+    }
+    pub fn clock_offset(&self) -> (i32, Duration) {
+        (self.offset, self.delay)
+    }
     
     pub fn create_ack(
         &mut self,
@@ -110,7 +224,7 @@ impl NeonConnection {
         window: usize,
         bandwidth: usize,
     ) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::ack(
+        let packet = self.stamped(ControlPacket::ack(
             self.partner_id,
             ack_no,
             seq_no,
@@ -123,40 +237,45 @@ impl NeonConnection {
         (self.partner_in_addr, packet)
     }
     pub fn create_ack_square(&mut self, ack_no: SequenceNumber) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::ack_square(self.partner_id, ack_no));
+        let packet = self.stamped(ControlPacket::ack_square(self.partner_id, ack_no));
         (self.partner_in_addr, packet)
     }
     pub fn create_loss(&mut self, ranges: Vec<SequenceRange>) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::loss(self.partner_id, ranges));
+        let packet = self.stamped(ControlPacket::loss(self.partner_id, ranges));
         (self.partner_in_addr, packet)
     }
     pub fn create_congestion(&mut self, factor: f64) -> (SocketAddr, Packet) {
         let encoding = factor.exp() as u16;
-        let packet = Packet::Control(ControlPacket::congestion(self.partner_id, encoding));
+        let packet = self.stamped(ControlPacket::congestion(self.partner_id, encoding));
         (self.partner_in_addr, packet)
     }
     pub fn create_keep_alive(&mut self) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::keep_alive(self.partner_id));
+        let packet = self.stamped(ControlPacket::keep_alive(self.partner_id));
         (self.partner_in_addr, packet)
     }
     pub fn create_shutdown(&mut self, code: u16) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::shutdown(self.partner_id, code));
+        let packet = self.stamped(ControlPacket::shutdown(self.partner_id, code));
         (self.partner_in_addr, packet)
     }
     pub fn create_error(&mut self, code: u16) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::error(self.partner_id, code));
+        let packet = self.stamped(ControlPacket::error(self.partner_id, code));
         (self.partner_in_addr, packet)
     }
     pub fn create_drop(
         &mut self,
+        channel: u16,
         msg_no: MessageNumber,
-        ranges: SequenceRange,
+        ranges: Vec<SequenceRange>,
     ) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::drop(self.partner_id, msg_no, ranges));
+        let packet = self.stamped(ControlPacket::drop(self.partner_id, channel, msg_no, ranges));
+        (self.partner_in_addr, packet)
+    }
+    pub fn create_shed(&mut self, channel: u16, msg_no: MessageNumber) -> (SocketAddr, Packet) {
+        let packet = self.stamped(ControlPacket::shed(self.partner_id, channel, msg_no));
         (self.partner_in_addr, packet)
     }
     pub fn create_discovery(&mut self,req_type:ReqType) -> (SocketAddr, Packet) {
-        let packet = Packet::Control(ControlPacket::discovery(
+        let packet = self.stamped(ControlPacket::discovery(
             self.partner_id,
             self.in_mss,
             self.out_mss,
@@ -164,15 +283,96 @@ impl NeonConnection {
         ));
         (self.partner_in_addr, packet)
     }
+    //Only probes once the connection is up, None if the search is settled or a probe is out
+    pub fn create_probe(&mut self, rtt: Duration) -> Option<(SocketAddr, Packet)> {
+        match self.status {
+            NeonStatus::Established | NeonStatus::Queued | NeonStatus::Healthy => {}
+            _ => return None,
+        }
+        if !self.supports(CAP_PMTU) {
+            return None;
+        }
+        let size = self.path_mtu.next_probe(rtt)?;
+        let packet = self.stamped(ControlPacket::probe(
+            self.partner_id,
+            size,
+            size,
+            ReqType::Connection,
+        ));
+        Some((self.partner_in_addr, packet))
+    }
+    pub fn create_probe_ack(&mut self, received: u16) -> (SocketAddr, Packet) {
+        let packet = self.stamped(ControlPacket::probe(
+            self.partner_id,
+            0,
+            received,
+            ReqType::Response,
+        ));
+        (self.partner_in_addr, packet)
+    }
+    pub fn on_probe_ack(&mut self, received: u16) {
+        if let Some(mss) = self.path_mtu.on_probe_ack(received) {
+            self.out_mss = mss;
+        }
+    }
+    //Retransmission timed out, too many in a row means big packets are vanishing
+    pub fn on_timeout(&mut self) {
+        if let Some(mss) = self.path_mtu.on_timeout() {
+            self.out_mss = mss;
+        }
+    }
+    pub fn on_progress(&mut self) {
+        self.path_mtu.on_progress()
+    }
 
     pub fn establish(&mut self, in_mss: u16, out_mss: u16) {
         self.in_mss = min(self.in_mss, in_mss);
-        self.out_mss = min(self.out_mss, out_mss);
-        self.status = NeonStatus::Established;
+        //a late duplicate discover shouldn't undo the path mtu search
+        if matches!(self.status, NeonStatus::Connecting | NeonStatus::Negotiating) {
+            self.out_mss = min(self.out_mss, out_mss);
+            self.path_mtu.reset(self.out_mss);
+            self.status = NeonStatus::Established;
+        }
     }
     pub fn error(&mut self, code: u16) {
         self.status = NeonStatus::Unhealthy(code)
     }
+    pub fn writable(&self) -> Result<(), Error> {
+        if self.write_closed {
+            return Err(Error::new(ErrorKind::BrokenPipe, "Stream shut down for writing"));
+        }
+        match self.status {
+            NeonStatus::Closed => Err(Error::new(ErrorKind::BrokenPipe, "Partner closed the stream")),
+            NeonStatus::Unhealthy(code) => Err(Error::new(
+                ErrorKind::ConnectionAborted,
+                format!("Connection failed with code {:#06x}", code),
+            )),
+            _ => Ok(()),
+        }
+    }
+    //once everything buffered is read the stream is at eof
+    pub fn at_eof(&self) -> bool {
+        self.read_closed || self.remote_write_closed || self.status == NeonStatus::Closed
+    }
+    pub fn claim(&mut self) {
+        self.claimed = true;
+    }
+    pub fn claimed(&self) -> bool {
+        self.claimed
+    }
+    pub fn shutdown_write(&mut self) {
+        self.write_closed = true;
+    }
+    pub fn shutdown_read(&mut self) {
+        self.read_closed = true;
+    }
+    pub fn remote_shutdown_write(&mut self) {
+        self.remote_write_closed = true;
+    }
+    pub fn remote_close(&mut self) {
+        self.remote_write_closed = true;
+        self.status = NeonStatus::Closed;
+    }
 
     pub fn validate(&mut self, addr: SocketAddr) -> bool {
         if self.partner_out_addr == addr {
@@ -187,12 +387,89 @@ impl NeonConnection {
         (self.in_mss, self.out_mss)
     }
 
-    pub fn negotiate(&mut self, stamp: SystemTime, partner_id: u16, port: u16) {
-        self.first_update = stamp;
+    pub fn negotiate(&mut self, partner_id: u16, port: u16) {
         self.partner_id = partner_id;
         self.status = NeonStatus::Negotiating;
         self.partner_out_addr = SocketAddr::new(self.partner_out_addr.ip(), port);
     }
+    pub fn agree(&mut self, version: u16, features: u32, partner_isn: SequenceNumber) {
+        self.version = version;
+        self.features = features;
+        self.narrowed = match version < WIRE_VERSION {
+            true => Some(Narrowed {
+                in_seq: partner_isn,
+                out_seq: self.isn,
+                in_msgs: HashMap::new(),
+                out_msgs: HashMap::new(),
+            }),
+            false => None,
+        };
+    }
+    pub fn narrow(&self) -> bool {
+        self.narrowed.is_some()
+    }
+    //How many packets may be in flight, the partner's window only counts on a wide connection
+    pub fn max_window(&self) -> u16 {
+        match self.narrowed {
+            Some(_) => NARROW_WINDOW,
+            None => u16::MAX,
+        }
+    }
+    //Our messages come back narrowed in sheds, they're widened against the last one written
+    pub fn sent_message(&mut self, channel: u16, msg_no: MessageNumber) {
+        if let Some(narrowed) = &mut self.narrowed {
+            narrowed.out_msgs.insert(channel, msg_no);
+        }
+    }
+    pub fn widen_data(&mut self, packet: &mut DataPacket) {
+        let narrowed = match &mut self.narrowed {
+            Some(narrowed) => narrowed,
+            None => return,
+        };
+        packet.seq_no = packet.seq_no.widen(narrowed.in_seq);
+        narrowed.in_seq = packet.seq_no;
+        let last_msg = narrowed.in_msgs.entry(packet.channel).or_insert(MessageNumber::FIRST);
+        packet.msg_no = packet.msg_no.widen(*last_msg);
+        *last_msg = packet.msg_no;
+    }
+    pub fn widen_control(&mut self, packet: &mut ControlPacket) {
+        let narrowed = match &mut self.narrowed {
+            Some(narrowed) => narrowed,
+            None => return,
+        };
+        match (&packet.control_type, &mut packet.meta, &mut packet.info) {
+            (ControlType::Ack, ControlMeta::Seq(ack_no), ControlPacketInfo::Ack(info)) => {
+                *ack_no = ack_no.widen(narrowed.out_seq);
+                info.seq_no = info.seq_no.widen(narrowed.out_seq);
+                narrowed.out_seq = info.seq_no;
+            }
+            (ControlType::Loss, _, ControlPacketInfo::Loss(info)) => info
+                .loss_range
+                .iter_mut()
+                .for_each(|range| *range = range.widen(narrowed.out_seq)),
+            (ControlType::AckSquare, ControlMeta::Seq(ack_no), _) => {
+                *ack_no = ack_no.widen(narrowed.in_seq)
+            }
+            (ControlType::Drop, ControlMeta::Message(msg_no), ControlPacketInfo::Drop(info)) => {
+                let last_msg = narrowed.in_msgs.get(&info.channel).copied();
+                *msg_no = msg_no.widen(last_msg.unwrap_or(MessageNumber::FIRST));
+                info.ranges
+                    .iter_mut()
+                    .for_each(|range| *range = range.widen(narrowed.in_seq));
+            }
+            (ControlType::Shed, ControlMeta::Message(msg_no), ControlPacketInfo::Shed(info)) => {
+                let last_msg = narrowed.out_msgs.get(&info.channel).copied();
+                *msg_no = msg_no.widen(last_msg.unwrap_or(MessageNumber::FIRST));
+            }
+            _ => {}
+        }
+    }
+    pub fn negotiated(&self) -> (u16, u32) {
+        (self.version, self.features)
+    }
+    pub fn supports(&self, feature: u32) -> bool {
+        self.features & feature == feature
+    }
     pub fn partner_id(&self) -> u16 {
         self.partner_id
     }
@@ -1,35 +1,48 @@
 use std::{
     collections::{HashMap, VecDeque},
     io::{Error, ErrorKind},
-    net::SocketAddr,
-    sync::{Arc, RwLock},
-    thread,
+    net::{Shutdown, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
     time::Duration,
 };
 
-use channel::{NeonChannel, MAX_PACKET_SIZE};
+use channel::NeonChannel;
 use recv::recv_queue::RecvQueue;
-use send::send_queue::{NeonPoll, SendQueue};
+use send::{
+    send_buffer::{MessageId, MessageOptions, Receipt},
+    send_list::WritableHook,
+    send_queue::{NeonPoll, SendQueue},
+};
+use signal::NeonSignal;
 
 use crate::{
+    config::NeonConfig,
     connection::NeonConnection,
     packet::{
         control::{
-            handshake::{Handshake, ReqType, FLOW_CONTROL},
+            err::{ERR_BACKLOG, ERR_VERSION},
+            handshake::{Handshake, ReqType, CAP_DATAGRAM},
+            shutdown::{SHUTDOWN_CLOSE, SHUTDOWN_WRITE},
             ControlMeta, ControlPacket, ControlPacketInfo, ControlType,
         },
         data::DataPacket,
         Packet,
     },
-    stream::NeonStream,
     utils::{MessageNumber, SequenceNumber, SequenceRange},
 };
 
 pub mod channel;
 pub mod loss_list;
+pub mod path_mtu;
 pub mod recv;
 pub mod send;
+pub mod signal;
 pub const SYN_INTERVAL: Duration = Duration::from_millis(10);
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(1000);
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum NeonStatus {
@@ -39,6 +52,64 @@ pub enum NeonStatus {
     Queued,         //Stream is built but no one is working it
     Healthy,        //Connection is ready
     Unhealthy(u16), //Something went wrong, have an error code
+    Closed,         //Partner closed, what's buffered can still be read
+}
+
+/*
+    Owns the threads working a core, the listener and every stream on the core share one
+    When the last of them lets go the threads are stopped and joined
+    Which drops the last reference to the core and with it the udp socket
+*/
+pub struct NeonWorkers {
+    running: Arc<AtomicBool>,
+    stop: Arc<NeonSignal>,
+    channel: Arc<RwLock<NeonChannel>>,
+    poll: Option<Arc<NeonPoll>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl NeonWorkers {
+    pub fn running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+    //Work that shouldn't hold up the caller, stop joins it with the rest
+    pub fn spawn(&self, work: impl FnOnce() + Send + 'static) {
+        let handle = thread::spawn(work);
+        if let Ok(mut handles) = self.handles.lock() {
+            handles.push(handle);
+        }
+    }
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Release);
+        //keep alive thread is sleeping on the stop signal
+        self.stop.notify();
+        //send thread is waiting for sockets to work
+        if let Some(poll) = &self.poll {
+            let _waiting = poll.sockets.lock();
+            poll.cond.notify_all();
+        }
+        //recv thread is blocked in recv_from
+        if let Ok(channel) = self.channel.read() {
+            let _ = channel.wake();
+        }
+        let handles = match self.handles.lock() {
+            Ok(mut handles) => handles.drain(..).collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        let current = thread::current().id();
+        handles
+            .into_iter()
+            .filter(|handle| handle.thread().id() != current)
+            .for_each(|handle| {
+                let _ = handle.join();
+            });
+    }
+}
+
+impl Drop for NeonWorkers {
+    fn drop(&mut self) {
+        self.stop()
+    }
 }
 
 /*
@@ -54,14 +125,17 @@ pub struct NeonCore {
     connections: HashMap<u16, NeonConnection>,
     send: Arc<RwLock<SendQueue>>,
     recv: Arc<RwLock<RecvQueue>>,
-    queued_streams: VecDeque<NeonStream>,
+    queued_streams: VecDeque<u16>, //socket ids, a stream isn't built till it's accepted
+    accept: Arc<NeonSignal>,       //bumped whenever a stream is queued
+    config: NeonConfig,
 }
 
 //this is mostly doing generic packet dispatch / recovery off the socket
 impl NeonCore {
-    pub fn new(channel: Arc<RwLock<NeonChannel>>) -> Self {
+    pub fn new(channel: Arc<RwLock<NeonChannel>>, config: NeonConfig) -> Self {
         let connections = HashMap::new();
         let queued_streams = VecDeque::new();
+        let accept = Arc::new(NeonSignal::new());
         let send = Arc::new(RwLock::new(SendQueue::new()));
         let recv = Arc::new(RwLock::new(RecvQueue::new()));
 
@@ -69,20 +143,36 @@ impl NeonCore {
             channel,
             connections,
             queued_streams,
+            accept,
             send,
             recv,
+            config,
         }
     }
 
-    pub fn work(core: Arc<RwLock<NeonCore>>) {
+    pub fn work(core: Arc<RwLock<NeonCore>>) -> Result<NeonWorkers, Error> {
+        let (channel, poll) = match core.read() {
+            Ok(tc) => (tc.channel.clone(), tc.poll_send()),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let stop = Arc::new(NeonSignal::new());
+        //the state thread is on the long keep alive sleep, new data owing an ack has to wake it
+        let napping = Arc::new(AtomicBool::new(false));
+        let mut handles = vec![];
+
         let thread_core = core.clone();
+        let thread_running = running.clone();
+        let thread_stop = stop.clone();
+        let thread_napping = napping.clone();
         //thread for working recv packets
-        thread::spawn(move || {
-            let channel = match thread_core.read() {
-                Ok(tc) => tc.channel.clone(),
+        handles.push(thread::spawn(move || {
+            //big enough for the largest probe, not just the starting mss
+            let (channel, mss) = match thread_core.read() {
+                Ok(tc) => (tc.channel.clone(), tc.config.max_payload()),
                 Err(_) => return,
             };
-            loop {
+            while thread_running.load(Ordering::Acquire) {
                 //is there a way to poll on channel
                 match channel.read() {
                     Ok(channel) => {
@@ -90,53 +180,77 @@ impl NeonCore {
                             Ok(addr) => addr,
                             Err(_) => return,
                         };
-                        if let Ok(packet) = channel.inbound.recv_from(&mut addr) {
+                        if let Ok(packet) = channel.inbound.recv_from(&mut addr, mss) {
                             drop(channel);
-                            match thread_core.write() {
-                                Ok(mut tc) => tc.process_packet(addr, packet),
+                            //an ack can make room, the hooks may write so the core has to be free
+                            let (hooks, owes_ack) = match thread_core.write() {
+                                Ok(mut tc) => {
+                                    tc.process_packet(addr, packet);
+                                    (tc.writable(), tc.owes_ack())
+                                }
                                 Err(_) => return,
                             };
+                            hooks.iter().for_each(|hook| hook());
+                            //the tail of a burst isn't acked on arrival, it can't wait for the keep alive either
+                            if owes_ack && thread_napping.swap(false, Ordering::AcqRel) {
+                                thread_stop.notify();
+                            }
                         }
                     }
                     Err(_) => return,
                 }
             }
-        });
+        }));
 
         let thread_core = core.clone();
+        let thread_running = running.clone();
+        let thread_stop = stop.clone();
+        let thread_napping = napping.clone();
         //thread for doing keep alive packets
-        thread::spawn(move || {
-            loop {
+        handles.push(thread::spawn(move || {
+            let (interval, ack_interval) = match thread_core.read() {
+                Ok(tc) => (tc.config.keep_alive_interval, tc.config.ack_interval),
+                Err(_) => return,
+            };
+            while thread_running.load(Ordering::Acquire) {
+                let generation = thread_stop.generation();
                 //TODO: should sleep to next keep alive time but get interrupted if ???
-                match thread_core.write() {
+                //expired messages make room too
+                let (hooks, owes_ack) = match thread_core.write() {
                     Ok(mut tc) => {
                         tc.manage_state();
+                        (tc.writable(), tc.owes_ack())
                     }
                     Err(_) => return,
                 };
-                thread::sleep(Duration::from_millis(1000));
+                hooks.iter().for_each(|hook| hook());
+                //a sender waiting on its window can't wait for the keep alive
+                let interval = match owes_ack {
+                    true => ack_interval,
+                    false => interval,
+                };
+                thread_napping.store(!owes_ack, Ordering::Release);
+                //sleeps the interval unless told to stop or woken for an ack
+                thread_stop.wait(generation, Some(interval));
             }
-        });
+        }));
 
         //thread for working send queue
         let thread_core = core.clone();
-        thread::spawn(move || {
-            let poll = match thread_core.read() {
-                Ok(tc) => tc.poll_send(),
-                Err(_) => None,
-            };
-
-            loop {
+        let thread_running = running.clone();
+        let thread_poll = poll.clone();
+        handles.push(thread::spawn(move || {
+            while thread_running.load(Ordering::Acquire) {
                 if let Ok(tc) = thread_core.read() {
                     tc.manage_queue()
                 }
                 //lot of words to say 'only pop when something gets added to the buffer'
-                if let Some(arc) = poll.clone() {
+                if let Some(arc) = thread_poll.clone() {
                     let mut waiting_sockets = match arc.sockets.lock() {
                         Ok(ws) => ws,
                         Err(_) => return,
                     };
-                    while waiting_sockets.is_empty() {
+                    while waiting_sockets.is_empty() && thread_running.load(Ordering::Acquire) {
                         waiting_sockets = match arc.cond.wait(waiting_sockets) {
                             Ok(ws) => ws,
                             Err(_) => return,
@@ -144,7 +258,14 @@ impl NeonCore {
                     }
                 }
             }
-        });
+        }));
+        Ok(NeonWorkers {
+            running,
+            stop,
+            channel,
+            poll,
+            handles: Mutex::new(handles),
+        })
     }
     pub fn poll_send(&self) -> Option<Arc<NeonPoll>> {
         match self.send.read() {
@@ -167,22 +288,51 @@ impl NeonCore {
                 Ok(channel) => channel,
                 Err(_) => return,
             };
-            if let Ok(mut send) = self.send.write() {
-                let _ = send.send_data(&channel, addr, socket_id);
+            let sent = match self.send.write() {
+                Ok(mut send) => {
+                    let _ = send.send_data(&channel, addr, socket_id);
+                    send.last_seq(socket_id)
+                }
+                Err(_) => None,
             };
+            //the congestion control lives with the receive side, the send lock is let go first
+            if let (Some(seq_no), Ok(recv)) = (sent, self.recv.read()) {
+                recv.on_send(socket_id, seq_no);
+            }
         }
     }
-    pub fn manage_streams(&mut self, core: Arc<RwLock<NeonCore>>) {
+    pub fn manage_streams(&mut self) {
         let streams = self
             .connections
             .iter_mut()
-            .filter(|(_, connection)| connection.status() == NeonStatus::Established)
+            .filter(|(_, connection)| {
+                connection.status() == NeonStatus::Established && !connection.claimed()
+            })
             .map(|(socket_id, connection)| {
                 connection.set_status(NeonStatus::Queued);
-                NeonStream::from_core(*socket_id, core.clone())
+                *socket_id
             })
             .collect::<Vec<_>>();
-        self.queued_streams.extend(streams)
+        if !streams.is_empty() {
+            self.queued_streams.extend(streams);
+            self.accept.notify();
+        }
+    }
+    pub fn accept_signal(&self) -> Arc<NeonSignal> {
+        self.accept.clone()
+    }
+    //Connections that got a handshake but no one has accepted
+    fn backlog(&self) -> usize {
+        self.connections
+            .values()
+            .filter(|connection| {
+                !connection.claimed()
+                    && !matches!(
+                        connection.status(),
+                        NeonStatus::Closed | NeonStatus::Unhealthy(_)
+                    )
+            })
+            .count()
     }
     pub fn manage_state(&mut self) {
         //heal discovery portion
@@ -198,6 +348,21 @@ impl NeonCore {
         sockets
             .into_iter()
             .for_each(|socket_id| self.send_discover(socket_id, ReqType::Connection));
+        //timing portion
+        let sockets = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.timing_due())
+            .map(|(socket_id, _)| *socket_id)
+            .collect::<Vec<_>>();
+        sockets
+            .into_iter()
+            .for_each(|socket_id| self.send_timing(socket_id, None));
+        //path mtu portion
+        let sockets = self.connections.keys().copied().collect::<Vec<_>>();
+        sockets
+            .into_iter()
+            .for_each(|socket_id| self.send_probe(socket_id));
         //ack portion
         let sockets = self.connections.keys().copied().collect::<Vec<_>>();
         sockets.into_iter().for_each(|socket_id| {
@@ -209,10 +374,38 @@ impl NeonCore {
                 self.send_ack(socket_id, ack_no, seq_no)
             }
         });
+        //drop portion
+        let sockets = self.connections.keys().copied().collect::<Vec<_>>();
+        sockets.into_iter().for_each(|socket_id| {
+            let (rtt, rtt_var) = match self.recv.read() {
+                Ok(recv) => match recv.time_data(socket_id) {
+                    Some(data) => (data.0, data.1),
+                    None => (Duration::ZERO, Duration::ZERO),
+                },
+                Err(_) => (Duration::ZERO, Duration::ZERO),
+            };
+            //give the last drop a round trip to be acked past before repeating it
+            let resend = (rtt + rtt_var * 4).max(self.config.ack_interval);
+            let drops = match self.send.read() {
+                Ok(send) => send.drops(socket_id, resend),
+                Err(_) => vec![],
+            };
+            drops
+                .into_iter()
+                .for_each(|(channel, msg_no, ranges)| self.send_drop(socket_id, channel, msg_no, ranges));
+            //messages we acked then threw out, the sender's receipts still say acked
+            let shed = match self.recv.read() {
+                Ok(recv) => recv.take_shed(socket_id),
+                Err(_) => vec![],
+            };
+            shed.into_iter()
+                .for_each(|(channel, msg_no)| self.send_shed(socket_id, channel, msg_no));
+        });
         //keep alive portion
         let sockets = self
             .connections
             .iter_mut()
+            .filter(|(_, connection)| connection.status() != NeonStatus::Closed)
             .filter_map(|(socket_id, connection)| {
                 let (rtt, rtt_var) = match self.recv.read() {
                     Ok(recv) => match recv.time_data(*socket_id) {
@@ -228,6 +421,14 @@ impl NeonCore {
                 }
             })
             .collect::<Vec<_>>();
+        //let blocked readers see dead connections
+        if self
+            .connections
+            .values()
+            .any(|connection| matches!(connection.status(), NeonStatus::Unhealthy(_)))
+        {
+            self.notify();
+        }
         sockets
             .into_iter()
             .for_each(|socket_id| self.send_keep_alive(socket_id));
@@ -240,14 +441,22 @@ impl NeonCore {
             Packet::Data(packet) => self.process_data(addr, packet),
         }
     }
-    pub fn process_control(&mut self, addr: SocketAddr, packet: ControlPacket) {
+    pub fn process_control(&mut self, addr: SocketAddr, mut packet: ControlPacket) {
         let socket_id = packet.dst_socket_id;
         //protect existing connections
         let connection_status = match self.connections.get_mut(&socket_id) {
             Some(connection) => {
-                if !connection.validate(addr) && packet.control_type != ControlType::Handshake {
+                //a refusal comes back from the address we're connecting to, not the partner's out socket
+                let refusal = packet.control_type == ControlType::Err
+                    && connection.status() == NeonStatus::Connecting
+                    && addr == connection.partner_in_addr();
+                if !connection.validate(addr)
+                    && packet.control_type != ControlType::Handshake
+                    && !refusal
+                {
                     return;
                 }
+                connection.widen_control(&mut packet);
                 connection.status()
             }
             None => {
@@ -284,11 +493,14 @@ impl NeonCore {
             ControlType::Drop => self.process_drop(socket_id, packet),
             ControlType::Err => self.process_err(socket_id, packet),
             ControlType::Discover => self.process_discover(socket_id, packet),
+            ControlType::Datagram => self.process_datagram(socket_id, packet),
+            ControlType::Timing => self.process_timing(socket_id, packet),
+            ControlType::Shed => self.process_shed(socket_id, packet),
             ControlType::Custom => {} //unsupported
         }
         self.manage_state();
     }
-    pub fn process_data(&mut self, addr: SocketAddr, packet: DataPacket) {
+    pub fn process_data(&mut self, addr: SocketAddr, mut packet: DataPacket) {
         let socket_id = packet.dst_socket_id;
         //protect existing connections
         let (_, out_mss) = match self.connections.get_mut(&socket_id) {
@@ -296,6 +508,7 @@ impl NeonCore {
                 if !connection.validate(addr) {
                     return;
                 }
+                connection.widen_data(&mut packet);
                 connection.mss()
             }
             None => return,
@@ -326,7 +539,6 @@ impl NeonCore {
 
     pub fn new_connection(&mut self, in_addr: SocketAddr, packet: ControlPacket) {
         let mut socket_id = packet.dst_socket_id;
-        let stamp = packet.stamp;
 
         //should send a handshake packet back of type response, but only send it once (it will beacon if it doesn't get it)
         let info = match packet.info {
@@ -335,16 +547,40 @@ impl NeonCore {
         };
         let partner_in_addr = SocketAddr::new(in_addr.ip(), info.port);
         let mut isn = SequenceNumber::new(0);
-        let valid = match info.req_type {
-            ReqType::Connection => {
+        let agreed = info.agree(self.config.wire_version, self.config.capabilities);
+        let valid = match (info.req_type, agreed) {
+            //the seq and msg widths come with the version, there's no talking across them
+            (ReqType::Connection, None) => {
+                if let Ok(channel) = self.channel.read() {
+                    let refusal = Packet::Control(ControlPacket::error(info.src_socket_id, ERR_VERSION));
+                    let _ = channel.reply_to(partner_in_addr, refusal);
+                }
+                false
+            }
+            (ReqType::Connection, _) if self.backlog() >= self.config.backlog => {
+                //too many waiting on accept, tell them rather than let them time out
+                if let Ok(channel) = self.channel.read() {
+                    let refusal = Packet::Control(ControlPacket::error(info.src_socket_id, ERR_BACKLOG));
+                    let _ = channel.reply_to(partner_in_addr, refusal);
+                }
+                false
+            }
+            (ReqType::Connection, Some(agreed)) => {
                 //If it's a new request send back a response (use advertized socket, create new socket)
                 match self.channel.read() {
                     Ok(channel) => {
                         socket_id = channel.inc_socket_id(); //A new request didn't know my socket
 
                         let local_out_addr = channel.outbound.addr;
-                        let (out_isn, packet) =
-                            Handshake::reply(socket_id, info.src_socket_id, info, local_out_addr);
+                        let (out_isn, packet) = Handshake::reply(
+                            socket_id,
+                            info.src_socket_id,
+                            info,
+                            local_out_addr,
+                            self.config.mss,
+                            self.config.flow_window,
+                            agreed,
+                        );
                         isn = out_isn;
                         let response_packet = Packet::Control(packet);
 
@@ -353,33 +589,53 @@ impl NeonCore {
                     Err(_) => false,
                 }
             }
-            ReqType::Response => {
-                Handshake::validate(MAX_PACKET_SIZE, FLOW_CONTROL, socket_id, info)
-            }
+            (ReqType::Response, None) => false,
+            (ReqType::Response, Some(_)) => Handshake::validate(
+                self.config.mss,
+                self.config.flow_window,
+                socket_id,
+                info,
+            ),
         };
-        if valid {
+        if let (true, Some((version, features))) = (valid, agreed) {
             match self.connections.get_mut(&socket_id) {
                 Some(connection) => {
-                    connection.negotiate(stamp, info.src_socket_id, info.port);
+                    connection.negotiate(info.src_socket_id, info.port);
+                    connection.agree(version, features, info.isn);
+                    if let Ok(channel) = self.channel.read() {
+                        channel.narrow(connection.partner_in_addr(), connection.narrow());
+                    }
                     //If there was already a connection update it
                 }
                 None => {
                     //If this is a first time set up a new connection
-                    let connection = NeonConnection::new(
+                    let mut connection = NeonConnection::new(
                         isn,
                         NeonStatus::Negotiating,
                         info.src_socket_id,
                         partner_in_addr,
                         in_addr,
-                        MAX_PACKET_SIZE,
                         info.mss,
+                        &self.config,
                     );
+                    connection.agree(version, features, info.isn);
+                    if let Ok(channel) = self.channel.read() {
+                        channel.narrow(partner_in_addr, connection.narrow());
+                    }
+                    let epoch = connection.epoch();
+                    let flow_window = info.flow_control.min(connection.max_window());
                     self.connections.insert(socket_id, connection);
                     if let Ok(send) = self.send.write() {
-                        send.register_connection(socket_id, isn)
+                        send.register_connection(
+                            socket_id,
+                            isn,
+                            &self.config.agreed(features),
+                            flow_window,
+                            epoch,
+                        )
                     }
                     if let Ok(recv) = self.recv.write() {
-                        recv.register_connection(socket_id, info.isn)
+                        recv.register_connection(socket_id, info.isn, &self.config)
                     }
                 }
             }
@@ -394,7 +650,7 @@ impl NeonCore {
         other_addr: SocketAddr,
         socket_id: u16,
     ) -> Result<(), Error> {
-        let mss = MAX_PACKET_SIZE;
+        let mss = self.config.mss;
         let local_in_addr = match self.channel.read() {
             Ok(channel) => channel.inbound.addr,
             Err(_) => return Err(Error::new(ErrorKind::NotConnected, "Channel collapsed")),
@@ -403,8 +659,10 @@ impl NeonCore {
             u16::MAX, //We don't know the other socket
             req_type,
             mss,
+            self.config.flow_window,
             socket_id,
             local_in_addr,
+            (self.config.wire_version, self.config.capabilities),
         ));
         let isn = match &packet {
             Packet::Control(ctrl) => match ctrl.info {
@@ -419,6 +677,12 @@ impl NeonCore {
             //If this socket is already started
             Some(connection) => {
                 //If it's connecting send another packet incase we dropped
+                if let NeonStatus::Unhealthy(code) = connection.status() {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionRefused,
+                        format!("Refused with code {:#06x}", code),
+                    ));
+                }
                 if connection.status() == NeonStatus::Connecting {
                     match self.channel.read() {
                         Ok(channel) => match channel.send_to(other_addr, packet.clone()) {
@@ -433,15 +697,17 @@ impl NeonCore {
             }
             None => {
                 //First time connection
-                let connection = NeonConnection::new(
+                let mut connection = NeonConnection::new(
                     isn,
                     NeonStatus::Connecting,
                     0,
                     other_addr,
                     other_addr,
-                    MAX_PACKET_SIZE,
-                    MAX_PACKET_SIZE,
+                    mss,
+                    &self.config,
                 );
+                //the stream asking for this handshake owns it
+                connection.claim();
                 self.connections.insert(socket_id, connection);
                 match self.channel.read() {
                     Ok(channel) => match channel.send_to(other_addr, packet.clone()) {
@@ -454,21 +720,273 @@ impl NeonCore {
         }
     }
 
-    pub fn read_data(&mut self, socket_id: u16) -> Option<Vec<u8>> {
+    //Ok(None) means nothing is ready yet, an error means nothing ever will be
+    pub fn read_data(&self, socket_id: u16, channel: u16) -> Result<Option<Vec<u8>>, Error> {
+        let data = match self.recv.read() {
+            Ok(recv) => recv.read_data(socket_id, channel),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        if data.is_some() {
+            return Ok(data);
+        }
+        self.nothing_ready(socket_id)
+    }
+    //Like read_data but a message dropped or shed on the way is an error instead of being skipped
+    pub fn read_intact(&self, socket_id: u16, channel: u16) -> Result<Option<Vec<u8>>, Error> {
+        let data = match self.recv.read() {
+            Ok(recv) => recv.read_intact(socket_id, channel),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        match data {
+            Ok(Some(data)) => Ok(Some(data)),
+            Ok(None) => self.nothing_ready(socket_id),
+            Err(_) => Err(Error::new(ErrorKind::InvalidData, "A message was dropped before it arrived")),
+        }
+    }
+    //What a read gets when nothing is buffered
+    fn nothing_ready(&self, socket_id: u16) -> Result<Option<Vec<u8>>, Error> {
+        match self.connections.get(&socket_id) {
+            Some(connection) if connection.at_eof() => {
+                Err(Error::new(ErrorKind::UnexpectedEof, "End of stream"))
+            }
+            Some(connection) => match connection.status() {
+                NeonStatus::Unhealthy(code) => Err(Error::new(
+                    ErrorKind::ConnectionAborted,
+                    format!("Connection failed with code {:#06x}", code),
+                )),
+                _ => Ok(None),
+            },
+            None => Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
+        }
+    }
+    pub fn read_datagram(&self, socket_id: u16, channel: u16) -> Result<Option<Vec<u8>>, Error> {
+        let data = match self.recv.read() {
+            Ok(recv) => recv.read_datagram(socket_id, channel),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        if data.is_some() {
+            return Ok(data);
+        }
+        self.nothing_ready(socket_id)
+    }
+    //Channel 0 is the stream, any other number is fine to open from either side
+    pub fn open_channel(&self, socket_id: u16, channel: u16) -> Result<(), Error> {
+        if !self.connections.contains_key(&socket_id) {
+            return Err(Error::new(ErrorKind::NotConnected, "Connection closed"));
+        }
+        match self.recv.read() {
+            Ok(recv) if recv.open_channel(socket_id, channel) => Ok(()),
+            Ok(_) => Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Ok(None) until the partner sends on a channel we haven't opened
+    pub fn next_channel(&self, socket_id: u16) -> Result<Option<u16>, Error> {
+        let channel = match self.recv.read() {
+            Ok(recv) => recv.next_channel(socket_id),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        match channel {
+            Some(channel) => Ok(Some(channel)),
+            None => match self.nothing_ready(socket_id) {
+                //no new channels after eof
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                    Err(Error::new(ErrorKind::NotConnected, "Connection closed"))
+                }
+                Err(err) => Err(err),
+                Ok(_) => Ok(None),
+            },
+        }
+    }
+    pub fn recv_signal(&self) -> Option<Arc<NeonSignal>> {
         match self.recv.read() {
-            Ok(recv) => recv.read_data(socket_id),
+            Ok(recv) => Some(recv.signal()),
+            Err(_) => None,
+        }
+    }
+    pub fn send_signal(&self) -> Option<Arc<NeonSignal>> {
+        match self.send.read() {
+            Ok(send) => Some(send.signal()),
             Err(_) => None,
         }
     }
-    pub fn next_stream(&mut self, core: Arc<RwLock<NeonCore>>) -> Option<NeonStream> {
+    //wake everyone blocked on this core so they can look at connection state again
+    fn notify(&self) {
+        if let Ok(recv) = self.recv.read() {
+            recv.notify()
+        }
+        if let Ok(send) = self.send.read() {
+            send.notify()
+        }
+    }
+    pub fn config(&self) -> &NeonConfig {
+        &self.config
+    }
+    //None once there is nothing left worth waiting on
+    pub fn unacked(&self, socket_id: u16) -> Option<usize> {
+        match self.connections.get(&socket_id) {
+            Some(connection) => match connection.status() {
+                NeonStatus::Closed | NeonStatus::Unhealthy(_) => None,
+                _ => match self.send.read() {
+                    Ok(send) => Some(send.size(socket_id)),
+                    Err(_) => None,
+                },
+            },
+            None => None,
+        }
+    }
+    //The payload that fits in one packet to the partner
+    pub fn mss(&self, socket_id: u16) -> Option<u16> {
+        self.connections.get(&socket_id).map(|connection| connection.mss().1)
+    }
+    pub fn negotiated(&self, socket_id: u16) -> Option<(u16, u32)> {
+        self.connections.get(&socket_id).map(|connection| connection.negotiated())
+    }
+    pub fn clock_offset(&self, socket_id: u16) -> Option<(i32, Duration)> {
+        self.connections.get(&socket_id).map(|connection| connection.clock_offset())
+    }
+    pub fn set_send_limits(&self, socket_id: u16, packets: usize, bytes: usize) -> Result<(), Error> {
+        match self.send.read() {
+            Ok(send) => send.limit(socket_id, packets, bytes),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    pub fn on_writable(&self, socket_id: u16, hook: WritableHook) -> Result<(), Error> {
+        match self.send.read() {
+            Ok(send) => send.on_writable(socket_id, hook),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    fn owes_ack(&self) -> bool {
+        match self.recv.read() {
+            Ok(recv) => recv.owes_ack(),
+            Err(_) => false,
+        }
+    }
+    pub fn writable(&self) -> Vec<WritableHook> {
+        match self.send.read() {
+            Ok(send) => send.writable(),
+            Err(_) => vec![],
+        }
+    }
+    //None while the message is in flight
+    pub fn receipt(&self, socket_id: u16, id: MessageId) -> Result<Option<Receipt>, Error> {
+        let receipt = match self.send.read() {
+            Ok(send) => send.receipt(socket_id, id)?,
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        //a dead connection is never going to ack what's left
+        match (receipt, self.connections.get(&socket_id).map(|c| c.status())) {
+            (None, Some(NeonStatus::Unhealthy(_))) => Ok(Some(Receipt::ConnectionLost)),
+            (receipt, _) => Ok(receipt),
+        }
+    }
+    //messages (sent, received, shed) that expired or were thrown out before they could be delivered
+    pub fn dropped(&self, socket_id: u16) -> Option<(usize, usize, usize, usize)> {
+        if !self.connections.contains_key(&socket_id) {
+            return None;
+        }
+        let (sent, sent_shed) = match self.send.read() {
+            Ok(send) => (send.dropped(socket_id), send.partner_shed(socket_id)),
+            Err(_) => return None,
+        };
+        let (received, shed) = match self.recv.read() {
+            Ok(recv) => (recv.dropped(socket_id), recv.shed(socket_id)),
+            Err(_) => return None,
+        };
+        Some((sent, sent_shed, received, shed))
+    }
+    pub fn shutdown(&mut self, socket_id: u16, how: Shutdown) -> Result<(), Error> {
+        match how {
+            Shutdown::Read => match self.connections.get_mut(&socket_id) {
+                Some(connection) => {
+                    connection.shutdown_read();
+                    self.notify();
+                    Ok(())
+                }
+                None => Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
+            },
+            Shutdown::Write => {
+                let open = match self.connections.get_mut(&socket_id) {
+                    Some(connection) => {
+                        let open = connection.writable().is_ok();
+                        connection.shutdown_write();
+                        open
+                    }
+                    None => return Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
+                };
+                if open {
+                    self.send_shutdown(socket_id, SHUTDOWN_WRITE);
+                }
+                Ok(())
+            }
+            Shutdown::Both => {
+                self.close(socket_id);
+                Ok(())
+            }
+        }
+    }
+    //Everything still waiting on an ack, a listener drains this before closing
+    pub fn unacked_total(&self) -> usize {
+        self.connections
+            .keys()
+            .filter_map(|socket_id| self.unacked(*socket_id))
+            .sum()
+    }
+    pub fn close_all(&mut self) {
+        let sockets = self.connections.keys().copied().collect::<Vec<_>>();
+        sockets
+            .into_iter()
+            .for_each(|socket_id| self.close(socket_id));
+        self.queued_streams.clear();
+        self.accept.notify();
+    }
+    //Tears the connection down locally, tells the partner unless it already left
+    pub fn close(&mut self, socket_id: u16) {
+        let tell_partner = match self.connections.get(&socket_id) {
+            Some(connection) => !matches!(
+                connection.status(),
+                NeonStatus::Closed | NeonStatus::Unhealthy(_)
+            ),
+            None => return,
+        };
+        if tell_partner {
+            self.send_shutdown(socket_id, SHUTDOWN_CLOSE);
+        }
+        self.remove_connection(socket_id);
+    }
+    fn remove_connection(&mut self, socket_id: u16) {
+        if let Some(connection) = self.connections.remove(&socket_id) {
+            //another connection to the same partner still needs its numbers narrowed
+            let addr = connection.partner_in_addr();
+            let shared = self.connections.values().any(|other| other.partner_in_addr() == addr);
+            if let (false, Ok(channel)) = (shared, self.channel.read()) {
+                channel.narrow(addr, false);
+            }
+        }
+        if let Ok(mut binding) = self.send.write() {
+            binding.remove(socket_id)
+        }
+        if let Ok(mut binding) = self.recv.write() {
+            binding.remove(socket_id)
+        }
+    }
+    pub fn next_stream(&mut self) -> Option<u16> {
         //only clean up the queued connections when someone asks
-        self.manage_streams(core.clone());
-        self.queued_streams.pop_front()
+        self.manage_streams();
+        while let Some(socket_id) = self.queued_streams.pop_front() {
+            //skip anything that closed while it was waiting
+            if let Some(connection) = self.connections.get_mut(&socket_id) {
+                if connection.status() == NeonStatus::Queued {
+                    connection.claim();
+                    return Some(socket_id);
+                }
+            }
+        }
+        None
     }
 
     pub fn process_handshake(&mut self, socket_id: u16, packet: ControlPacket) {
-        let stamp = packet.stamp;
-
         //should send a handshake packet back of type response, but only send it once (it will beacon if it doesn't get it)
         let info = match packet.info {
             ControlPacketInfo::Handshake(info) => info,
@@ -477,14 +995,35 @@ impl NeonCore {
         match info.req_type {
             ReqType::Connection => {}
             ReqType::Response => {
-                if Handshake::validate(MAX_PACKET_SIZE, FLOW_CONTROL, socket_id, info) {
+                //the partner picked, it has to be something we speak and offered
+                let (version, features) = match info.agree(self.config.wire_version, self.config.capabilities) {
+                    Some(agreed) => agreed,
+                    None => {
+                        if let Some(connection) = self.connections.get_mut(&socket_id) {
+                            connection.error(ERR_VERSION);
+                        }
+                        self.notify();
+                        return;
+                    }
+                };
+                if Handshake::validate(self.config.mss, self.config.flow_window, socket_id, info) {
                     if let Some(connection) = self.connections.get_mut(&socket_id) {
-                        connection.negotiate(stamp, info.src_socket_id, info.port);
+                        connection.negotiate(info.src_socket_id, info.port);
+                        connection.agree(version, features, info.isn);
+                        if let Ok(channel) = self.channel.read() {
+                            channel.narrow(connection.partner_in_addr(), connection.narrow());
+                        }
                         if let Ok(recv) = self.recv.write() {
-                            recv.register_connection(socket_id, info.isn)
+                            recv.register_connection(socket_id, info.isn, &self.config)
                         }
                         if let Ok(send) = self.send.write() {
-                            send.register_connection(socket_id, connection.isn())
+                            send.register_connection(
+                                socket_id,
+                                connection.isn(),
+                                &self.config.agreed(features),
+                                info.flow_control.min(connection.max_window()),
+                                connection.epoch(),
+                            )
                         }
                     }
 
@@ -497,32 +1036,41 @@ impl NeonCore {
         &mut self,
         socket_id: u16,
         data: &[u8],
-        ttl: Duration,
-        order: bool,
-    ) -> Result<(), Error> {
+        options: MessageOptions,
+    ) -> Result<MessageId, Error> {
         let (partner_id, out_mss) = match self.connections.get(&socket_id) {
             Some(connection) => {
+                connection.writable()?;
                 let partner_id = connection.partner_id();
                 let (_, out_mss) = connection.mss();
 
                 (partner_id, out_mss)
             }
-            None => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            None => return Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
         };
         let delay = match self.recv.read() {
             Ok(recv) => recv.delay(socket_id),
             Err(_) => Duration::ZERO,
         };
+        let coalesce = self.config.coalesce;
         let out = match self.send.write() {
-            Ok(mut send) => {
-                let cnt = send.push_data(socket_id, data, ttl, order, partner_id, out_mss);
-                send.update(socket_id, cnt, delay);
-                Ok(())
-            }
+            Ok(mut send) => send
+                .push_data(socket_id, data, options, partner_id, out_mss)
+                .map(|(cnt, id)| {
+                    send.update(socket_id, cnt, delay);
+                    //wake up again when a bundle this joined is due to flush
+                    if let Some(flush) = coalesce {
+                        send.update(socket_id, 1, flush);
+                    }
+                    id
+                }),
             Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
         };
         if let Some(connection)=self.connections.get_mut(&socket_id){
             connection.sent_packet();
+            if let Ok(id) = &out {
+                connection.sent_message(id.channel(), id.msg_no());
+            }
         }
         out
     }
@@ -539,7 +1087,7 @@ impl NeonCore {
                     seq_no,
                     rtt,
                     rtt_var,
-                    buffer as u16,
+                    buffer.min(u16::MAX as usize) as u16,
                     window,
                     bandwidth,
                 );
@@ -603,7 +1151,12 @@ impl NeonCore {
     }
     pub fn send_keep_alive(&mut self, socket_id: u16) {
         if let Ok(send) = self.send.write() {
-            if send.keep_alive(socket_id) {
+            if !send.keep_alive(socket_id) {
+                //data timed out and is being resent
+                if let Some(connection) = self.connections.get_mut(&socket_id) {
+                    connection.on_timeout();
+                }
+            } else {
                 if let Some(connection) = self.connections.get_mut(&socket_id) {
                     let (addr, packet) = connection.create_keep_alive();
                     let channel = match self.channel.read() {
@@ -648,9 +1201,60 @@ impl NeonCore {
             connection.sent_packet();
         }
     }
-    pub fn send_drop(&mut self, socket_id: u16, msg_no: MessageNumber, range: SequenceRange) {
+    //Sent once in its turn with the data, never resent
+    pub fn send_datagram(&mut self, socket_id: u16, channel: u16, data: &[u8]) -> Result<(), Error> {
+        let (partner_id, out_mss) = match self.connections.get(&socket_id) {
+            Some(connection) => {
+                connection.writable()?;
+                if !connection.supports(CAP_DATAGRAM) {
+                    return Err(Error::new(ErrorKind::Unsupported, "Partner doesn't take datagrams"));
+                }
+                let (_, out_mss) = connection.mss();
+                (connection.partner_id(), out_mss)
+            }
+            None => return Err(Error::new(ErrorKind::NotConnected, "Connection closed")),
+        };
+        let data = DataPacket::compress(data);
+        if data.len() > out_mss as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "Datagram larger than the mss"));
+        }
+        let delay = match self.recv.read() {
+            Ok(recv) => recv.delay(socket_id),
+            Err(_) => Duration::ZERO,
+        };
+        let packet = Packet::Control(ControlPacket::datagram(partner_id, channel, data));
+        let out = match self.send.write() {
+            Ok(mut send) => send
+                .push_datagram(socket_id, packet)
+                .map(|_| send.update(socket_id, 1, delay)),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.sent_packet();
+        }
+        out
+    }
+    pub fn send_shed(&mut self, socket_id: u16, channel: u16, msg_no: MessageNumber) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            let (addr, packet) = connection.create_shed(channel, msg_no);
+            let channel = match self.channel.read() {
+                Ok(channel) => channel,
+                Err(_) => return,
+            };
+            if let Ok(send) = self.send.write() {
+                let _ = send.send_packet(&channel, addr, packet);
+            }
+        }
+    }
+    pub fn send_drop(
+        &mut self,
+        socket_id: u16,
+        channel: u16,
+        msg_no: MessageNumber,
+        ranges: Vec<SequenceRange>,
+    ) {
         if let Some(connection) = self.connections.get_mut(&socket_id) {
-            let (addr, packet) = connection.create_drop(msg_no, range);
+            let (addr, packet) = connection.create_drop(channel, msg_no, ranges);
             let channel = match self.channel.read() {
                 Ok(channel) => channel,
                 Err(_) => return,
@@ -678,6 +1282,56 @@ impl NeonCore {
             connection.sent_packet();
         }
     }
+    //A request when origin is none, otherwise the reply to the partner's request sent at origin
+    pub fn send_timing(&mut self, socket_id: u16, origin: Option<u32>) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            let (addr, packet) = match origin {
+                None => connection.create_timing(),
+                Some(origin) => connection.create_timing_reply(origin),
+            };
+            let channel = match self.channel.read() {
+                Ok(channel) => channel,
+                Err(_) => return,
+            };
+            if let Ok(send) = self.send.write() {
+                send.resync(socket_id, connection.epoch());
+                let _ = send.send_packet(&channel, addr, packet);
+            }
+            connection.sent_packet();
+        }
+    }
+    pub fn send_probe(&mut self, socket_id: u16) {
+        let rtt = match self.recv.read() {
+            Ok(recv) => match recv.time_data(socket_id) {
+                Some(data) => data.0,
+                None => Duration::ZERO,
+            },
+            Err(_) => Duration::ZERO,
+        };
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            if let Some((addr, packet)) = connection.create_probe(rtt) {
+                let channel = match self.channel.read() {
+                    Ok(channel) => channel,
+                    Err(_) => return,
+                };
+                if let Ok(send) = self.send.write() {
+                    let _ = send.send_packet(&channel, addr, packet);
+                }
+            }
+        }
+    }
+    pub fn send_probe_ack(&mut self, socket_id: u16, received: u16) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            let (addr, packet) = connection.create_probe_ack(received);
+            let channel = match self.channel.read() {
+                Ok(channel) => channel,
+                Err(_) => return,
+            };
+            if let Ok(send) = self.send.write() {
+                let _ = send.send_packet(&channel, addr, packet);
+            }
+        }
+    }
     pub fn process_keep_alive(&mut self, socket_id: u16, _: ControlPacket) {
         //If keep alive but ack is wrong resend
         if let Ok(send) = self.send.write() {
@@ -697,14 +1351,35 @@ impl NeonCore {
             ControlMeta::Seq(other) => other,
             _ => return,
         };
-        if let Ok(mut binding) = self.recv.write() {
-            binding.on_ack(socket_id, ack_no, info)
-        }
+        let free = match self.connections.get(&socket_id) {
+            Some(connection) => info.buffer_size.min(connection.max_window()) as usize,
+            None => return,
+        };
+        let congestion_window = match self.recv.write() {
+            Ok(mut binding) => {
+                binding.on_ack(socket_id, ack_no, info);
+                binding.congestion_window(socket_id)
+            }
+            Err(_) => return,
+        };
 
-        if match self.send.write() {
-            Ok(mut binding) => binding.ack(socket_id, ack_no),
-            Err(_) => false,
-        } {
+        let (square, progress) = match self.send.write() {
+            Ok(mut binding) => {
+                let size = binding.size(socket_id);
+                let square = binding.ack(socket_id, ack_no);
+                //never more in flight than the partner has room for
+                let window = congestion_window.min(free);
+                binding.window(socket_id, window);
+                (square, binding.size(socket_id) < size)
+            }
+            Err(_) => (false, false),
+        };
+        if progress {
+            if let Some(connection) = self.connections.get_mut(&socket_id) {
+                connection.on_progress();
+            }
+        }
+        if square {
             self.send_ack_square(socket_id, ack_no)
         }
     }
@@ -738,19 +1413,37 @@ impl NeonCore {
             ControlMeta::Other(other) => other,
             _ => return,
         };
-        match self.connections.get(&socket_id) {
-            Some(_) => self.send_shutdown(socket_id, code),
-            None => todo!(),
-        }
-
-        self.connections.remove(&socket_id);
-
-        if let Ok(mut binding) = self.send.write() {
-            binding.remove(socket_id)
-        }
-        if let Ok(mut binding) = self.recv.write() {
-            binding.remove(socket_id)
+        match code {
+            SHUTDOWN_WRITE => {
+                if let Some(connection) = self.connections.get_mut(&socket_id) {
+                    connection.remote_shutdown_write();
+                }
+            }
+            _ => {
+                //answer once so the partner knows it landed, a late answer hits a removed socket
+                let status = match self.connections.get(&socket_id) {
+                    Some(connection) => connection.status(),
+                    None => return,
+                };
+                if status == NeonStatus::Closed {
+                    return;
+                }
+                self.send_shutdown(socket_id, SHUTDOWN_CLOSE);
+                if let Some(connection) = self.connections.get_mut(&socket_id) {
+                    connection.remote_close();
+                }
+                //nothing we send will be read, keep the recv side so what arrived can be
+                if let Ok(mut binding) = self.send.write() {
+                    binding.remove(socket_id)
+                }
+                //a stream nobody accepted yet has no one to read it
+                if !self.connections.get(&socket_id).is_some_and(|c| c.claimed()) {
+                    self.queued_streams.retain(|queued| *queued != socket_id);
+                    self.remove_connection(socket_id);
+                }
+            }
         }
+        self.notify();
     }
     pub fn process_ack_square(&mut self, socket_id: u16, packet: ControlPacket) {
         let ack_no = match packet.meta {
@@ -771,6 +1464,40 @@ impl NeonCore {
             Err(_) => {}
         }*/
     }
+    pub fn process_datagram(&mut self, socket_id: u16, packet: ControlPacket) {
+        let channel = match packet.meta {
+            ControlMeta::Other(channel) => channel,
+            _ => return,
+        };
+        let info = match packet.info {
+            ControlPacketInfo::Datagram(info) => info,
+            _ => return,
+        };
+        //nothing to hand it to until the stream exists
+        match self.connections.get(&socket_id) {
+            Some(connection) => match connection.status() {
+                NeonStatus::Healthy | NeonStatus::Queued | NeonStatus::Established => {}
+                _ => return,
+            },
+            None => return,
+        }
+        if let Ok(recv) = self.recv.read() {
+            recv.process_datagram(socket_id, channel, DataPacket::decompress(&info.data));
+        };
+    }
+    pub fn process_shed(&mut self, socket_id: u16, packet: ControlPacket) {
+        let msg_no = match packet.meta {
+            ControlMeta::Message(msg_no) => msg_no,
+            _ => return,
+        };
+        let info = match packet.info {
+            ControlPacketInfo::Shed(info) => info,
+            _ => return,
+        };
+        if let Ok(send) = self.send.read() {
+            send.shed(socket_id, info.channel, msg_no);
+        };
+    }
     pub fn process_drop(&mut self, socket_id: u16, packet: ControlPacket) {
         let msg_no = match packet.meta {
             ControlMeta::Message(msg_no) => msg_no,
@@ -781,7 +1508,7 @@ impl NeonCore {
             _ => return,
         };
         if let Ok(recv) = self.recv.write() {
-            recv.drop_msg(socket_id, msg_no, info.range);
+            recv.drop_msg(socket_id, info.channel, msg_no, &info.ranges);
         };
     }
 
@@ -793,6 +1520,21 @@ impl NeonCore {
         if let Some(connection) = self.connections.get_mut(&socket_id) {
             connection.error(code)
         }
+        self.notify();
+    }
+    pub fn process_timing(&mut self, socket_id: u16, packet: ControlPacket) {
+        let info = match packet.info {
+            ControlPacketInfo::Timing(info) => info,
+            _ => return,
+        };
+        match info.req_type {
+            ReqType::Connection => self.send_timing(socket_id, Some(packet.timestamp)),
+            ReqType::Response => {
+                if let Some(connection) = self.connections.get_mut(&socket_id) {
+                    connection.on_timing_reply(info.origin, info.received, packet.timestamp);
+                }
+            }
+        }
     }
     pub fn process_discover(&mut self, socket_id: u16, packet: ControlPacket) {
         //measures how much of the packet made it
@@ -804,12 +1546,28 @@ impl NeonCore {
             ControlMeta::Other(other) => other,
             _ => return,
         };
+        if info.probe {
+            //path mtu probes don't touch the connection state
+            match info.req_type {
+                ReqType::Connection => self.send_probe_ack(socket_id, info.size()),
+                ReqType::Response => {
+                    if let Some(connection) = self.connections.get_mut(&socket_id) {
+                        connection.on_probe_ack(meta);
+                    }
+                    //no need to wait for the state thread to keep searching
+                    self.send_probe(socket_id);
+                }
+            }
+            return;
+        }
         if let Some(connection) = self.connections.get_mut(&socket_id) {
-            connection.establish(info.data.len() as u16, meta);
+            connection.establish(info.size(), meta);
             match info.req_type {
                 ReqType::Connection => self.send_discover(socket_id, ReqType::Response),
                 ReqType::Response => {}
             }
         }
+        //queue it now so a blocked accept wakes up
+        self.manage_streams();
     }
 }
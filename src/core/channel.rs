@@ -1,10 +1,11 @@
 use crate::{
-    packet::{Packet, HEADER_SIZE},
+    packet::{Packet, DATA_HEADER_SIZE, HEADER_SIZE},
     serial::Serial,
 };
 use std::{
-    io::Error,
-    net::{SocketAddr, UdpSocket},
+    collections::HashSet,
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     num::Wrapping,
     sync::{Arc, Mutex},
 };
@@ -14,6 +15,7 @@ static SOCKET_COUNTER: Mutex<Wrapping<u16>> = Mutex::new(Wrapping(0));
 pub struct NeonChannel {
     pub outbound: Arc<NeonSocket>,
     pub inbound: Arc<NeonSocket>,
+    narrow: Mutex<HashSet<SocketAddr>>, //partners that agreed on version 1, see Packet::narrow
 }
 pub struct NeonSocket {
     pub addr: SocketAddr,
@@ -36,6 +38,7 @@ impl NeonChannel {
         Ok(Self {
             outbound: dual.clone(),
             inbound: dual.clone(),
+            narrow: Mutex::new(HashSet::new()),
         })
     }
 
@@ -48,7 +51,11 @@ impl NeonChannel {
             Ok(connection) => Arc::new(connection),
             Err(err) => return Err(err),
         };
-        Ok(Self { outbound, inbound })
+        Ok(Self {
+            outbound,
+            inbound,
+            narrow: Mutex::new(HashSet::new()),
+        })
     }
 
     pub fn inc_socket_id(&self) -> u16 {
@@ -63,10 +70,40 @@ impl NeonChannel {
     }
 
     pub fn send_to(&self, addr: SocketAddr, packet: Packet) -> Result<usize, Error> {
-        self.outbound.send_to(addr, packet)
+        self.outbound.send_to(addr, self.fit(addr, packet))
+    }
+    pub fn recv_from(&self, addr: &mut SocketAddr, mss: u16) -> Result<Packet, Error> {
+        self.inbound.recv_from(addr, mss)
     }
-    pub fn recv_from(&self, addr: &mut SocketAddr) -> Result<Packet, Error> {
-        self.inbound.recv_from(addr)
+    //Back out the socket a packet came in on, the sender knows that address
+    pub fn reply_to(&self, addr: SocketAddr, packet: Packet) -> Result<usize, Error> {
+        self.inbound.send_to(addr, self.fit(addr, packet))
+    }
+    //Everything to the partner goes out at the width it agreed on
+    pub fn narrow(&self, addr: SocketAddr, narrow: bool) {
+        if let Ok(mut partners) = self.narrow.lock() {
+            match narrow {
+                true => partners.insert(addr),
+                false => partners.remove(&addr),
+            };
+        }
+    }
+    fn fit(&self, addr: SocketAddr, packet: Packet) -> Packet {
+        match self.narrow.lock() {
+            Ok(partners) if partners.contains(&addr) => packet.narrow(),
+            _ => packet,
+        }
+    }
+    //An empty datagram to ourselves so a blocked recv_from returns
+    pub fn wake(&self) -> Result<usize, Error> {
+        let mut addr = self.inbound.addr;
+        if addr.ip().is_unspecified() {
+            match addr {
+                SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        self.outbound.socket.send_to(&[], addr)
     }
 }
 
@@ -125,18 +162,23 @@ impl NeonSocket {
             socket.send_to(&packet.serialize(), addr)
         }
     }
-    pub fn recv_from(&self, addr: &mut SocketAddr) -> Result<Packet, Error> {
+    pub fn recv_from(&self, addr: &mut SocketAddr, mss: u16) -> Result<Packet, Error> {
         let socket = match self.direction {
             SocketDirection::In => &self.socket,
             SocketDirection::Out => unreachable!(),
             SocketDirection::Shared => &self.socket,
         };
         //the maximum allowed packet size
-        let mut bytes = vec![0u8; HEADER_SIZE + MAX_PACKET_SIZE as usize];
+        let mut bytes = vec![0u8; DATA_HEADER_SIZE + mss as usize];
         let (count, recv_addr) = match socket.recv_from(&mut bytes) {
             Ok(res) => res,
             Err(err) => return Err(err),
         };
+        //wake ups and garbage, nothing shorter than a header is a packet
+        let data = count > 0 && bytes[0] & 0x80 == 0;
+        if count < HEADER_SIZE || (data && count < DATA_HEADER_SIZE) {
+            return Err(Error::new(ErrorKind::InvalidData, "Runt packet"));
+        }
         let packet = Packet::deserialize(&bytes[..count], &mut 0);
         *addr = recv_addr;
         #[cfg(feature = "drop_recv")]
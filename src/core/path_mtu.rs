@@ -0,0 +1,136 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+use crate::packet::DATA_HEADER_SIZE;
+
+//largest udp payloads on a 1500 byte ethernet link
+pub const IPV4_MAX_PAYLOAD: u16 = 1472;
+pub const IPV6_MAX_PAYLOAD: u16 = 1452;
+pub const PMTU_INTERVAL: Duration = Duration::from_secs(600);
+const PMTU_STEP: u16 = 8; //stop searching once the window is this tight
+const PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+const BLACK_HOLE_LIMIT: usize = 3; //retransmission timeouts without progress
+
+/*
+    Binary search for the largest discover packet that makes it to the partner
+        low is always a size that was acknowledged (starts at the negotiated mss)
+        high is the largest size that might still work
+    A probe that times out lowers high, an acknowledged probe raises low
+    Once settled the search restarts every interval in case the path got better
+*/
+#[derive(Debug)]
+pub struct PathMtu {
+    floor: u16,
+    ceiling: u16,
+    low: u16,
+    high: u16,
+    probe: Option<(u16, SystemTime)>,
+    settled: Option<SystemTime>,
+    interval: Duration,
+    failures: usize,
+}
+
+impl PathMtu {
+    pub fn new(floor: u16, max_mss: Option<u16>, interval: Duration, addr: SocketAddr) -> Self {
+        let ceiling = match max_mss {
+            Some(max_mss) => max_mss,
+            None => Self::default_ceiling(addr),
+        }
+        .max(floor);
+        Self {
+            floor,
+            ceiling,
+            low: floor,
+            high: ceiling,
+            probe: None,
+            settled: None,
+            interval,
+            failures: 0,
+        }
+    }
+    pub fn default_ceiling(addr: SocketAddr) -> u16 {
+        match addr {
+            SocketAddr::V4(_) => IPV4_MAX_PAYLOAD - DATA_HEADER_SIZE as u16,
+            SocketAddr::V6(_) => IPV6_MAX_PAYLOAD - DATA_HEADER_SIZE as u16,
+        }
+    }
+    //the negotiated mss is the safe floor, never search below it
+    pub fn reset(&mut self, floor: u16) {
+        self.floor = floor;
+        self.low = floor;
+        self.high = self.ceiling.max(floor);
+        self.probe = None;
+        self.settled = None;
+        self.failures = 0;
+    }
+    pub fn mss(&self) -> u16 {
+        self.low
+    }
+    //Returns the next size to probe if one should go out now
+    pub fn next_probe(&mut self, rtt: Duration) -> Option<u16> {
+        if let Some((size, sent)) = self.probe {
+            match sent.elapsed() {
+                Ok(elapsed) if elapsed > PROBE_TIMEOUT.max(rtt * 2) => {
+                    //didn't make it, assume the path is smaller than this
+                    self.high = size - 1;
+                    self.probe = None;
+                }
+                _ => return None,
+            }
+        }
+        if self.high.saturating_sub(self.low) < PMTU_STEP {
+            match self.settled {
+                Some(settled) => match settled.elapsed() {
+                    Ok(elapsed) if elapsed > self.interval => {
+                        //periodically check if the path got wider
+                        self.high = self.ceiling;
+                        self.settled = None;
+                    }
+                    _ => return None,
+                },
+                None => {
+                    self.settled = Some(SystemTime::now());
+                    return None;
+                }
+            }
+        }
+        let size = self.low + (self.high - self.low).div_ceil(2);
+        self.probe = Some((size, SystemTime::now()));
+        Some(size)
+    }
+    //The partner tells us how much of the probe arrived, returns the new mss if it grew
+    pub fn on_probe_ack(&mut self, received: u16) -> Option<u16> {
+        let size = match self.probe {
+            Some((size, _)) => size,
+            None => return None,
+        };
+        self.probe = None;
+        if received < size {
+            //the partner couldn't take all of it
+            self.high = received;
+        }
+        if received > self.low {
+            self.low = received.min(self.high);
+            Some(self.low)
+        } else {
+            None
+        }
+    }
+    //A retransmission timed out with nothing acked, returns the new mss on a black hole
+    pub fn on_timeout(&mut self) -> Option<u16> {
+        self.failures += 1;
+        if self.failures >= BLACK_HOLE_LIMIT && self.low > self.floor {
+            //packets that used to fit are vanishing, drop to safe and search again
+            let floor = self.floor;
+            self.reset(floor);
+            Some(floor)
+        } else {
+            None
+        }
+    }
+    pub fn on_progress(&mut self) {
+        self.failures = 0;
+    }
+}
@@ -1,21 +1,32 @@
 use std::{
-    cmp::{min, Ordering},
-    collections::HashMap,
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet, VecDeque},
     time::{Duration, SystemTime},
 };
 
 use crate::{
+    config::NeonConfig,
     congestion::CongestionController,
-    core::SYN_INTERVAL,
     packet::data::{DataPacket, DataPacketType},
     utils::{MessageNumber, SequenceNumber, SequenceRange},
     window::ack_window::Window,
 };
 
+//What a full receive buffer does with a message that doesn't fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    #[default]
+    Refuse,         //nothing that doesn't fit is acked, the sender resends it once the reader catches up
+    Shed(Duration), //messages sent with a ttl left unread this long are thrown out to make room, the rest are refused
+}
+
 //This structure only tracks data messages and outputs fully formed packets
 pub struct RecvBuffer {
-    last_msg: MessageNumber,         //the last msg popped
-    last_seq: SequenceNumber,        //the last seq processed
+    channels: HashMap<u16, ChannelOrder>, //every channel numbers its messages on its own
+    fresh: VecDeque<u16>,                 //channels the partner opened that no one has picked up
+    last_seq: SequenceNumber,        //the last seq processed, everything up to it is in
+    max_seq: SequenceNumber,         //the highest seq seen, gaps below it are already reported
+    ahead: HashSet<SequenceNumber>,  //seqs past a gap, last_seq jumps over them once it fills
     last_ack: SequenceNumber,        //the last ack sent
     last_ack_square: SequenceNumber, //the last ack square sent
     last_ack_time: SystemTime,
@@ -23,25 +34,44 @@ pub struct RecvBuffer {
     next_ack_time: SystemTime,
     ack_window: Window,
     congestion: CongestionController,
-    blocks: HashMap<MessageNumber, RecvBlock>,
+    blocks: HashMap<(u16, MessageNumber), RecvBlock>,
+    packets: usize,       //held in blocks, kept up as they come and go
+    bytes: usize,         //same, as counted by RecvBlock::size
+    capacity: usize,      //packets
+    byte_capacity: usize, //payload bytes as they came off the wire
+    msg_capacity: usize,  //messages, finished or not
+    mss: usize,           //turns free bytes into the packets advertised
+    overflow: Overflow,
+    advertised: usize, //free packets in the last ack
+    dropped: usize,    //messages the partner gave up on
+    shed: usize,       //messages thrown out to make room
+    unreported: Vec<(u16, MessageNumber)>, //shed messages the sender hasn't been told about
 }
 
 impl RecvBuffer {
-    pub fn new(self_isn: SequenceNumber) -> Self {
-        let last_msg = MessageNumber::ZERO;
+    pub fn new(self_isn: SequenceNumber, config: &NeonConfig) -> Self {
+        //channel 0 is the stream itself
+        let channels = HashMap::from([(0, ChannelOrder::new())]);
+        let fresh = VecDeque::new();
         let blocks = HashMap::new();
         let last_seq = self_isn;
         let last_ack = self_isn;
         let last_ack_square = last_ack;
         let last_ack_time = SystemTime::now();
         let last_ack_square_time = SystemTime::now();
-        let next_ack_time = SystemTime::now() + SYN_INTERVAL;
-        let congestion = CongestionController::new();
+        let next_ack_time = SystemTime::now() + config.ack_interval;
+        let congestion = CongestionController::new(config);
+        let capacity = config.recv_buffer;
         let ack_window = Window::new(Duration::from_millis(2000));
         Self {
-            last_msg,
+            channels,
+            fresh,
             last_seq,
+            max_seq: last_seq,
+            ahead: HashSet::new(),
             blocks,
+            packets: 0,
+            bytes: 0,
             last_ack,
             last_ack_square,
             last_ack_time,
@@ -49,33 +79,65 @@ impl RecvBuffer {
             next_ack_time,
             ack_window,
             congestion,
+            capacity,
+            byte_capacity: config.recv_bytes,
+            msg_capacity: config.recv_messages,
+            mss: config.mss.max(1) as usize,
+            overflow: config.overflow,
+            advertised: capacity,
+            dropped: 0,
+            shed: 0,
+            unreported: Vec::new(),
         }
     }
 
     pub fn add(&mut self, packet: DataPacket) -> Option<SequenceRange> {
         let msg_no = packet.msg_no;
-        //check for dropped packets
-        let mut start = self.last_seq;
+        let key = (packet.channel, msg_no);
+        //when full only take packets that finish messages, the rest are resent once the reader catches up
+        if !self.blocks.contains_key(&key) && self.full(packet.data.len()) {
+            self.make_room(packet.data.len());
+            if self.full(packet.data.len()) {
+                return None;
+            }
+        }
+        //the rest of a message gets past a full buffer, but no one message holds more than all of it
+        if self.capped(key, &packet) {
+            return None;
+        }
+        //check for dropped packets, only the ones past anything seen are news
+        let mut start = self.max_seq;
         start.inc();
-        let skip_range = if packet.seq_no > start {
+        let skip_range = if start.serial_lt(packet.seq_no) {
             //someone dropped a packet
             let mut stop = packet.seq_no;
             stop.dec();
             Some(SequenceRange { start, stop })
         } else {
-            //update sequence numbers
-            if packet.seq_no > self.last_seq {
-                self.last_seq = packet.seq_no;
-            }
             None
         };
+        if self.max_seq.serial_lt(packet.seq_no) {
+            self.max_seq = packet.seq_no;
+        }
+        if self.last_seq.serial_lt(packet.seq_no) {
+            self.ahead.insert(packet.seq_no);
+            self.advance();
+        }
+        //a resend of something the reader already has
+        if self.channel(packet.channel).delivered(msg_no) {
+            return skip_range;
+        }
 
         //put the data into the list
-        match self.blocks.get_mut(&msg_no) {
+        let last_seq = self.last_seq;
+        match self.blocks.get_mut(&key) {
             //Append to existing messages (if not already in list)
             Some(recv_block) => {
+                let (packets, bytes) = (recv_block.data.len(), recv_block.size());
                 recv_block.push(packet);
-                recv_block.update_state();
+                recv_block.update_state(last_seq);
+                self.packets += recv_block.data.len() - packets;
+                self.bytes += recv_block.size() - bytes;
             }
             //Create a new message
             None => {
@@ -84,71 +146,293 @@ impl RecvBuffer {
                 let data = vec![packet];
                 let mut recv_block = RecvBlock {
                     _stamp,
+                    arrived: SystemTime::now(),
                     data,
                     state,
+                    bytes: None,
+                    wire: 0,
                 };
-                recv_block.update_state();
-                self.blocks.insert(msg_no, recv_block);
+                recv_block.update_state(last_seq);
+                self.insert(key, recv_block);
             }
         }
+        self.unpack(key);
         skip_range
     }
-    pub fn pop(&mut self) -> Option<Vec<u8>> {
-        //Find all blocks for this socket
-
-        //Find all complete blocks
+    //A packet that isn't already held would take a message past the whole buffer
+    fn capped(&self, key: (u16, MessageNumber), packet: &DataPacket) -> bool {
+        match self.blocks.get(&key) {
+            Some(block) if !block.data.iter().any(|data| data.seq_no == packet.seq_no) => {
+                block.data.len() >= self.capacity || block.size() + packet.data.len() > self.byte_capacity
+            }
+            _ => false,
+        }
+    }
+    /*
+        A finished bundle is split into one block per message once they fit under the message limit
+        so pops, drops and resends treat them like they came on their own
+        Until then it waits whole and pop steps over it
+    */
+    fn unpack(&mut self, key: (u16, MessageNumber)) {
+        let messages = match self.blocks.get(&key) {
+            Some(block) if block.state == BlockState::Complete && block.data[0].bundle => {
+                DataPacket::unpack(&block.to_bytes())
+            }
+            _ => return,
+        };
+        let (channel, first) = key;
+        let mut msg_no = first;
+        let messages = messages
+            .into_iter()
+            .filter_map(|message| {
+                let out = (!self.channel(channel).delivered(msg_no)).then_some((msg_no, message));
+                msg_no.inc();
+                out
+            })
+            .collect::<Vec<_>>();
+        //alone it always goes, or a bundle bigger than the limit would never be read
+        if self.blocks.len() > 1 && self.blocks.len() - 1 + messages.len() > self.msg_capacity {
+            return;
+        }
+        let block = match self.remove(key) {
+            Some(block) => block,
+            None => return,
+        };
+        //each message carries its share of the wire bytes so the byte count doesn't change
+        let wire = block.size();
+        let total = messages.iter().map(|(_, message)| message.len()).sum::<usize>().max(1);
+        let mut left = wire;
+        let count = messages.len();
+        messages.into_iter().enumerate().for_each(|(i, (msg_no, message))| {
+            let share = match i + 1 == count {
+                true => left,
+                false => (wire * message.len() / total).min(left),
+            };
+            left -= share;
+            let mut packet = block.data[0].clone();
+            packet.msg_no = msg_no;
+            packet.element = DataPacketType::Solo;
+            packet.bundle = false;
+            packet.data = Vec::new();
+            let recv_block = RecvBlock {
+                _stamp: block._stamp,
+                arrived: block.arrived,
+                data: vec![packet],
+                state: BlockState::Complete,
+                bytes: Some(message),
+                wire: share,
+            };
+            self.insert((channel, msg_no), recv_block);
+        });
+    }
+    pub fn pop(&mut self, channel: u16) -> Option<Vec<u8>> {
+        //a plain read steps over the holes, see pop_intact
+        if let Some(order) = self.channels.get_mut(&channel) {
+            order.passed();
+        }
+        //gaps filled by other messages may have finished some
+        let last_seq = self.last_seq;
+        self.blocks
+            .values_mut()
+            .filter(|block| block.state == BlockState::Partial)
+            .for_each(|block| block.update_state(last_seq));
+        //and reads since may have made room for bundles still waiting whole
+        let bundles = self
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.state == BlockState::Complete && block.data[0].bundle)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        bundles.into_iter().for_each(|key| self.unpack(key));
+        let order = self.channels.get_mut(&channel)?;
+        //Find all complete blocks on this channel
         let mut complete_blocks = self
             .blocks
-            .iter_mut()
-            .filter(|(_, block)| block.state == BlockState::Complete)
+            .iter()
+            .filter(|((block_channel, _), block)| {
+                *block_channel == channel && block.state == BlockState::Complete && !block.data[0].bundle
+            })
+            .map(|((_, msg_no), block)| (*msg_no, block.ordered()))
             .collect::<Vec<_>>();
-        //Sort the block by message number
-        complete_blocks.sort_unstable_by(|a, b| a.0.cmp(b.0));
-        let out = match complete_blocks.first_mut() {
-            Some((msg_no, block)) => {
-                //check that this is the next message in the sequence
-
-                match **msg_no == self.last_msg {
-                    true => {
-                        let bytes = block.to_bytes();
-                        self.last_msg.inc();
-                        Some((**msg_no, bytes))
-                    }
-                    false => None,
-                }
-            }
-            None => None,
+        //Sort the block by how far past the next message they are
+        complete_blocks.sort_unstable_by_key(|(msg_no, _)| msg_no.diff(order.last_msg));
+        //the next message in the sequence, or an unordered one that doesn't have to wait for it
+        let msg_no = complete_blocks
+            .into_iter()
+            .find(|(msg_no, ordered)| *msg_no == order.last_msg || !ordered)
+            .map(|(msg_no, _)| msg_no)?;
+        order.release(msg_no);
+        let block = self.remove((channel, msg_no))?;
+        Some(block.to_bytes())
+    }
+    //Taking len more bytes as a new message would go past one of the limits
+    fn full(&self, len: usize) -> bool {
+        self.packets() >= self.capacity
+            || self.blocks.len() >= self.msg_capacity
+            || self.bytes() + len > self.byte_capacity
+    }
+    fn insert(&mut self, key: (u16, MessageNumber), block: RecvBlock) {
+        self.packets += block.data.len();
+        self.bytes += block.size();
+        if let Some(old) = self.blocks.insert(key, block) {
+            self.packets -= old.data.len();
+            self.bytes -= old.size();
+        }
+    }
+    fn remove(&mut self, key: (u16, MessageNumber)) -> Option<RecvBlock> {
+        let block = self.blocks.remove(&key)?;
+        self.packets -= block.data.len();
+        self.bytes -= block.size();
+        Some(block)
+    }
+    /*
+        Under Shed the messages left sitting longest go first, as long as they're past the age
+        Only ones sent with a ttl, the sender already has the rest down as acked
+    */
+    fn make_room(&mut self, len: usize) {
+        let age = match self.overflow {
+            Overflow::Refuse => return,
+            Overflow::Shed(age) => age,
         };
-
-        match out {
-            Some((msg_no, bytes)) => {
-                self.blocks.remove(&msg_no);
-                Some(bytes)
+        let mut stale = self
+            .blocks
+            .iter()
+            //a bundle's messages past the first have no block of their own to mark lost
+            .filter(|(_, block)| block.data[0].ttl && !block.data[0].bundle)
+            .filter(|(_, block)| block.arrived.elapsed().is_ok_and(|waited| waited >= age))
+            .map(|(key, block)| (block.arrived, *key))
+            .collect::<Vec<_>>();
+        stale.sort_unstable();
+        for (_, (channel, msg_no)) in stale {
+            if !self.full(len) {
+                break;
+            }
+            self.remove((channel, msg_no));
+            //whatever is still on its way for it is thrown away as a resend
+            self.channel(channel).lose(msg_no);
+            self.unreported.push((channel, msg_no));
+            self.shed += 1;
+        }
+    }
+    //Move last_seq up over whatever already arrived behind it
+    fn advance(&mut self) {
+        let mut next = self.last_seq;
+        next.inc();
+        while self.ahead.remove(&next) {
+            self.last_seq = next;
+            next.inc();
+        }
+    }
+    //Anything arriving on a channel we haven't seen means the partner opened it
+    fn channel(&mut self, channel: u16) -> &mut ChannelOrder {
+        match self.channels.entry(channel) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                self.fresh.push_back(channel);
+                entry.insert(ChannelOrder::new())
+            }
+        }
+    }
+    pub fn note_channel(&mut self, channel: u16) {
+        self.channel(channel);
+    }
+    //Channels the partner started sending on, in the order they showed up
+    pub fn next_channel(&mut self) -> Option<u16> {
+        self.fresh.pop_front()
+    }
+    //Like pop for readers that can't skip a message, a dropped or shed one they've caught up to is an Err once
+    pub fn pop_intact(&mut self, channel: u16) -> Result<Option<Vec<u8>>, MessageNumber> {
+        if let Some(order) = self.channels.get_mut(&channel) {
+            if let Some(msg_no) = order.passed() {
+                return Err(msg_no);
             }
-            None => None,
         }
+        Ok(self.pop(channel))
+    }
+    //Opening a channel ourselves means it isn't waiting to be picked up
+    pub fn open_channel(&mut self, channel: u16) {
+        self.fresh.retain(|fresh| *fresh != channel);
+        self.channels.entry(channel).or_insert_with(ChannelOrder::new);
     }
 
-    pub fn drop_msg(&mut self, msg_no: MessageNumber, range: SequenceRange) {
-        self.blocks.remove(&msg_no);
-        let mut prev_msg = msg_no;
-        prev_msg.dec();
-        if self.last_msg == prev_msg {
-            self.last_msg = msg_no
+    /*
+        The partner stopped sending a message, throw away what we have of it
+        Returns false when the message was already delivered or only waiting on a read
+    */
+    pub fn drop_msg(&mut self, channel: u16, msg_no: MessageNumber, ranges: &[SequenceRange]) -> bool {
+        //the seqs won't be resent either way so count them as in, the ones between belong to other messages
+        let last_seq = self.last_seq;
+        let ahead = &mut self.ahead;
+        ranges.iter().for_each(|range| {
+            range.for_each_seq(|seq| {
+                if last_seq.serial_lt(seq) {
+                    ahead.insert(seq);
+                }
+            })
+        });
+        self.advance();
+        if self.max_seq.serial_lt(self.last_seq) {
+            self.max_seq = self.last_seq;
         }
-        if range.start < self.last_seq && range.stop > self.last_seq {
-            self.last_seq = range.stop
+        let order = self.channels.entry(channel).or_insert_with(ChannelOrder::new);
+        if order.delivered(msg_no) {
+            return false;
         }
+        let key = (channel, msg_no);
+        if let Some(block) = self.blocks.get(&key) {
+            if block.state == BlockState::Complete {
+                return false;
+            }
+        }
+        //treat it like it went out so last_msg steps over it
+        order.lose(msg_no);
+        self.remove(key);
+        self.dropped += 1;
+        true
+    }
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+    pub fn shed(&self) -> usize {
+        self.shed
+    }
+    //The messages shed since the last call, for telling the sender
+    pub fn take_shed(&mut self) -> Vec<(u16, MessageNumber)> {
+        std::mem::take(&mut self.unreported)
     }
 
     pub fn size(&self) -> usize {
         self.blocks.len()
     }
+    pub fn packets(&self) -> usize {
+        self.packets
+    }
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
     pub fn last_seq(&self) -> SequenceNumber {
         self.last_seq
     }
+    /*
+        What the sender is allowed to have in flight, whichever of packets or bytes runs out first
+        The message limit isn't in it, one message can be any number of packets
+    */
+    pub fn free(&self) -> usize {
+        let packets = self.capacity.saturating_sub(self.packets());
+        let bytes = self.byte_capacity.saturating_sub(self.bytes()) / self.mss;
+        packets.min(bytes)
+    }
+    //free on an empty buffer
+    fn room(&self) -> usize {
+        self.capacity.min(self.byte_capacity / self.mss)
+    }
+    //Something arrived that hasn't been acked, or the sender was last told there's little room
+    pub fn owes_ack(&self) -> bool {
+        self.last_ack.serial_lt(self.last_seq) || self.advertised < self.room() / 2
+    }
     pub fn sent_ack(&mut self, ack_no: SequenceNumber) {
-        if ack_no > self.last_ack {
+        self.advertised = self.free();
+        if self.last_ack.serial_lt(ack_no) {
             self.last_ack = ack_no
         }
         self.ack_window.store(ack_no, ack_no)
@@ -158,7 +442,7 @@ impl RecvBuffer {
     }
     pub fn ack_square(&mut self, ack_no: SequenceNumber) {
         self.last_ack_square_time = SystemTime::now();
-        if ack_no > self.last_ack_square {
+        if self.last_ack_square.serial_lt(ack_no) {
             self.last_ack_square = ack_no;
         }
         let mut _discard = SequenceNumber::new(0);
@@ -177,13 +461,18 @@ impl RecvBuffer {
         if !should_ack {
             return None;
         }
+        //the reader made room since the last ack, the sender may be sitting on a shut window
+        if self.advertised < self.room() / 2 && self.free() >= self.room() / 2 {
+            self.next_ack_time = SystemTime::now() + self.congestion.next_ack();
+            return Some((proposed_ack, self.last_seq));
+        }
         //if we've confirmed this ack don't send it
         if proposed_ack == self.last_ack_square {
             return None;
         }
 
         //If this is a new ack or the last ack timed out
-        match proposed_ack.cmp(&self.last_ack){
+        match proposed_ack.serial_cmp(self.last_ack){
             Ordering::Less => return None,
             Ordering::Equal => {
                 let ack_timeout = match self.last_ack_time.elapsed() {
@@ -196,19 +485,16 @@ impl RecvBuffer {
             },
             Ordering::Greater => self.last_ack = proposed_ack,
         }
-        if self.last_ack > self.last_ack_square {
+        if self.last_ack_square.serial_lt(self.last_ack) {
             self.next_ack_time = SystemTime::now() + self.congestion.next_ack();
             return Some((proposed_ack, self.last_seq));
         }
         None
     }
     pub fn loss(&mut self, loss_ranges: Vec<SequenceRange>) {
-        let loss_start = loss_ranges
-            .iter()
-            .fold(SequenceNumber::MAX_SEQ_NO, |acc, range| {
-                min(range.start, acc)
-            });
-        self.congestion.on_loss(loss_start);
+        if !loss_ranges.is_empty() {
+            self.congestion.on_loss(SequenceNumber::earliest(loss_ranges.iter().map(|range| range.start)));
+        }
     }
     pub fn delay(&self) -> Duration {
         self.congestion.next_time()
@@ -216,9 +502,15 @@ impl RecvBuffer {
     pub fn on_pkt(&mut self) {
         self.congestion.inc_pkt_cnt();
     }
+    pub fn on_send(&mut self, seq_no: SequenceNumber) {
+        self.congestion.on_send(seq_no);
+    }
     pub fn rtt(&self) -> (Duration, Duration) {
         self.congestion.rtt()
     }
+    pub fn congestion_window(&self) -> usize {
+        self.congestion.window()
+    }
     pub fn update_rtt(&mut self, rtt: u16) {
         self.congestion.update_rtt(rtt)
     }
@@ -233,11 +525,65 @@ impl RecvBuffer {
     }
 }
 
+#[derive(Debug)]
+struct ChannelOrder {
+    last_msg: MessageNumber,           //the next msg to pop in order
+    released: BTreeSet<MessageNumber>, //unordered msgs already popped ahead of last_msg
+    lost: BTreeSet<MessageNumber>,     //dropped or shed msgs the reader hasn't got to yet
+}
+
+impl ChannelOrder {
+    fn new() -> Self {
+        Self {
+            last_msg: MessageNumber::FIRST,
+            released: BTreeSet::new(),
+            lost: BTreeSet::new(),
+        }
+    }
+    fn delivered(&self, msg_no: MessageNumber) -> bool {
+        msg_no.serial_lt(self.last_msg) || self.released.contains(&msg_no)
+    }
+    //A message went out (or was dropped), move last_msg past it and anything released early
+    fn release(&mut self, msg_no: MessageNumber) {
+        if msg_no == self.last_msg {
+            self.last_msg.inc();
+            while self.released.remove(&self.last_msg) {
+                self.last_msg.inc();
+            }
+        } else {
+            self.released.insert(msg_no);
+        }
+    }
+    //Released without ever reaching the reader
+    fn lose(&mut self, msg_no: MessageNumber) {
+        self.lost.insert(msg_no);
+        self.release(msg_no);
+    }
+    //Forgets the lost msgs everything before has been read up to, the first of them if any
+    fn passed(&mut self) -> Option<MessageNumber> {
+        let last_msg = self.last_msg;
+        let passed = self
+            .lost
+            .iter()
+            .filter(|msg_no| msg_no.serial_lt(last_msg))
+            .copied()
+            .collect::<Vec<_>>();
+        passed.iter().for_each(|msg_no| {
+            self.lost.remove(msg_no);
+        });
+        //the set is in plain number order, the first is the one furthest behind
+        passed.into_iter().max_by_key(|msg_no| last_msg.diff(*msg_no))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct RecvBlock {
     _stamp: SystemTime,
+    arrived: SystemTime, //when the first packet showed up here
     data: Vec<DataPacket>,
     state: BlockState,
+    bytes: Option<Vec<u8>>, //already decompressed, for messages out of a bundle
+    wire: usize,            //their share of the bundle's wire bytes, what they count against the limit
 }
 
 impl RecvBlock {
@@ -251,17 +597,20 @@ impl RecvBlock {
             None => self.data.push(insert_data),
         }
     }
-    pub fn update_state(&mut self) {
+    //last_seq is the receiver's, everything up to it has arrived
+    pub fn update_state(&mut self, last_seq: SequenceNumber) {
         //if this is solo it's complete
         self.state = if self.data[0].element == DataPacketType::Solo {
             BlockState::Complete
         } else {
             //order the data by seq_no
-            self.data.sort_unstable_by(|a, b| a.seq_no.cmp(&b.seq_no));
+            let base = SequenceNumber::earliest(self.data.iter().map(|data| data.seq_no));
+            self.data.sort_unstable_by_key(|data| data.seq_no.diff(base));
             //easy check to make sure first and last are here
             if self.data[0].element == DataPacketType::First
                 && self.data[self.data.len() - 1].element == DataPacketType::Last
             {
+
                 //Make sure the sequence is fully there
                 let mut first_seq = self.data[0].seq_no;
                 first_seq.dec();
@@ -274,7 +623,11 @@ impl RecvBlock {
                         (false, pkt.seq_no)
                     }
                 });
-                if valid {
+                //higher priorities cut in so the seqs aren't always back to back
+                //last_seq only steps over seqs that arrived or a drop named, and a drop takes its whole message
+                //so once the last is covered whatever is missing in between was someone else's
+                let covered = self.data[self.data.len() - 1].seq_no.serial_le(last_seq);
+                if valid || covered {
                     BlockState::Complete
                 } else {
                     BlockState::Partial
@@ -284,7 +637,20 @@ impl RecvBlock {
             }
         }
     }
+    pub fn ordered(&self) -> bool {
+        self.data[0].order
+    }
+    //what it holds against the byte limit, always as it came off the wire
+    pub fn size(&self) -> usize {
+        match &self.bytes {
+            Some(_) => self.wire,
+            None => self.data.iter().map(|pkt| pkt.data.len()).sum(),
+        }
+    }
     pub fn to_bytes(&self) -> Vec<u8> {
+        if let Some(bytes) = &self.bytes {
+            return bytes.clone();
+        }
         //Assume sorted in update state
         let raw_bytes = self.data.iter().fold(Vec::new(), |mut acc, pkt| {
             acc.extend_from_slice(&pkt.data);
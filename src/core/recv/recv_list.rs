@@ -1,6 +1,10 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use crate::{
+    config::{NeonConfig, DATAGRAM_BACKLOG},
     core::loss_list::LossBuffer,
     packet::{control::ack::Ack, data::DataPacket},
     utils::{MessageNumber, SequenceNumber, SequenceRange},
@@ -16,6 +20,7 @@ struct RecvBacker {
     data_buffer: RecvBuffer,
     loss_buffer: LossBuffer,
     time_window: TimeWindow,
+    datagrams: HashMap<u16, VecDeque<Vec<u8>>>, //per channel, oldest are thrown away once full
 }
 
 impl Default for RecvList {
@@ -33,14 +38,17 @@ impl RecvList {
         &mut self,
         socket_id: u16,
         self_isn: SequenceNumber,
+        config: &NeonConfig,
     ) {
-        let data_buffer = RecvBuffer::new(self_isn);
+        let data_buffer = RecvBuffer::new(self_isn, config);
         let loss_buffer = LossBuffer::new();
         let time_window = TimeWindow::new();
+        let datagrams = HashMap::new();
         let backer = RecvBacker {
             data_buffer,
             loss_buffer,
             time_window,
+            datagrams,
         };
         self.connections.insert(socket_id, backer);
     }
@@ -74,17 +82,73 @@ impl RecvList {
             None => vec![],
         }
     }
-    pub fn pop_data(&mut self, socket_id: u16) -> Option<Vec<u8>> {
+    pub fn pop_data(&mut self, socket_id: u16, channel: u16) -> Option<Vec<u8>> {
+        match self.connections.get_mut(&socket_id) {
+            Some(connection) => connection.data_buffer.pop(channel),
+            None => None,
+        }
+    }
+    pub fn pop_intact(&mut self, socket_id: u16, channel: u16) -> Result<Option<Vec<u8>>, MessageNumber> {
+        match self.connections.get_mut(&socket_id) {
+            Some(connection) => connection.data_buffer.pop_intact(channel),
+            None => Ok(None),
+        }
+    }
+    pub fn add_datagram(&mut self, socket_id: u16, channel: u16, data: Vec<u8>) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.data_buffer.note_channel(channel);
+            let datagrams = connection.datagrams.entry(channel).or_default();
+            if datagrams.len() >= DATAGRAM_BACKLOG {
+                datagrams.pop_front();
+            }
+            datagrams.push_back(data);
+        }
+    }
+    pub fn pop_datagram(&mut self, socket_id: u16, channel: u16) -> Option<Vec<u8>> {
+        match self.connections.get_mut(&socket_id) {
+            Some(connection) => connection.datagrams.get_mut(&channel)?.pop_front(),
+            None => None,
+        }
+    }
+    pub fn open_channel(&mut self, socket_id: u16, channel: u16) -> bool {
         match self.connections.get_mut(&socket_id) {
-            Some(connection) => connection.data_buffer.pop(),
+            Some(connection) => {
+                connection.data_buffer.open_channel(channel);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn next_channel(&mut self, socket_id: u16) -> Option<u16> {
+        match self.connections.get_mut(&socket_id) {
+            Some(connection) => connection.data_buffer.next_channel(),
             None => None,
         }
     }
-    pub fn drop_msg(&mut self, socket_id: u16, msg_no: MessageNumber, range: SequenceRange) {
+    pub fn drop_msg(&mut self, socket_id: u16, channel: u16, msg_no: MessageNumber, ranges: &[SequenceRange]) {
         if let Some(connection) = self.connections.get_mut(&socket_id) {
-            connection.data_buffer.drop_msg(msg_no, range)
+            connection.data_buffer.drop_msg(channel, msg_no, ranges);
+            //nothing in the ranges is coming, stop asking for it
+            ranges
+                .iter()
+                .for_each(|range| connection.loss_buffer.remove_range(*range));
         }
     }
+    pub fn dropped(&self, socket_id: u16) -> usize {
+        self.connections
+            .get(&socket_id)
+            .map_or(0, |connection| connection.data_buffer.dropped())
+    }
+    pub fn shed(&self, socket_id: u16) -> usize {
+        self.connections
+            .get(&socket_id)
+            .map_or(0, |connection| connection.data_buffer.shed())
+    }
+    pub fn take_shed(&mut self, socket_id: u16) -> Vec<(u16, MessageNumber)> {
+        self.connections
+            .get_mut(&socket_id)
+            .map_or(vec![], |connection| connection.data_buffer.take_shed())
+    }
     pub fn recv_speed(&self, socket_id: u16) -> usize {
         match self.connections.get(&socket_id) {
             Some(connection) => connection.time_window.receive_speed(),
@@ -97,9 +161,9 @@ impl RecvList {
             None => 0,
         }
     }
-    pub fn buffer_size(&self, socket_id: u16) -> usize {
+    pub fn free_buffer(&self, socket_id: u16) -> usize {
         match self.connections.get(&socket_id) {
-            Some(connection) => connection.data_buffer.size(),
+            Some(connection) => connection.data_buffer.free(),
             None => 0,
         }
     }
@@ -124,6 +188,11 @@ impl RecvList {
             None => None,
         }
     }
+    pub fn owes_ack(&self) -> bool {
+        self.connections
+            .values()
+            .any(|connection| connection.data_buffer.owes_ack())
+    }
     pub fn sent_ack(&mut self, socket_id: u16, ack_no: SequenceNumber) {
         if let Some(connection) = self.connections.get_mut(&socket_id) {
             connection.data_buffer.sent_ack(ack_no)
@@ -144,6 +213,17 @@ impl RecvList {
             connection.data_buffer.on_pkt()
         }
     }
+    pub fn on_send(&mut self, socket_id: u16, seq_no: SequenceNumber) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.data_buffer.on_send(seq_no)
+        }
+    }
+    pub fn congestion_window(&self, socket_id: u16) -> usize {
+        match self.connections.get(&socket_id) {
+            Some(connection) => connection.data_buffer.congestion_window(),
+            None => 0,
+        }
+    }
     pub fn delay(&self, socket_id: u16) -> Duration {
         match self.connections.get(&socket_id) {
             Some(connection) => connection.data_buffer.delay(),
@@ -4,6 +4,8 @@ use std::{
 };
 
 use crate::{
+    config::NeonConfig,
+    core::signal::NeonSignal,
     packet::{control::ack::Ack, data::DataPacket},
     utils::{MessageNumber, SequenceNumber, SequenceRange},
 };
@@ -12,6 +14,7 @@ use super::recv_list::RecvList;
 
 pub struct RecvQueue {
     list: Arc<RwLock<RecvList>>,
+    signal: Arc<NeonSignal>, //readers sleep on this until something changes
 }
 
 /*
@@ -34,17 +37,47 @@ impl Default for RecvQueue {
 impl RecvQueue {
     pub fn new() -> Self {
         let list = Arc::new(RwLock::new(RecvList::new()));
-        Self { list }
+        let signal = Arc::new(NeonSignal::new());
+        Self { list, signal }
+    }
+    pub fn signal(&self) -> Arc<NeonSignal> {
+        self.signal.clone()
+    }
+    //wake readers so they can notice the connection state changed
+    pub fn notify(&self) {
+        self.signal.notify()
     }
     pub fn register_connection(
         &self,
         socket_id: u16,
         self_isn: SequenceNumber,
+        config: &NeonConfig,
     ) {
-        if let Ok(mut binding) = self.list.write() { binding.register_connection(socket_id, self_isn) }
+        if let Ok(mut binding) = self.list.write() { binding.register_connection(socket_id, self_isn, config) }
     }
-    pub fn drop_msg(&self, socket_id: u16, msg_no: MessageNumber, range: SequenceRange) {
-        if let Ok(mut binding) = self.list.write() { binding.drop_msg(socket_id, msg_no, range) }
+    pub fn drop_msg(&self, socket_id: u16, channel: u16, msg_no: MessageNumber, ranges: &[SequenceRange]) {
+        if let Ok(mut binding) = self.list.write() { binding.drop_msg(socket_id, channel, msg_no, ranges) }
+        //a drop can unblock the messages behind it
+        self.signal.notify();
+    }
+
+    pub fn dropped(&self, socket_id: u16) -> usize {
+        match self.list.read() {
+            Ok(binding) => binding.dropped(socket_id),
+            Err(_) => 0,
+        }
+    }
+    pub fn shed(&self, socket_id: u16) -> usize {
+        match self.list.read() {
+            Ok(binding) => binding.shed(socket_id),
+            Err(_) => 0,
+        }
+    }
+    pub fn take_shed(&self, socket_id: u16) -> Vec<(u16, MessageNumber)> {
+        match self.list.write() {
+            Ok(mut binding) => binding.take_shed(socket_id),
+            Err(_) => vec![],
+        }
     }
 
     pub fn process_data(&mut self, packet: DataPacket, mss: u16) -> Vec<SequenceRange> {
@@ -53,6 +86,7 @@ impl RecvQueue {
                 let socket_id = packet.dst_socket_id;
                 //add the data
                 binding.add_data(packet);
+                self.signal.notify();
                 //trigger an up to date loss list
                 binding.report_loss(socket_id, mss)
             }
@@ -71,6 +105,12 @@ impl RecvQueue {
             Err(_) => None,
         }
     }
+    pub fn owes_ack(&self) -> bool {
+        match self.list.read() {
+            Ok(binding) => binding.owes_ack(),
+            Err(_) => false,
+        }
+    }
     pub fn sent_ack(&self, socket_id: u16, ack_no: SequenceNumber) {
         if let Ok(mut binding) = self.list.write() { binding.sent_ack(socket_id, ack_no) }
     }
@@ -82,7 +122,7 @@ impl RecvQueue {
                 Some((
                     rtt,
                     rtt_var,
-                    binding.buffer_size(socket_id),
+                    binding.free_buffer(socket_id),
                     binding.recv_speed(socket_id),
                     binding.bandwidth(socket_id),
                 ))
@@ -93,21 +133,64 @@ impl RecvQueue {
 
     pub fn remove(&mut self, socket_id: u16) {
         if let Ok(mut binding) = self.list.write() { binding.remove_connection(socket_id) }
+        self.signal.notify();
     }
-    pub fn read_data(&self, socket_id: u16) -> Option<Vec<u8>> {
+    pub fn read_data(&self, socket_id: u16, channel: u16) -> Option<Vec<u8>> {
         match self.list.write() {
-            Ok(mut binding) => binding.pop_data(socket_id),
+            Ok(mut binding) => binding.pop_data(socket_id, channel),
+            Err(_) => None,
+        }
+    }
+    //Err is the first message given up on since the last read
+    pub fn read_intact(&self, socket_id: u16, channel: u16) -> Result<Option<Vec<u8>>, MessageNumber> {
+        match self.list.write() {
+            Ok(mut binding) => binding.pop_intact(socket_id, channel),
+            Err(_) => Ok(None),
+        }
+    }
+    pub fn process_datagram(&self, socket_id: u16, channel: u16, data: Vec<u8>) {
+        if let Ok(mut binding) = self.list.write() {
+            binding.add_datagram(socket_id, channel, data)
+        }
+        self.signal.notify();
+    }
+    pub fn read_datagram(&self, socket_id: u16, channel: u16) -> Option<Vec<u8>> {
+        match self.list.write() {
+            Ok(mut binding) => binding.pop_datagram(socket_id, channel),
+            Err(_) => None,
+        }
+    }
+    pub fn open_channel(&self, socket_id: u16, channel: u16) -> bool {
+        match self.list.write() {
+            Ok(mut binding) => binding.open_channel(socket_id, channel),
+            Err(_) => false,
+        }
+    }
+    pub fn next_channel(&self, socket_id: u16) -> Option<u16> {
+        match self.list.write() {
+            Ok(mut binding) => binding.next_channel(socket_id),
             Err(_) => None,
         }
     }
     pub fn loss(&self, socket_id: u16, loss_ranges: Vec<SequenceRange>) {
         if let Ok(mut binding) = self.list.write() { binding.loss(socket_id, loss_ranges) }
     }
+    pub fn on_send(&self, socket_id: u16, seq_no: SequenceNumber) {
+        if let Ok(mut list) = self.list.write() {
+            list.on_send(socket_id, seq_no);
+        }
+    }
     pub fn ack_square(&mut self, socket_id: u16, ack_no: SequenceNumber) {
         if let Ok(mut list) = self.list.write() {
             list.ack_square(socket_id, ack_no);
         }
     }
+    pub fn congestion_window(&self, socket_id: u16) -> usize {
+        match self.list.read() {
+            Ok(list) => list.congestion_window(socket_id),
+            Err(_) => 0,
+        }
+    }
     pub fn delay(&self, socket_id: u16) -> Duration {
         match self.list.read() {
             Ok(list) => list.delay(socket_id),
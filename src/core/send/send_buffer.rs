@@ -1,28 +1,202 @@
 use std::{
-    collections::HashMap,
-    time::{Duration, SystemTime},
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{Error, ErrorKind},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    core::SYN_INTERVAL,
+    config::NeonConfig,
     packet::data::{DataPacket, DataPacketType},
     utils::{MessageNumber, SequenceNumber, SequenceRange},
 };
 
+const RECEIPT_LIMIT: usize = 8192; //finished receipts kept per connection, oldest go first
+const PRIORITIES: usize = 3;
+const WEIGHTS: [usize; PRIORITIES] = [4, 2, 1]; //fresh packets each class gets per round
+pub const NO_TTL: Duration = Duration::MAX;       //never expires, resent until acked or the connection goes
+
+//Which fresh messages go out first, retransmissions always go ahead of all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+//How the next fresh message is picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheduling {
+    #[default]
+    Weighted, //by priority, see WEIGHTS
+    Deadline, //earliest ttl first, anything that can't make it at the pacing rate is dropped up front
+}
+
+//Counts every message written on a channel, the low bits are its MessageNumber
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageId {
+    channel: u16,
+    count: u64,
+}
+
+impl MessageId {
+    pub fn channel(&self) -> u16 {
+        self.channel
+    }
+    pub fn msg_no(&self) -> MessageNumber {
+        MessageNumber::new(self.count as u32)
+    }
+    fn key(&self) -> (u16, MessageNumber) {
+        (self.channel, self.msg_no())
+    }
+}
+
+//How a single message is sent
+#[derive(Debug, Clone, Copy)]
+pub struct MessageOptions {
+    pub ttl: Duration,
+    pub order: bool,
+    pub channel: u16,
+    pub priority: Priority,
+}
+
+//What finally happened to a written message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Receipt {
+    Acked,              //every packet was acknowledged
+    Dropped(Duration),  //the ttl it was written with ran out first
+    Shed,               //the partner took it but threw it out unread to make room, see Overflow
+    ConnectionLost,     //the connection went away with it in flight
+}
+
+/*
+    Tracks messages from write till they are acked or dropped
+    Outcomes are kept (up to a limit) so they can be polled more than once
+*/
+#[derive(Debug, Default)]
+pub struct Receipts {
+    next_id: HashMap<u16, u64>, //per channel
+    in_flight: HashMap<(u16, MessageNumber), InFlight>,
+    done: HashMap<MessageId, Receipt>,
+    settled: VecDeque<MessageId>, //oldest first so the limit throws those away
+}
+
+#[derive(Debug)]
+struct InFlight {
+    id: MessageId,
+    ttl: Duration,
+    remaining: usize, //packets not yet acked
+}
+
+impl Receipts {
+    fn open(&mut self, channel: u16, ttl: Duration, packets: usize) -> MessageId {
+        let next_id = self.next_id.entry(channel).or_insert(MessageNumber::FIRST.0 as u64);
+        let id = MessageId {
+            channel,
+            count: *next_id,
+        };
+        *next_id += 1;
+        let in_flight = InFlight {
+            id,
+            ttl,
+            remaining: packets,
+        };
+        self.in_flight.insert(id.key(), in_flight);
+        id
+    }
+    fn acked(&mut self, key: (u16, MessageNumber)) {
+        if let Some(in_flight) = self.in_flight.get_mut(&key) {
+            in_flight.remaining = in_flight.remaining.saturating_sub(1);
+            if in_flight.remaining == 0 {
+                self.settle(key, Receipt::Acked);
+            }
+        }
+    }
+    //A bundle spread over several packets is only acked once all of them are
+    fn expect(&mut self, key: (u16, MessageNumber), packets: usize) {
+        if let Some(in_flight) = self.in_flight.get_mut(&key) {
+            in_flight.remaining = packets;
+        }
+    }
+    fn dropped(&mut self, key: (u16, MessageNumber)) {
+        if let Some(in_flight) = self.in_flight.get(&key) {
+            let ttl = in_flight.ttl;
+            self.settle(key, Receipt::Dropped(ttl));
+        }
+    }
+    //The partner may already have acked it, the last message with the key is the one it means
+    fn shed(&mut self, key: (u16, MessageNumber)) {
+        if self.in_flight.contains_key(&key) {
+            self.settle(key, Receipt::Shed);
+            return;
+        }
+        let id = self.settled.iter().rev().find(|id| id.key() == key).copied();
+        if let Some(id) = id {
+            self.done.insert(id, Receipt::Shed);
+        }
+    }
+    fn settle(&mut self, key: (u16, MessageNumber), receipt: Receipt) {
+        if let Some(in_flight) = self.in_flight.remove(&key) {
+            self.done.insert(in_flight.id, receipt);
+            self.settled.push_back(in_flight.id);
+            while self.settled.len() > RECEIPT_LIMIT {
+                if let Some(id) = self.settled.pop_front() {
+                    self.done.remove(&id);
+                }
+            }
+        }
+    }
+    //Everything still in flight is never going to be acked
+    pub fn lose(&mut self) {
+        let lost = self.in_flight.keys().copied().collect::<Vec<_>>();
+        lost.into_iter()
+            .for_each(|key| self.settle(key, Receipt::ConnectionLost));
+    }
+    //None while the message is in flight
+    pub fn get(&self, id: MessageId) -> Result<Option<Receipt>, Error> {
+        if let Some(receipt) = self.done.get(&id) {
+            return Ok(Some(*receipt));
+        }
+        match self.in_flight.get(&id.key()) {
+            Some(in_flight) if in_flight.id == id => Ok(None),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Unknown message")),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SendBuffer {
-    last_msg: MessageNumber, //the last msg no created
-    last_seq: SequenceNumber, //the last seq no created
+    last_msg: HashMap<u16, MessageNumber>, //the next msg no for each channel
+    last_seq: SequenceNumber, //the last seq no handed out, they're given when a packet is first sent
     last_ack: SequenceNumber, // the last ack recv
     last_ack_square: SequenceNumber, //the last ack square recv
     last_ack_time: SystemTime,
     last_ack_square_time: SystemTime,
-    blocks: HashMap<SequenceNumber, SendBlock>,
-    drops: HashMap<MessageNumber, Vec<SequenceNumber>>,
+    blocks: HashMap<SequenceNumber, SendBlock>, //sent and waiting on an ack
+    queued: [VecDeque<SendBlock>; PRIORITIES],   //not sent yet, one queue per priority
+    credits: [usize; PRIORITIES],                //what's left of each class's share this round
+    deadlines: BTreeMap<(SystemTime, u64), SendBlock>, //not sent yet under Deadline, soonest timeout then first written
+    writes: u64,  //packets queued so far, breaks ties between deadlines
+    reshed: bool, //something was queued or the pacing changed since the last shed
+    scheduling: Scheduling,
+    interval: Duration, //between packets at the current pacing rate
+    window: usize,      //packets allowed in flight, the smaller of the partner's free buffer and the congestion window
+    stalled: usize,     //reads the window turned away, each one was a scheduled send
+    drops: HashMap<(u16, MessageNumber), DroppedMessage>, //expired, kept till the partner acks past them
+    dropped: usize,                                //messages that expired before being acked
+    partner_shed: usize,                           //messages the partner threw out after taking them
+    receipts: Receipts,
+    syn_interval: Duration,
+    capacity: usize, //packets
+    byte_capacity: usize,
+    bytes: usize,  //held in blocks, queues and bundles
+    blocked: bool, //a write was turned away since the last time there was room
+    coalesce: Option<Duration>,
+    bundles: HashMap<u16, Bundle>, //per channel, waiting to fill up or for the delay to run out
 }
 impl SendBuffer {
-    pub fn new(self_isn: SequenceNumber) -> Self {
-        let last_msg = MessageNumber::ZERO;
+    pub fn new(self_isn: SequenceNumber, config: &NeonConfig) -> Self {
+        let last_msg = HashMap::new();
         let blocks = HashMap::new();
         let drops = HashMap::new();
         let last_seq = self_isn;
@@ -30,6 +204,9 @@ impl SendBuffer {
         let last_ack_square = last_ack;
         let last_ack_time = SystemTime::now();
         let last_ack_square_time = SystemTime::now();
+        let syn_interval = config.ack_interval;
+        let capacity = config.send_buffer;
+        let byte_capacity = config.send_bytes;
         Self {
             last_seq,
             last_msg,
@@ -38,18 +215,42 @@ impl SendBuffer {
             last_ack_time,
             last_ack_square_time,
             blocks,
+            queued: Default::default(),
+            credits: WEIGHTS,
+            deadlines: BTreeMap::new(),
+            writes: 0,
+            reshed: false,
+            scheduling: config.scheduling,
+            interval: Duration::ZERO,
+            window: config.congestion_window.min(config.flow_window as usize),
+            stalled: 0,
             drops,
+            dropped: 0,
+            partner_shed: 0,
+            receipts: Receipts::default(),
+            syn_interval,
+            capacity,
+            byte_capacity,
+            bytes: 0,
+            blocked: false,
+            coalesce: config.coalesce,
+            bundles: HashMap::new(),
         }
     }
+    fn next_msg(&mut self, channel: u16) -> MessageNumber {
+        let last_msg = self.last_msg.entry(channel).or_insert(MessageNumber::FIRST);
+        let msg_no = *last_msg;
+        last_msg.inc();
+        msg_no
+    }
     fn create_packets(
-        &mut self,
         data: &[u8],
         mss: u16,
         order: bool,
         partner_id: u16,
+        channel: u16,
+        msg_no: MessageNumber,
     ) -> Vec<DataPacket> {
-        let data = DataPacket::compress(data);
-
         //split up the data into chunks
         let count = data.chunks(mss as usize).count();
         if count == 0 {
@@ -57,19 +258,18 @@ impl SendBuffer {
         }
         let stamp = SystemTime::now();
 
+        //seq nos are filled in by read, priorities mean they don't go out in write order
         if count == 1 {
-            self.last_seq.inc();
-
             let packet = DataPacket::new(
-                self.last_seq,
-                self.last_msg,
+                SequenceNumber::ZERO,
+                msg_no,
                 DataPacketType::Solo,
                 order,
                 stamp,
                 partner_id,
                 data.to_vec(),
-            );
-            self.last_msg.inc();
+            )
+            .on_channel(channel);
 
             return vec![packet];
         }
@@ -77,8 +277,6 @@ impl SendBuffer {
             .chunks(mss as usize)
             .enumerate()
             .map(|(i, chunk)| {
-                self.last_seq.inc();
-
                 let element = if i == 0 {
                     DataPacketType::First
                 } else if i == count - 1 {
@@ -89,43 +287,206 @@ impl SendBuffer {
                 
 
                 DataPacket::new(
-                    self.last_seq,
-                    self.last_msg,
+                    SequenceNumber::ZERO,
+                    msg_no,
                     element,
                     order,
                     stamp,
                     partner_id,
                     chunk.to_vec(),
                 )
+                .on_channel(channel)
             })
             .collect::<Vec<_>>();
-        self.last_msg.inc();
         out
     }
 
     pub fn add(
         &mut self,
         data: &[u8],
-        ttl: Duration,
-        order: bool,
+        options: MessageOptions,
         partner_id: u16,
         mss: u16,
-    ) -> usize {
-        let packets = self.create_packets(data, mss, order, partner_id);
+    ) -> Result<(usize, MessageId), Error> {
+        let MessageOptions {
+            ttl,
+            order,
+            channel,
+            priority,
+        } = options;
+        //it would still compress to a byte count
+        if data.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Empty message"));
+        }
+        if self.coalesce.is_some() && data.len() + 2 <= mss as usize / 2 {
+            self.reserve(1, data.len() + 2)?;
+            return Ok(self.coalesce(data, options, partner_id, mss));
+        }
+        let data = DataPacket::compress(data);
+        //refuse the whole message rather than half of it
+        self.reserve(data.chunks(mss as usize).count(), data.len())?;
+        self.bytes += data.len();
+        //whatever is waiting on the channel was written first
+        let flushed = self.flush_bundle(channel);
+        let msg_no = self.next_msg(channel);
+        let packets = Self::create_packets(&data, mss, order, partner_id, channel, msg_no);
         let len = packets.len();
-        packets.into_iter().for_each(|packet| {
-            let seq_no = packet.seq_no;
-            let timeout = SystemTime::now() + ttl;
+        let id = self.receipts.open(channel, ttl, len);
+        //one timeout per message so it expires all at once
+        let timeout = expiry(SystemTime::now(), ttl);
+        for packet in packets {
             let send_block = SendBlock {
-                packet,
+                packet: packet.expiring(ttl != NO_TTL),
                 timeout,
                 state: BlockState::Fresh,
+                msgs: 1,
             };
-            self.blocks.insert(seq_no, send_block);
+            self.enqueue(priority, send_block);
+        }
+        Ok((flushed + len, id))
+    }
+    fn enqueue(&mut self, priority: Priority, block: SendBlock) {
+        match self.scheduling {
+            Scheduling::Weighted => self.queued[priority as usize].push_back(block),
+            Scheduling::Deadline => {
+                self.deadlines.insert((block.timeout, self.writes), block);
+                self.writes += 1;
+                self.reshed = true;
+            }
+        }
+    }
+
+    //Err when a message this size doesn't fit now, or never would
+    fn reserve(&mut self, packets: usize, bytes: usize) -> Result<(), Error> {
+        if packets > self.capacity || bytes > self.byte_capacity {
+            return Err(Error::new(ErrorKind::InvalidInput, "Message larger than the send buffer"));
+        }
+        if self.size() + packets > self.capacity || self.bytes + bytes > self.byte_capacity {
+            self.blocked = true;
+            return Err(Error::new(ErrorKind::WouldBlock, "Send buffer full"));
+        }
+        Ok(())
+    }
+    //Lowering them below what's held only holds off new writes till acks catch up
+    pub fn limit(&mut self, packets: usize, bytes: usize) {
+        self.capacity = packets;
+        self.byte_capacity = bytes;
+    }
+    //True once after a write was turned away, as soon as there's room again
+    pub fn writable(&mut self) -> bool {
+        let room = self.size() < self.capacity && self.bytes < self.byte_capacity;
+        if self.blocked && room {
+            self.blocked = false;
+            return true;
+        }
+        false
+    }
+
+    /*
+        Adds a small message to its channel's bundle, the bundle is flushed first if it can't take it
+        A bundle expires as a whole with the shortest ttl in it
+    */
+    fn coalesce(
+        &mut self,
+        data: &[u8],
+        options: MessageOptions,
+        partner_id: u16,
+        mss: u16,
+    ) -> (usize, MessageId) {
+        let MessageOptions {
+            ttl,
+            order,
+            channel,
+            priority,
+        } = options;
+        let fits = match self.bundles.get(&channel) {
+            Some(bundle) => {
+                bundle.order == order
+                    && bundle.priority == priority
+                    && bundle.data.len() + data.len() + 2 <= mss as usize
+            }
+            None => true,
+        };
+        let flushed = match fits {
+            true => 0,
+            false => self.flush_bundle(channel),
+        };
+        let msg_no = self.next_msg(channel);
+        let id = self.receipts.open(channel, ttl, 1);
+        let now = SystemTime::now();
+        let bundle = self.bundles.entry(channel).or_insert(Bundle {
+            msg_no,
+            count: 0,
+            order,
+            priority,
+            partner_id,
+            mss,
+            data: Vec::new(),
+            started: now,
+            timeout: expiry(now, ttl),
+            expires: false,
         });
+        DataPacket::pack(&mut bundle.data, data);
+        self.bytes += data.len() + 2;
+        bundle.count += 1;
+        bundle.timeout = bundle.timeout.min(expiry(now, ttl));
+        bundle.expires |= ttl != NO_TTL;
+        (flushed, id)
+    }
+
+    //Queues a channel's bundle as a message of its own, returns the packets queued
+    fn flush_bundle(&mut self, channel: u16) -> usize {
+        let bundle = match self.bundles.remove(&channel) {
+            Some(bundle) => bundle,
+            None => return 0,
+        };
+        let data = DataPacket::compress(&bundle.data);
+        self.bytes = self.bytes - bundle.data.len() + data.len();
+        let packets = Self::create_packets(
+            &data,
+            bundle.mss,
+            bundle.order,
+            bundle.partner_id,
+            channel,
+            bundle.msg_no,
+        );
+        let len = packets.len();
+        let mut msg_no = bundle.msg_no;
+        (0..bundle.count).for_each(|_| {
+            self.receipts.expect((channel, msg_no), len);
+            msg_no.inc();
+        });
+        for packet in packets {
+            let send_block = SendBlock {
+                packet: packet.bundled().expiring(bundle.expires),
+                timeout: bundle.timeout,
+                state: BlockState::Fresh,
+                msgs: bundle.count,
+            };
+            self.enqueue(bundle.priority, send_block);
+        }
         len
     }
 
+    fn flush_due(&mut self) {
+        let delay = match self.coalesce {
+            Some(delay) => delay,
+            None => return,
+        };
+        let due = self
+            .bundles
+            .iter()
+            .filter(|(_, bundle)| match bundle.started.elapsed() {
+                Ok(waited) => waited >= delay,
+                Err(_) => false,
+            })
+            .map(|(channel, _)| *channel)
+            .collect::<Vec<_>>();
+        due.into_iter().for_each(|channel| {
+            self.flush_bundle(channel);
+        });
+    }
+
     fn manage_drops(&mut self) {
         let (keeps, drops): (Vec<_>, Vec<_>) =
             self.blocks
@@ -133,12 +494,21 @@ impl SendBuffer {
                 .into_iter()
                 .partition(|(_, block)| block.timeout.elapsed().is_err());
         drops.into_iter().for_each(|(seq_no, block)| {
-            match self.drops.get_mut(&block.packet.msg_no) {
+            self.bytes -= block.packet.data.len();
+            let key = (block.packet.channel, block.packet.msg_no);
+            match self.drops.get_mut(&key) {
                 Some(dropped_msg) => {
-                    dropped_msg.push(seq_no);
+                    dropped_msg.seqs.push(seq_no);
                 }
                 None => {
-                    self.drops.insert(block.packet.msg_no, vec![seq_no]);
+                    self.dropped += block.msgs as usize;
+                    block.for_each_msg(|key| self.receipts.dropped(key));
+                    let dropped_msg = DroppedMessage {
+                        seqs: vec![seq_no],
+                        msgs: block.msgs,
+                        sent: None,
+                    };
+                    self.drops.insert(key, dropped_msg);
                 }
             }
         });
@@ -146,34 +516,145 @@ impl SendBuffer {
         keeps.into_iter().for_each(|(seq_no, block)| {
             self.blocks.insert(seq_no, block);
         });
+        let mut expired = Vec::new();
+        let mut bytes = 0;
+        self.queued.iter_mut().for_each(|queue| {
+            queue.retain(|block| {
+                let keep = block.timeout.elapsed().is_err();
+                if !keep {
+                    bytes += block.packet.data.len();
+                    expired.push(block.clone());
+                }
+                keep
+            })
+        });
+        //the deadlines are in timeout order, what's expired is at the front
+        let now = SystemTime::now();
+        while let Some(entry) = self.deadlines.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let block = entry.remove();
+            bytes += block.packet.data.len();
+            expired.push(block);
+        }
+        self.bytes -= bytes;
+        expired.into_iter().for_each(|block| {
+            let key = (block.packet.channel, block.packet.msg_no);
+            if self.drops.contains_key(&key) {
+                return;
+            }
+            //never went out, spend a seq on it so the drop gets acked past like any other
+            self.last_seq.inc();
+            self.dropped += block.msgs as usize;
+            block.for_each_msg(|key| self.receipts.dropped(key));
+            let dropped_msg = DroppedMessage {
+                seqs: vec![self.last_seq],
+                msgs: block.msgs,
+                sent: None,
+            };
+            self.drops.insert(key, dropped_msg);
+        });
+    }
+
+    /*
+        Weighted round robin over the classes with something queued
+        Each class gets its weight in packets per round so low never starves
+    */
+    fn next_class(&mut self) -> Option<usize> {
+        let waiting = (0..PRIORITIES)
+            .filter(|class| !self.queued[*class].is_empty())
+            .collect::<Vec<_>>();
+        if waiting.is_empty() {
+            return None;
+        }
+        if waiting.iter().all(|class| self.credits[*class] == 0) {
+            self.credits = WEIGHTS;
+        }
+        let class = *waiting.iter().find(|class| self.credits[**class] > 0)?;
+        self.credits[class] -= 1;
+        Some(class)
+    }
+
+    /*
+        Lays the queued messages out by deadline as if they went back to back at the pacing rate
+        Anything that would land late is expired now instead of going out stale
+        The layout holds while packets leave at that rate so it's only redone when it changes
+    */
+    fn shed(&mut self) {
+        if !std::mem::take(&mut self.reshed) {
+            return;
+        }
+        //a message's packets were queued together so they sit side by side
+        let mut messages: Vec<(SystemTime, Vec<(SystemTime, u64)>)> = Vec::new();
+        let mut last = None;
+        for (key, block) in &self.deadlines {
+            let msg = Some((block.packet.channel, block.packet.msg_no));
+            match messages.last_mut() {
+                Some((_, keys)) if last == msg => keys.push(*key),
+                _ => messages.push((block.timeout, vec![*key])),
+            }
+            last = msg;
+        }
+        let now = SystemTime::now();
+        let mut finish = now;
+        let late = messages
+            .into_iter()
+            .filter(|(timeout, keys)| {
+                let done = finish + self.interval * keys.len() as u32;
+                if done > *timeout {
+                    true
+                } else {
+                    finish = done;
+                    false
+                }
+            })
+            .flat_map(|(_, keys)| keys)
+            .collect::<Vec<_>>();
+        for key in late {
+            if let Some(mut block) = self.deadlines.remove(&key) {
+                block.timeout = now;
+                self.deadlines.insert((now, key.1), block);
+            }
+        }
     }
 
     pub fn read(&mut self) -> Option<DataPacket> {
+        self.flush_due();
+        self.shed();
         self.manage_drops();
-        //find the first unsent element, mark it as seen
-        let mut fresh_blocks = self
-            .blocks
-            .iter_mut()
-            .filter(|(_, block)| block.state == BlockState::Fresh)
-            .collect::<Vec<_>>();
-        fresh_blocks.sort_by(|a, b| a.0.cmp(b.0));
-        match fresh_blocks.first_mut() {
-            Some((_, block)) => {
-                let out = block.packet.clone();
-                block.state = BlockState::Read;
-                Some(out)
+        //one packet still goes out into a shut window, its ack is how we hear it opened
+        if self.blocks.len() >= self.window.max(1) {
+            if self.queued.iter().any(|queue| !queue.is_empty()) || !self.deadlines.is_empty() {
+                self.stalled += 1;
             }
-            None => None,
+            return None;
         }
+        //take the next unsent element, give it a seq and mark it as seen
+        let mut block = match self.scheduling {
+            Scheduling::Weighted => {
+                let class = self.next_class()?;
+                self.queued[class].pop_front()?
+            }
+            Scheduling::Deadline => self.deadlines.pop_first()?.1,
+        };
+        self.last_seq.inc();
+        block.packet.seq_no = self.last_seq;
+        block.state = BlockState::Read;
+        let out = block.packet.clone();
+        self.blocks.insert(self.last_seq, block);
+        Some(out)
     }
 
     /* */
-    pub fn read_recall(&mut self, msg_no: MessageNumber) -> Option<DataPacket> {
+    pub fn read_recall(&mut self, channel: u16, msg_no: MessageNumber) -> Option<DataPacket> {
         self.manage_drops();
         self
             .blocks
-            .iter()
-            .find(|(_, block)| block.packet.msg_no == msg_no).map(|(_, block)| block.packet.clone())
+            .values()
+            .chain(self.queued.iter().flatten())
+            .chain(self.deadlines.values())
+            .find(|block| block.packet.channel == channel && block.packet.msg_no == msg_no).map(|block| block.packet.clone())
     }
     pub fn search(&mut self, range: SequenceRange) -> Option<DataPacket> {
         
@@ -181,24 +662,28 @@ impl SendBuffer {
             .blocks
             .iter()
             .filter(|(seq_no, _)| range.contains(**seq_no)).map(|(seq_no, block)| (*seq_no,block.packet.clone())).collect::<Vec<_>>();
-        let first = candidates.iter().fold((SequenceNumber::MAX_SEQ_NO, None), |acc, c|{
-            if c.0<acc.0{
-                (c.0, Some(c.1.clone()))
-            }else{
-                acc
-            }
-        });
-        first.1
+        candidates
+            .into_iter()
+            .min_by_key(|(seq_no, _)| seq_no.diff(range.start))
+            .map(|(_, packet)| packet)
     }
     pub fn ack(&mut self, ack_no: SequenceNumber) -> bool {
         //Remove blocks and drops from before this ack number->problem because ack is wrong (TODO)
-        self.blocks
-            .retain(|seq_no, block| *seq_no > ack_no || block.state == BlockState::Fresh);
+        let receipts = &mut self.receipts;
+        let bytes = &mut self.bytes;
+        self.blocks.retain(|seq_no, block| {
+            let keep = ack_no.serial_lt(*seq_no);
+            if !keep {
+                *bytes -= block.packet.data.len();
+                block.for_each_msg(|key| receipts.acked(key));
+            }
+            keep
+        });
         self.drops
-            .retain(|_, seqs| seqs.iter().any(|seq| *seq > ack_no));
+            .retain(|_, dropped_msg| dropped_msg.seqs.iter().any(|seq| ack_no.serial_lt(*seq)));
         match self.last_ack_time.elapsed() {
             Ok(time) => {
-                if time > SYN_INTERVAL || ack_no == self.last_ack {
+                if time > self.syn_interval || ack_no == self.last_ack {
                     self.last_ack = ack_no;
                     self.last_ack_time = SystemTime::now();
                     true
@@ -215,15 +700,75 @@ impl SendBuffer {
         self.last_ack_square_time = SystemTime::now();
     }
 
-    //To clean up when ready
-    pub fn release_drops(&mut self) -> HashMap<MessageNumber, Vec<SequenceNumber>> {
-        let out = self.drops.clone();
-        self.drops.clear();
-        out
+    /*
+        Expired messages that need a drop sent, either new or not acked past since the last one
+        They stay around until an ack covers them in case the drop gets lost
+        A bundle gets one drop per message in it
+    */
+    pub fn release_drops(&mut self, resend: Duration) -> Vec<(u16, MessageNumber, Vec<SequenceRange>)> {
+        self.manage_drops();
+        self.drops
+            .iter_mut()
+            .filter(|(_, dropped_msg)| match dropped_msg.sent {
+                Some(sent) => match sent.elapsed() {
+                    Ok(elapsed) => elapsed > resend,
+                    Err(_) => false,
+                },
+                None => true,
+            })
+            .map(|((channel, msg_no), dropped_msg)| {
+                dropped_msg.sent = Some(SystemTime::now());
+                //only the seqs it went out on, other messages cut in between them
+                let ranges = SequenceRange::runs(&dropped_msg.seqs);
+                (*channel, *msg_no, dropped_msg.msgs, ranges)
+            })
+            .flat_map(|(channel, msg_no, msgs, ranges)| {
+                let mut msg_no = msg_no;
+                (0..msgs)
+                    .map(|_| {
+                        let out = (channel, msg_no, ranges.clone());
+                        msg_no.inc();
+                        out
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+    //The partner acked the message then threw it out unread
+    pub fn on_shed(&mut self, channel: u16, msg_no: MessageNumber) {
+        self.partner_shed += 1;
+        self.receipts.shed((channel, msg_no));
+    }
+    pub fn partner_shed(&self) -> usize {
+        self.partner_shed
+    }
+    //Returns the sends that were turned away and now have room, they need scheduling again
+    pub fn window(&mut self, packets: usize) -> usize {
+        self.window = packets;
+        if self.blocks.len() >= self.window.max(1) {
+            return 0;
+        }
+        std::mem::take(&mut self.stalled)
+    }
+    pub fn pace(&mut self, interval: Duration) {
+        self.reshed |= interval != self.interval && !self.deadlines.is_empty();
+        self.interval = interval;
+    }
+    pub fn receipt(&self, id: MessageId) -> Result<Option<Receipt>, Error> {
+        self.receipts.get(id)
+    }
+    pub fn into_receipts(self) -> Receipts {
+        self.receipts
     }
 
     pub fn size(&self) -> usize {
         self.blocks.len()
+            + self.queued.iter().map(|queue| queue.len()).sum::<usize>()
+            + self.deadlines.len()
+            + self.bundles.len()
     }
     pub fn last_seq(&self) -> SequenceNumber {
         self.last_seq
@@ -243,11 +788,49 @@ impl SendBuffer {
     }
 }
 
+//When a message written now with this ttl expires, one too long to add like NO_TTL is put out of reach
+fn expiry(now: SystemTime, ttl: Duration) -> SystemTime {
+    now.checked_add(ttl)
+        .unwrap_or(UNIX_EPOCH + Duration::from_secs(u32::MAX as u64) * 64)
+}
+
+#[derive(Debug, Clone)]
+pub struct DroppedMessage {
+    seqs: Vec<SequenceNumber>,
+    msgs: u16, //more than one for a bundle
+    sent: Option<SystemTime>, //when the last drop went out
+}
+
 #[derive(Debug, Clone)]
 pub struct SendBlock {
     packet: DataPacket,
     timeout: SystemTime,
     state: BlockState,
+    msgs: u16, //messages packed in, more than one for a bundle
+}
+
+impl SendBlock {
+    fn for_each_msg<F: FnMut((u16, MessageNumber))>(&self, mut f: F) {
+        let mut msg_no = self.packet.msg_no;
+        (0..self.msgs).for_each(|_| {
+            f((self.packet.channel, msg_no));
+            msg_no.inc();
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Bundle {
+    msg_no: MessageNumber, //of the first message, the rest follow on
+    count: u16,
+    order: bool,
+    priority: Priority,
+    partner_id: u16,
+    mss: u16,
+    data: Vec<u8>, //packed but not compressed
+    started: SystemTime,
+    timeout: SystemTime, //the soonest of its messages
+    expires: bool,       //any of its messages has a ttl, so the whole bundle can go
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
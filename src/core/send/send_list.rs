@@ -1,26 +1,39 @@
 use std::{
-    collections::{BinaryHeap, HashMap},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
+    io::{Error, ErrorKind},
     sync::{Arc, Condvar, Mutex},
     time::{Duration, SystemTime},
 };
 
 use crate::{
+    config::{NeonConfig, DATAGRAM_BACKLOG},
     core::loss_list::LossBuffer,
     packet::Packet,
-    utils::{MessageNumber, SequenceNumber, SequenceRange},
+    utils::{self, MessageNumber, SequenceNumber, SequenceRange},
 };
 
-use super::{send_buffer::SendBuffer, send_queue::NeonPoll};
+//Called without any locks held once a connection that turned a write away has room again
+pub type WritableHook = Arc<dyn Fn() + Send + Sync>;
+
+use super::{
+    send_buffer::{MessageId, MessageOptions, Receipt, Receipts, SendBuffer},
+    send_queue::NeonPoll,
+};
 
 pub struct SendList {
     connections: HashMap<u16, SendBacker>,
+    closed: HashMap<u16, Receipts>, //receipts outlive the connection till the socket id is reused
+    hooks: HashMap<u16, WritableHook>,
     poll: Arc<NeonPoll>,
 }
 #[derive(Debug)]
 struct SendBacker {
     data_buffer: SendBuffer,
     loss_buffer: LossBuffer,
-    updates: BinaryHeap<SystemTime>,
+    datagrams: VecDeque<Packet>, //unreliable, sent once and forgotten
+    updates: BinaryHeap<Reverse<SystemTime>>, //soonest first
+    epoch: SystemTime,
 }
 
 impl Default for SendList {
@@ -35,46 +48,122 @@ impl SendList {
         let sockets = Arc::new(Mutex::new(Vec::new()));
         let cond = Condvar::new();
         let poll = Arc::new(NeonPoll{cond,sockets});
-        Self { connections, poll }
+        let closed = HashMap::new();
+        Self {
+            connections,
+            closed,
+            hooks: HashMap::new(),
+            poll,
+        }
     }
-    pub fn register_connection(&mut self, socket_id: u16, self_isn: SequenceNumber) {
-        let data_buffer = SendBuffer::new(self_isn);
+    pub fn register_connection(
+        &mut self,
+        socket_id: u16,
+        self_isn: SequenceNumber,
+        config: &NeonConfig,
+        flow_window: u16, //the partner's, from its handshake
+        epoch: SystemTime, //the connection's start, packets are timestamped from it
+    ) {
+        let mut data_buffer = SendBuffer::new(self_isn, config);
+        data_buffer.window(config.congestion_window.min(flow_window as usize));
         let loss_buffer = LossBuffer::new();
         let updates = BinaryHeap::new();
+        let datagrams = VecDeque::new();
         let backer = SendBacker {
             data_buffer,
             loss_buffer,
+            datagrams,
             updates,
+            epoch,
         };
         self.connections.insert(socket_id, backer);
+        self.closed.remove(&socket_id);
     }
     pub fn remove_connection(&mut self, socket_id: u16) {
-        self.connections.remove(&socket_id);
+        self.hooks.remove(&socket_id);
+        if let Some(connection) = self.connections.remove(&socket_id) {
+            let mut receipts = connection.data_buffer.into_receipts();
+            receipts.lose();
+            self.closed.insert(socket_id, receipts);
+        }
     }
     //Wrapper for send buffer add
     pub fn insert(
         &mut self,
         socket_id: u16,
         data: &[u8],
-        ttl: Duration,
-        order: bool,
+        options: MessageOptions,
         partner_id: u16,
         mss: u16,
-    ) -> usize {
+    ) -> Result<(usize, MessageId), Error> {
         match self.connections.get_mut(&socket_id) {
             Some(connection) => connection
                 .data_buffer
-                .add(data, ttl, order, partner_id, mss),
+                .add(data, options, partner_id, mss),
+            None => Err(Error::new(ErrorKind::NotConnected, "Unknown socket")),
+        }
+    }
+    pub fn limit(&mut self, socket_id: u16, packets: usize, bytes: usize) -> Result<(), Error> {
+        match self.connections.get_mut(&socket_id) {
+            Some(connection) => {
+                connection.data_buffer.limit(packets, bytes);
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::NotConnected, "Unknown socket")),
+        }
+    }
+    //The ack is the clock, what the window held back goes straight away
+    pub fn window(&mut self, socket_id: u16, packets: usize) {
+        let resumed = match self.connections.get_mut(&socket_id) {
+            Some(connection) => connection.data_buffer.window(packets),
             None => 0,
+        };
+        self.schedule(socket_id, resumed, Duration::ZERO);
+    }
+    pub fn on_writable(&mut self, socket_id: u16, hook: WritableHook) -> Result<(), Error> {
+        if !self.connections.contains_key(&socket_id) {
+            return Err(Error::new(ErrorKind::NotConnected, "Unknown socket"));
+        }
+        self.hooks.insert(socket_id, hook);
+        Ok(())
+    }
+    //Hooks for the connections that just got room, the caller runs them after letting go of the core
+    pub fn writable(&mut self) -> Vec<WritableHook> {
+        let hooks = &self.hooks;
+        self.connections
+            .iter_mut()
+            .filter_map(|(socket_id, connection)| match connection.data_buffer.writable() {
+                true => hooks.get(socket_id).cloned(),
+                false => None,
+            })
+            .collect()
+    }
+    pub fn insert_datagram(&mut self, socket_id: u16, packet: Packet) -> Result<(), Error> {
+        match self.connections.get_mut(&socket_id) {
+            Some(connection) => {
+                //only the newest matter so make room rather than refuse
+                if connection.datagrams.len() >= DATAGRAM_BACKLOG {
+                    connection.datagrams.pop_front();
+                }
+                connection.datagrams.push_back(packet);
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::NotConnected, "Unknown socket")),
         }
     }
     //Wrapper for updating time
     pub fn update(&mut self, socket_id: u16, cnt: usize, delay: Duration) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.data_buffer.pace(delay);
+        }
+        self.schedule(socket_id, cnt, delay);
+    }
+    fn schedule(&mut self, socket_id: u16, cnt: usize, delay: Duration) {
         if let Some(connection) = self.connections.get_mut(&socket_id) {
             (0..cnt).for_each(|i| {
                 connection
                     .updates
-                    .push(SystemTime::now() + delay * (i + 1) as u32);
+                    .push(Reverse(SystemTime::now() + delay * (i + 1) as u32));
             });
             //This socket is now waiting to be worked
             if let Ok(mut waiting) = self.poll.sockets.lock() {
@@ -83,14 +172,59 @@ impl SendList {
             self.poll.cond.notify_all();
         }
     }
-    pub fn drops(&mut self, socket_id: u16) -> HashMap<MessageNumber, Vec<SequenceNumber>> {
+    pub fn drops(&mut self, socket_id: u16, resend: Duration) -> Vec<(u16, MessageNumber, Vec<SequenceRange>)> {
         match self.connections.get_mut(&socket_id) {
-            Some(connection) => connection.data_buffer.release_drops(),
-            None => HashMap::new(),
+            Some(connection) => {
+                let drops = connection.data_buffer.release_drops(resend);
+                //stop resending anything that was asked for
+                drops
+                    .iter()
+                    .flat_map(|(_, _, ranges)| ranges.iter())
+                    .for_each(|range| connection.loss_buffer.remove_range(*range));
+                drops
+            }
+            None => vec![],
+        }
+    }
+    pub fn receipt(&self, socket_id: u16, id: MessageId) -> Result<Option<Receipt>, Error> {
+        match self.connections.get(&socket_id) {
+            Some(connection) => connection.data_buffer.receipt(id),
+            None => match self.closed.get(&socket_id) {
+                Some(receipts) => receipts.get(id),
+                None => Err(Error::new(ErrorKind::NotConnected, "Unknown socket")),
+            },
+        }
+    }
+    pub fn dropped(&self, socket_id: u16) -> usize {
+        self.connections
+            .get(&socket_id)
+            .map_or(0, |connection| connection.data_buffer.dropped())
+    }
+    pub fn shed(&mut self, socket_id: u16, channel: u16, msg_no: MessageNumber) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.data_buffer.on_shed(channel, msg_no);
         }
     }
+    pub fn partner_shed(&self, socket_id: u16) -> usize {
+        self.connections
+            .get(&socket_id)
+            .map_or(0, |connection| connection.data_buffer.partner_shed())
+    }
 
+    //The connection restarted its clock, what goes out from now is timestamped from there
+    pub fn resync(&mut self, socket_id: u16, epoch: SystemTime) {
+        if let Some(connection) = self.connections.get_mut(&socket_id) {
+            connection.epoch = epoch;
+        }
+    }
     pub fn pop(&mut self, socket_id: u16) -> Option<Packet> {
+        let connection = self.connections.get(&socket_id)?;
+        let timestamp = utils::timestamp(connection.epoch);
+        let mut packet = self.next(socket_id)?;
+        packet.set_timestamp(timestamp);
+        Some(packet)
+    }
+    fn next(&mut self, socket_id: u16) -> Option<Packet> {
         match self.connections.get_mut(&socket_id) {
             Some(connection) => {
                 while let Some(loss) = connection.loss_buffer.first() {
@@ -104,6 +238,10 @@ impl SendList {
                         }
                     }
                 }
+                //datagrams go ahead of new data, they're stale if they wait
+                if let Some(packet) = connection.datagrams.pop_front() {
+                    return Some(packet);
+                }
                 connection.data_buffer.read().map(Packet::Data)
             }
             None => None,
@@ -112,20 +250,21 @@ impl SendList {
 
     pub fn loss(&mut self, socket_id: u16, loss: SequenceRange) {
         if let Some(connection) = self.connections.get_mut(&socket_id) {
-            if loss.start < loss.stop && loss.stop < connection.data_buffer.last_seq() {
+            //a single packet comes as start == stop
+            if loss.start.serial_le(loss.stop) && loss.stop.serial_le(connection.data_buffer.last_seq()) {
                 connection.loss_buffer.insert(loss);
             }
         }
     }
     pub fn out_of_sequence(&mut self, socket_id: u16, ack_no: SequenceNumber) -> bool {
         match self.connections.get_mut(&socket_id) {
-            Some(connection) => connection.data_buffer.last_seq() < ack_no,
+            Some(connection) => connection.data_buffer.last_seq().serial_lt(ack_no),
             None => false,
         }
     }
     pub fn out_of_sequence_square(&mut self, socket_id: u16, ack_no: SequenceNumber) -> bool {
         match self.connections.get_mut(&socket_id) {
-            Some(connection) => connection.data_buffer.last_ack() < ack_no,
+            Some(connection) => connection.data_buffer.last_ack().serial_lt(ack_no),
             None => false,
         }
     }
@@ -157,7 +296,7 @@ impl SendList {
             .iter()
             .fold(None, |acc, (c_sock, c_conn)| match acc {
                 Some((_, acc_delay)) => match c_conn.updates.peek() {
-                    Some(time) => match time.duration_since(SystemTime::now()) {
+                    Some(Reverse(time)) => match time.duration_since(SystemTime::now()) {
                         Ok(delay) => {
                             if delay < acc_delay {
                                 Some((*c_sock, delay))
@@ -170,7 +309,7 @@ impl SendList {
                     None => acc,
                 },
                 None => match c_conn.updates.peek() {
-                    Some(time) => match time.duration_since(SystemTime::now()) {
+                    Some(Reverse(time)) => match time.duration_since(SystemTime::now()) {
                         Ok(delay) => Some((*c_sock, delay)),
                         Err(_) => Some((*c_sock, Duration::ZERO)),
                     },
@@ -198,7 +337,7 @@ impl SendList {
                     }
                 };
                 match connection.updates.pop() {
-                    Some(time) => match time.duration_since(SystemTime::now()) {
+                    Some(Reverse(time)) => match time.duration_since(SystemTime::now()) {
                         Ok(delay) => Some(delay),
                         Err(_) => None,
                     },
@@ -214,6 +353,12 @@ impl SendList {
             .map(|connection| connection.data_buffer.last_seq())
     }
 
+    pub fn size(&self, socket_id: u16) -> usize {
+        self.connections
+            .get(&socket_id)
+            .map_or(0, |connection| connection.data_buffer.size())
+    }
+
     pub fn keep_alive(&mut self, socket_id: u16) -> bool {
         match self.connections.get_mut(&socket_id) {
             Some(connection) => match connection.data_buffer.keep_alive() {
@@ -222,7 +367,7 @@ impl SendList {
                     if let Ok(mut ws) = self.poll.sockets.lock() {
                         ws.push(socket_id);
                     }
-                    connection.updates.push(SystemTime::now());
+                    connection.updates.push(Reverse(SystemTime::now()));
                     self.poll.cond.notify_all();
                     false
                 }
@@ -1,17 +1,22 @@
 use std::{
-    io::{Error, ErrorKind}, net::SocketAddr, sync::{Arc, Condvar, Mutex, RwLock}, time::Duration
+    io::{Error, ErrorKind}, net::SocketAddr, sync::{Arc, Condvar, Mutex, RwLock}, time::{Duration, SystemTime}
 };
 
 use crate::{
-    core::channel::NeonChannel,
+    config::NeonConfig,
+    core::{channel::NeonChannel, signal::NeonSignal},
     packet::Packet,
-    utils::{SequenceNumber, SequenceRange},
+    utils::{MessageNumber, SequenceNumber, SequenceRange},
 };
 
-use super::send_list::SendList;
+use super::{
+    send_buffer::{MessageId, MessageOptions, Receipt},
+    send_list::{SendList, WritableHook},
+};
 
 pub struct SendQueue {
     list: Arc<RwLock<SendList>>,
+    signal: Arc<NeonSignal>, //anyone waiting on acks sleeps on this
 }
 
 /*
@@ -31,28 +36,66 @@ impl Default for SendQueue {
 impl SendQueue {
     pub fn new() -> Self {
         let list = Arc::new(RwLock::new(SendList::new()));
-        Self { list }
+        let signal = Arc::new(NeonSignal::new());
+        Self { list, signal }
+    }
+    pub fn signal(&self) -> Arc<NeonSignal> {
+        self.signal.clone()
+    }
+    pub fn notify(&self) {
+        self.signal.notify()
     }
     pub fn register_connection(
         &self,
         socket_id: u16,
         self_isn: SequenceNumber,
+        config: &NeonConfig,
+        flow_window: u16,
+        epoch: SystemTime,
     ) {
-        if let Ok(mut binding) = self.list.write() { binding.register_connection(socket_id, self_isn) }
+        if let Ok(mut binding) = self.list.write() {
+            binding.register_connection(socket_id, self_isn, config, flow_window, epoch)
+        }
     }
 
     pub fn push_data(
         &mut self,
         socket_id: u16,
         data: &[u8],
-        ttl: Duration,
-        order: bool,
+        options: MessageOptions,
         partner_id: u16,
         mss: u16,
-    ) -> usize {
+    ) -> Result<(usize, MessageId), Error> {
         match self.list.write() {
-            Ok(mut binding) => binding.insert(socket_id, data, ttl, order, partner_id, mss),
-            Err(_) => 0,
+            Ok(mut binding) => binding.insert(socket_id, data, options, partner_id, mss),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned lock")),
+        }
+    }
+    pub fn limit(&self, socket_id: u16, packets: usize, bytes: usize) -> Result<(), Error> {
+        let out = match self.list.write() {
+            Ok(mut binding) => binding.limit(socket_id, packets, bytes),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned lock")),
+        };
+        //a bigger buffer may let a blocked write in
+        self.signal.notify();
+        out
+    }
+    pub fn on_writable(&self, socket_id: u16, hook: WritableHook) -> Result<(), Error> {
+        match self.list.write() {
+            Ok(mut binding) => binding.on_writable(socket_id, hook),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned lock")),
+        }
+    }
+    pub fn writable(&self) -> Vec<WritableHook> {
+        match self.list.write() {
+            Ok(mut binding) => binding.writable(),
+            Err(_) => vec![],
+        }
+    }
+    pub fn push_datagram(&mut self, socket_id: u16, packet: Packet) -> Result<(), Error> {
+        match self.list.write() {
+            Ok(mut binding) => binding.insert_datagram(socket_id, packet),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned lock")),
         }
     }
     pub fn send_packet(&self, channel: &NeonChannel, addr: SocketAddr, packet: Packet)->Result<usize, Error> {
@@ -82,6 +125,7 @@ impl SendQueue {
             Ok(mut list) => {
                 if !list.out_of_sequence(socket_id, ack_no) {
                     list.remove_confirmed(socket_id, ack_no);
+                    self.signal.notify();
                     list.ack(socket_id, ack_no)
                 } else {
                     false
@@ -90,6 +134,16 @@ impl SendQueue {
             Err(_) => false,
         }
     }
+    pub fn window(&mut self, socket_id: u16, packets: usize) {
+        if let Ok(mut list) = self.list.write() {
+            list.window(socket_id, packets)
+        }
+    }
+    pub fn resync(&self, socket_id: u16, epoch: SystemTime) {
+        if let Ok(mut list) = self.list.write() {
+            list.resync(socket_id, epoch)
+        }
+    }
     pub fn ack_square(&mut self, socket_id: u16, ack_no: SequenceNumber) {
         if let Ok(mut list) = self.list.write() {
             if !list.out_of_sequence_square(socket_id, ack_no) {
@@ -103,6 +157,7 @@ impl SendQueue {
 
     pub fn remove(&mut self, socket_id: u16) {
         if let Ok(mut binding) = self.list.write() { binding.remove_connection(socket_id) }
+        self.signal.notify();
     }
     pub fn last_seq(&self, socket_id: u16)->Option<SequenceNumber>{
         match self.list.read() {
@@ -110,6 +165,49 @@ impl SendQueue {
             Err(_) => None
         }
     }
+    //expired messages the partner should be told about
+    pub fn drops(&self, socket_id: u16, resend: Duration) -> Vec<(u16, MessageNumber, Vec<SequenceRange>)> {
+        let drops = match self.list.write() {
+            Ok(mut binding) => binding.drops(socket_id, resend),
+            Err(_) => vec![],
+        };
+        //expired messages free up the buffer same as an ack
+        if !drops.is_empty() {
+            self.signal.notify();
+        }
+        drops
+    }
+    pub fn receipt(&self, socket_id: u16, id: MessageId) -> Result<Option<Receipt>, Error> {
+        match self.list.read() {
+            Ok(binding) => binding.receipt(socket_id, id),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned lock")),
+        }
+    }
+    pub fn dropped(&self, socket_id: u16) -> usize {
+        match self.list.read() {
+            Ok(binding) => binding.dropped(socket_id),
+            Err(_) => 0,
+        }
+    }
+    //The partner threw out a message it already took, its receipt changes
+    pub fn shed(&self, socket_id: u16, channel: u16, msg_no: MessageNumber) {
+        if let Ok(mut binding) = self.list.write() {
+            binding.shed(socket_id, channel, msg_no)
+        }
+        self.signal.notify();
+    }
+    pub fn partner_shed(&self, socket_id: u16) -> usize {
+        match self.list.read() {
+            Ok(binding) => binding.partner_shed(socket_id),
+            Err(_) => 0,
+        }
+    }
+    pub fn size(&self, socket_id: u16)->usize{
+        match self.list.read() {
+            Ok(binding) => binding.size(socket_id),
+            Err(_) => 0
+        }
+    }
     pub fn poll(&self)->Option<Arc<NeonPoll>>{
         match self.list.read() {
             Ok(binding) => Some(binding.poll()),
@@ -0,0 +1,47 @@
+use std::{
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/*
+    Wakes up anyone blocked on the core without them holding the core lock
+        Grab the generation, check for work with the core lock, let go of the core lock, then wait
+        Anything that happened in between bumped the generation so the wake up isn't lost
+*/
+#[derive(Debug, Default)]
+pub struct NeonSignal {
+    generation: Mutex<u64>,
+    cond: Condvar,
+}
+
+impl NeonSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn notify(&self) {
+        if let Ok(mut generation) = self.generation.lock() {
+            *generation = generation.wrapping_add(1);
+        }
+        self.cond.notify_all();
+    }
+    pub fn generation(&self) -> u64 {
+        match self.generation.lock() {
+            Ok(generation) => *generation,
+            Err(_) => 0,
+        }
+    }
+    //Returns false if the timeout ran out before anything changed
+    pub fn wait(&self, seen: u64, timeout: Option<Duration>) -> bool {
+        let generation = match self.generation.lock() {
+            Ok(generation) => generation,
+            Err(_) => return false,
+        };
+        match timeout {
+            Some(timeout) => match self.cond.wait_timeout_while(generation, timeout, |g| *g == seen) {
+                Ok((_, result)) => !result.timed_out(),
+                Err(_) => false,
+            },
+            None => self.cond.wait_while(generation, |g| *g == seen).is_ok(),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+pub mod config;
 pub mod congestion;
 pub mod core;
 pub mod huffman;
@@ -20,7 +21,6 @@ pub mod connection;
 
 /*
 Known issues: 
-    drop isn't enabled 
     handshake can loop around discovery 
     test for double server is flawed (timing dependant)
     dropping certain acks can cause spinning, probably when drop happens
@@ -1,46 +1,140 @@
-use std::{io::{Error, ErrorKind}, net::SocketAddr, sync::{Arc, RwLock}, thread, time::Duration};
+use std::{io::{Error, ErrorKind}, net::SocketAddr, sync::{Arc, RwLock}, time::{Duration, SystemTime}};
 
-use crate::{core::{channel::NeonChannel, NeonCore}, stream::NeonStream};
+use crate::{config::NeonConfig, core::{channel::NeonChannel, NeonCore, NeonWorkers}, stream::NeonStream};
 
 pub struct NeonListener {
     core: Arc<RwLock<NeonCore>>,
+    workers: Arc<NeonWorkers>, //shared with every accepted stream
 }
 impl NeonListener {
-    pub fn simplex(addr: SocketAddr) -> Result<Self, Error> {
+    pub fn simplex(addr: SocketAddr, config: NeonConfig) -> Result<Self, Error> {
         //create a socket
         let channel = match NeonChannel::simplex(addr) {
             Ok(channel) => Arc::new(RwLock::new(channel)),
             Err(err) => return Err(err),
         };
 
-        let core = Arc::new(RwLock::new(NeonCore::new(channel.clone())));
-        NeonCore::work(core.clone());
-        Ok(Self { core })
+        let core = Arc::new(RwLock::new(NeonCore::new(channel.clone(), config)));
+        let workers = Arc::new(NeonCore::work(core.clone())?);
+        Ok(Self { core, workers })
     }
-    pub fn duplex(out_addr: SocketAddr, in_addr: SocketAddr) -> Result<Self, Error> {
+    pub fn duplex(out_addr: SocketAddr, in_addr: SocketAddr, config: NeonConfig) -> Result<Self, Error> {
         //create a socket
         let channel = match NeonChannel::duplex(out_addr,in_addr) {
             Ok(channel) => Arc::new(RwLock::new(channel)),
             Err(err) => return Err(err),
         };
 
-        let core = Arc::new(RwLock::new(NeonCore::new(channel.clone())));
-        NeonCore::work(core.clone());
-        Ok(Self { core })
+        let core = Arc::new(RwLock::new(NeonCore::new(channel.clone(), config)));
+        let workers = Arc::new(NeonCore::work(core.clone())?);
+        Ok(Self { core, workers })
     }
+    //Blocks until a connection is ready
     pub fn accept(&mut self) -> Result<NeonStream, Error> {
+        self.accept_until(None)
+    }
+    //Like accept but gives up with TimedOut
+    pub fn accept_timeout(&mut self, timeout: Duration) -> Result<NeonStream, Error> {
+        self.accept_until(Some(timeout))
+    }
+    //Never blocks, WouldBlock if nothing is waiting
+    pub fn try_accept(&mut self) -> Result<NeonStream, Error> {
+        if !self.workers.running() {
+            return Err(Error::new(ErrorKind::NotConnected, "Listener closed"));
+        }
+        match self.core.write() {
+            Ok(mut core) => match core.next_stream() {
+                Some(socket_id) => Ok(self.stream(socket_id)),
+                None => Err(Error::new(ErrorKind::WouldBlock, "No connection waiting")),
+            },
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Accepts forever, ends once the listener is closed
+    pub fn incoming(&mut self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+    fn accept_until(&mut self, timeout: Option<Duration>) -> Result<NeonStream, Error> {
+        let start = SystemTime::now();
         loop {
-            //check if there is a queued stream
-            let thread_core = self.core.clone();
-            match self.core.write(){
-                Ok(mut core) => if let Some(stream) = core.next_stream(thread_core) {return Ok(stream);},
-                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            if !self.workers.running() {
+                return Err(Error::new(ErrorKind::NotConnected, "Listener closed"));
             }
-            //TODO condvar
-            thread::sleep(Duration::from_millis(100));
+            //same dance as reads, look at the generation before checking the queue
+            let (signal, generation) = match self.core.write() {
+                Ok(mut core) => {
+                    let signal = core.accept_signal();
+                    let generation = signal.generation();
+                    if let Some(socket_id) = core.next_stream() {
+                        drop(core);
+                        return Ok(self.stream(socket_id));
+                    }
+                    (signal, generation)
+                }
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            let remaining = match timeout {
+                Some(timeout) => match start.elapsed() {
+                    Ok(elapsed) if elapsed < timeout => Some(timeout - elapsed),
+                    Ok(_) => return Err(Error::new(ErrorKind::TimedOut, "No connection before timeout")),
+                    Err(_) => Some(timeout),
+                },
+                None => None,
+            };
+            signal.wait(generation, remaining);
+        }
+    }
+    fn stream(&self, socket_id: u16) -> NeonStream {
+        NeonStream::from_core(socket_id, self.core.clone(), self.workers.clone())
+    }
+    /*
+        Gives every connection up to the linger time to get its data acknowledged
+        then closes all of them and stops the core, streams that are still around get NotConnected
+    */
+    pub fn close(&mut self) -> Result<(), Error> {
+        let start = SystemTime::now();
+        loop {
+            let (signal, generation, linger) = match self.core.read() {
+                Ok(core) => {
+                    let signal = match core.send_signal() {
+                        Some(signal) => signal,
+                        None => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+                    };
+                    let generation = signal.generation();
+                    if core.unacked_total() == 0 {
+                        break;
+                    }
+                    (signal, generation, core.config().linger)
+                }
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            let remaining = match start.elapsed() {
+                Ok(elapsed) if elapsed < linger => linger - elapsed,
+                Ok(_) => break,
+                Err(_) => linger,
+            };
+            signal.wait(generation, Some(remaining));
         }
+        match self.core.write() {
+            Ok(mut core) => core.close_all(),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+        self.workers.stop();
+        Ok(())
     }
-    pub fn incoming(&mut self) {
-        todo!()
+}
+
+pub struct Incoming<'a> {
+    listener: &'a mut NeonListener,
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = Result<NeonStream, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.listener.accept() {
+            Err(err) if err.kind() == ErrorKind::NotConnected => None,
+            out => Some(out),
+        }
     }
-}
\ No newline at end of file
+}
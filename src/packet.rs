@@ -7,7 +7,12 @@ use data::DataPacket;
 
 use crate::serial::Serial;
 
-pub const HEADER_SIZE:usize = 8;
+//type, a meta wide enough for a seq, stamp, microsecond timestamp and socket id
+pub const HEADER_SIZE:usize = 14;
+//data packets carry their channel after the common header
+pub const CHANNEL_SIZE:usize = 2;
+//and the seq and msg no in front take 8 bytes where type and meta take 6
+pub const DATA_HEADER_SIZE:usize = HEADER_SIZE + 2 + CHANNEL_SIZE;
 
 #[derive(Clone, Debug)]
 pub enum Packet {
@@ -22,6 +27,25 @@ impl Packet {
             Packet::Data(packet) => packet.dst_socket_id,
         }
     }
+    pub fn timestamp(&self) -> u32 {
+        match self {
+            Packet::Control(packet) => packet.timestamp,
+            Packet::Data(packet) => packet.timestamp,
+        }
+    }
+    pub fn set_timestamp(&mut self, timestamp: u32) {
+        match self {
+            Packet::Control(packet) => packet.timestamp = timestamp,
+            Packet::Data(packet) => packet.timestamp = timestamp,
+        }
+    }
+    //Cut every seq and msg no down to what a version 1 partner reads, it widens them again
+    pub fn narrow(self) -> Self {
+        match self {
+            Packet::Control(packet) => Packet::Control(packet.narrow()),
+            Packet::Data(packet) => Packet::Data(packet.narrow()),
+        }
+    }
 }
 
 impl Serial for Packet {
@@ -8,25 +8,31 @@ use ack::Ack;
 use ack_square::AckSquare;
 use congestion::Congestion;
 use custom::Custom;
+use datagram::Datagram;
 use discover::Discover;
 use drop::Drop;
 use err::Err;
-use handshake::{Handshake, ReqType, FLOW_CONTROL};
+use handshake::{Handshake, ReqType};
 use keep_alive::KeepAlive;
 use loss::Loss;
+use shed::Shed;
 use shutdown::Shutdown;
+use timing::Timing;
 
 pub mod ack;
 pub mod ack_square;
 pub mod congestion;
 pub mod custom;
+pub mod datagram;
 pub mod discover;
 pub mod drop;
 pub mod err;
 pub mod handshake;
 pub mod keep_alive;
 pub mod loss;
+pub mod shed;
 pub mod shutdown;
+pub mod timing;
 
 
 #[derive(Clone, Debug)]
@@ -35,6 +41,7 @@ pub struct ControlPacket {
     pub control_type: ControlType,
     pub meta: ControlMeta, //this might need a enum
     pub stamp: SystemTime,
+    pub timestamp: u32,     //microseconds since the connection started, set as it goes out
     pub dst_socket_id: u16, //dst socket
     pub info: ControlPacketInfo,
 }
@@ -59,6 +66,9 @@ pub enum ControlType {
     Drop,
     Err,
     Discover,
+    Datagram,
+    Timing,
+    Shed,
     Custom,
 }
 #[derive(Clone, Debug)]
@@ -73,6 +83,9 @@ pub enum ControlPacketInfo {
     Drop(Drop),
     Err(Err),
     Discover(Discover),
+    Datagram(Datagram),
+    Timing(Timing),
+    Shed(Shed),
     Custom(Custom),
 }
 impl ControlPacket {
@@ -80,8 +93,10 @@ impl ControlPacket {
         dst_socket_id: u16,
         req_type: ReqType,
         mss: u16,
+        flow_control: u16,
         src_socket_id: u16,
         in_addr: SocketAddr,
+        offer: (u16, u32),
     ) -> Self {
         let control_type = ControlType::Handshake;
         let meta = ControlMeta::Other(0);
@@ -89,14 +104,16 @@ impl ControlPacket {
         let info = ControlPacketInfo::Handshake(Handshake::new(
             req_type,
             mss,
-            FLOW_CONTROL,
+            flow_control,
             src_socket_id,
             in_addr,
+            offer,
         ));
         Self {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -110,6 +127,7 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -134,6 +152,7 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -147,6 +166,7 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -160,6 +180,7 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -174,6 +195,7 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -187,20 +209,36 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
     }
 
-    pub fn drop(dst_socket_id: u16, msg_no: MessageNumber, range: SequenceRange) -> Self {
+    pub fn drop(dst_socket_id: u16, channel: u16, msg_no: MessageNumber, ranges: Vec<SequenceRange>) -> Self {
         let control_type = ControlType::Drop;
         let meta = ControlMeta::Message(msg_no);
         let stamp = SystemTime::now();
-        let info = ControlPacketInfo::Drop(Drop::new(range));
+        let info = ControlPacketInfo::Drop(Drop::new(channel, ranges));
         Self {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
+            dst_socket_id,
+            info,
+        }
+    }
+    pub fn shed(dst_socket_id: u16, channel: u16, msg_no: MessageNumber) -> Self {
+        let control_type = ControlType::Shed;
+        let meta = ControlMeta::Message(msg_no);
+        let stamp = SystemTime::now();
+        let info = ControlPacketInfo::Shed(Shed::new(channel));
+        Self {
+            control_type,
+            meta,
+            stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -214,6 +252,7 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
@@ -227,12 +266,74 @@ impl ControlPacket {
             control_type,
             meta,
             stamp,
+            timestamp: 0,
             dst_socket_id,
             info,
         }
     }
-
-
+    //meta is the size being probed on a request and the size that arrived on a response
+    pub fn probe(dst_socket_id: u16, size: u16, meta: u16, req_type: ReqType) -> Self {
+        let control_type = ControlType::Discover;
+        let meta = ControlMeta::Other(meta);
+        let stamp = SystemTime::now();
+        let info = ControlPacketInfo::Discover(Discover::probe(size.into(), req_type));
+        Self {
+            control_type,
+            meta,
+            stamp,
+            timestamp: 0,
+            dst_socket_id,
+            info,
+        }
+    }
+    //data is already compressed, meta is the channel
+    pub fn datagram(dst_socket_id: u16, channel: u16, data: Vec<u8>) -> Self {
+        let control_type = ControlType::Datagram;
+        let meta = ControlMeta::Other(channel);
+        let stamp = SystemTime::now();
+        let info = ControlPacketInfo::Datagram(Datagram::new(data));
+        Self {
+            control_type,
+            meta,
+            stamp,
+            timestamp: 0,
+            dst_socket_id,
+            info,
+        }
+    }
+    pub fn timing(dst_socket_id: u16, timing: Timing) -> Self {
+        let control_type = ControlType::Timing;
+        let meta = ControlMeta::Other(0);
+        let stamp = SystemTime::now();
+        let info = ControlPacketInfo::Timing(timing);
+        Self {
+            control_type,
+            meta,
+            stamp,
+            timestamp: 0,
+            dst_socket_id,
+            info,
+        }
+    }
+    //The handshake's isn stays whole, the width isn't agreed until it's answered
+    pub fn narrow(mut self) -> Self {
+        self.meta = match self.meta {
+            ControlMeta::Seq(seq_no) => ControlMeta::Seq(seq_no.narrow()),
+            ControlMeta::Message(msg_no) => ControlMeta::Message(msg_no.narrow()),
+            other => other,
+        };
+        match &mut self.info {
+            ControlPacketInfo::Ack(info) => info.seq_no = info.seq_no.narrow(),
+            ControlPacketInfo::Loss(info) => {
+                info.loss_range.iter_mut().for_each(|range| *range = range.narrow())
+            }
+            ControlPacketInfo::Drop(info) => {
+                info.ranges.iter_mut().for_each(|range| *range = range.narrow())
+            }
+            _ => {}
+        }
+        self
+    }
 }
 
 impl Serial for ControlPacket {
@@ -241,10 +342,12 @@ impl Serial for ControlPacket {
         let meta = match self.meta {
             ControlMeta::Seq(ack) => ack.serialize(),
             ControlMeta::Message(msg) => msg.serialize(),
-            ControlMeta::Other(other) => other.serialize(),
+            //padded so every header is the same size
+            ControlMeta::Other(other) => (other as u32).serialize(),
         };
         bytes.extend_from_slice(&meta);
         bytes.extend_from_slice(&self.stamp.serialize());
+        bytes.extend_from_slice(&self.timestamp.serialize());
         bytes.extend_from_slice(&self.dst_socket_id.serialize());
         let info = match &self.info {
             ControlPacketInfo::Handshake(info) => info.serialize(),
@@ -257,6 +360,9 @@ impl Serial for ControlPacket {
             ControlPacketInfo::Drop(info) => info.serialize(),
             ControlPacketInfo::Err(info) => info.serialize(),
             ControlPacketInfo::Discover(info) => info.serialize(),
+            ControlPacketInfo::Datagram(info) => info.serialize(),
+            ControlPacketInfo::Timing(info) => info.serialize(),
+            ControlPacketInfo::Shed(info) => info.serialize(),
             ControlPacketInfo::Custom(info) => info.serialize(),
         };
         bytes.extend_from_slice(&info);
@@ -266,19 +372,23 @@ impl Serial for ControlPacket {
     fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
         let control_type = ControlType::deserialize(bytes, start);
         let meta = match control_type {
-            ControlType::Handshake => ControlMeta::Other(u16::deserialize(bytes, start)),
-            ControlType::KeepAlive => ControlMeta::Other(u16::deserialize(bytes, start)),
+            ControlType::Handshake => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
+            ControlType::KeepAlive => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
             ControlType::Ack => ControlMeta::Seq(SequenceNumber::deserialize(bytes, start)),
             ControlType::Loss => ControlMeta::Seq(SequenceNumber::deserialize(bytes, start)),
-            ControlType::Congestion => ControlMeta::Other(u16::deserialize(bytes, start)),
-            ControlType::Shutdown => ControlMeta::Other(u16::deserialize(bytes, start)),
+            ControlType::Congestion => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
+            ControlType::Shutdown => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
             ControlType::AckSquare => ControlMeta::Seq(SequenceNumber::deserialize(bytes, start)),
             ControlType::Drop => ControlMeta::Message(MessageNumber::deserialize(bytes, start)),
-            ControlType::Err => ControlMeta::Other(u16::deserialize(bytes, start)),
-            ControlType::Discover => ControlMeta::Other(u16::deserialize(bytes, start)),
-            ControlType::Custom => ControlMeta::Other(u16::deserialize(bytes, start)),
+            ControlType::Err => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
+            ControlType::Discover => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
+            ControlType::Datagram => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
+            ControlType::Timing => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
+            ControlType::Shed => ControlMeta::Message(MessageNumber::deserialize(bytes, start)),
+            ControlType::Custom => ControlMeta::Other(u32::deserialize(bytes, start) as u16),
         };
         let stamp = SystemTime::deserialize(bytes, start);
+        let timestamp = u32::deserialize(bytes, start);
         let dst_socket_id = u16::deserialize(bytes, start);
         let info = match control_type {
             ControlType::Handshake => {
@@ -303,12 +413,18 @@ impl Serial for ControlPacket {
             ControlType::Discover => {
                 ControlPacketInfo::Discover(Discover::deserialize(bytes, start))
             }
+            ControlType::Datagram => {
+                ControlPacketInfo::Datagram(Datagram::deserialize(bytes, start))
+            }
+            ControlType::Timing => ControlPacketInfo::Timing(Timing::deserialize(bytes, start)),
+            ControlType::Shed => ControlPacketInfo::Shed(Shed::deserialize(bytes, start)),
             ControlType::Custom => ControlPacketInfo::Custom(Custom::deserialize(bytes, start)),
         };
         Self {
             control_type,
             meta,
             stamp,
+            timestamp,
             dst_socket_id,
             info,
         }
@@ -328,6 +444,9 @@ impl Serial for ControlType {
             ControlType::Drop => 0x0007u16,
             ControlType::Err => 0x0008u16,
             ControlType::Discover => 0x0009u16,
+            ControlType::Datagram => 0x000au16,
+            ControlType::Timing => 0x000bu16,
+            ControlType::Shed => 0x000cu16,
             ControlType::Custom => 0x7fffu16,
         };
         translation.serialize()
@@ -346,6 +465,9 @@ impl Serial for ControlType {
             0x0007u16 => ControlType::Drop,
             0x0008u16 => ControlType::Err,
             0x0009u16 => ControlType::Discover,
+            0x000au16 => ControlType::Datagram,
+            0x000bu16 => ControlType::Timing,
+            0x000cu16 => ControlType::Shed,
             0x7fffu16 => ControlType::Custom,
             _ => ControlType::Err,
         }
@@ -0,0 +1,25 @@
+use crate::serial::Serial;
+
+//Huffman compressed like data but never stored for a resend
+#[derive(Clone,Debug)]
+pub struct Datagram {
+    pub data: Vec<u8>,
+}
+
+impl Datagram{
+    pub fn new(data:Vec<u8>)->Self{
+        Self{data}
+    }
+}
+
+impl Serial for Datagram {
+    fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
+        let data = bytes[*start..].to_vec();
+        *start = bytes.len();
+        Self { data }
+    }
+}
@@ -1,22 +1,45 @@
 use std::fmt::Debug;
-use crate::serial::Serial;
+use crate::{packet::CHANNEL_SIZE, serial::Serial};
 
 use super::handshake::ReqType;
 
+/*
+    The fill byte says what the discover is for
+        0xff / 0x7f are the handshake discover and its response
+        0xfe / 0x7e are path mtu probes and their acks
+    Padded by the channel so a discover is as big as a data packet with the same payload
+*/
 #[derive(Clone)]
 pub struct Discover {
     pub req_type: ReqType,
+    pub probe: bool,
     pub data: Vec<u8>,
 }
 
 impl Discover{
     pub fn new(count:usize, req_type: ReqType)->Self{
-        let char = match req_type{
-            ReqType::Connection => 0xff,
-            ReqType::Response => 0x7f,
+        Self::fill(count, req_type, false)
+    }
+    pub fn probe(count:usize, req_type: ReqType)->Self{
+        Self::fill(count, req_type, true)
+    }
+    fn fill(count:usize, req_type: ReqType, probe: bool)->Self{
+        let char = match (req_type, probe){
+            (ReqType::Connection, false) => 0xff,
+            (ReqType::Response, false) => 0x7f,
+            (ReqType::Connection, true) => 0xfe,
+            (ReqType::Response, true) => 0x7e,
         };
-        let data = vec![char;count];
-        Self{req_type, data}
+        //always at least one byte so the type survives
+        let data = vec![char;count.max(1) + CHANNEL_SIZE];
+        Self{req_type, probe, data}
+    }
+}
+
+impl Discover{
+    //the payload size this stands in for
+    pub fn size(&self)->u16{
+        self.data.len().saturating_sub(CHANNEL_SIZE) as u16
     }
 }
 
@@ -28,21 +51,20 @@ impl Serial for Discover {
     fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
         let data = bytes[*start..].to_vec();
         *start = bytes.len();
-        let req_type = match data[0]{
-            0xff=>{
-                ReqType::Connection
-            },
-            _=>{
-                ReqType::Response
-            }
+        let (req_type, probe) = match data.first(){
+            Some(0xff)=>(ReqType::Connection, false),
+            Some(0xfe)=>(ReqType::Connection, true),
+            Some(0x7e)=>(ReqType::Response, true),
+            _=>(ReqType::Response, false),
         };
-        Self {req_type, data }
+        Self {req_type, probe, data }
     }
 }
 impl Debug for Discover {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Discover")
             .field("req_type", &self.req_type)
+            .field("probe", &self.probe)
             .field("len", &self.data.len())
             .finish()
     }
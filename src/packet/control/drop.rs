@@ -1,26 +1,42 @@
 use crate::{serial::Serial, utils::SequenceRange};
 
 
-#[derive(Copy,Clone,Debug)]
+/*
+    The seqs a message went out on, as back to back runs
+    Other messages cut in between its packets so only these are given up on
+*/
+#[derive(Clone,Debug)]
 pub struct Drop {
-    pub range: SequenceRange
+    pub channel: u16, //message numbers are per channel
+    pub ranges: Vec<SequenceRange>,
 }
 impl Drop{
-    pub fn new(range: SequenceRange)->Self{
+    pub fn new(channel: u16, ranges: Vec<SequenceRange>)->Self{
         Self{
-            range
+            channel,
+            ranges
         }
     }
 }
 impl Serial for Drop {
     fn serialize(&self) -> Vec<u8> {
-        self.range.serialize()
+        let mut bytes = self.channel.serialize();
+        self.ranges.iter().for_each(|range|{
+            bytes.extend_from_slice(&range.serialize());
+        });
+        bytes
     }
 
     fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
-        let range = SequenceRange::deserialize(bytes, start);
+        let channel = u16::deserialize(bytes, start);
+        //a range is two 4 byte seqs
+        let count = (bytes.len()-*start)/8;
+        let ranges = (0..count).map(|_|{
+            SequenceRange::deserialize(bytes, start)
+        }).collect();
         Self {
-            range
+            channel,
+            ranges
         }
     }
 }
@@ -1,5 +1,10 @@
 use crate::serial::Serial;
 
+//meta codes, these end up in NeonStatus::Unhealthy
+pub const ERR_TIMEOUT: u16 = 0x0001; //partner stopped answering keep alives
+pub const ERR_BACKLOG: u16 = 0x0002; //listener has too many connections waiting to be accepted
+pub const ERR_VERSION: u16 = 0x0003; //no wire version both sides speak
+
 #[derive(Copy, Clone, Debug)]
 pub struct Err {}
 
@@ -4,7 +4,6 @@ use std::{
 };
 
 use crate::{
-    core::channel::MAX_PACKET_SIZE,
     serial::Serial,
     sha::Hash,
     utils::{self, SequenceNumber},
@@ -13,6 +12,20 @@ use crate::{
 use super::{ControlMeta, ControlPacket, ControlPacketInfo, ControlType};
 
 pub const FLOW_CONTROL: u16 = 25600;
+//2 widened seqs to 31 bits and msg nos to 29, the newest we speak
+pub const WIRE_VERSION: u16 = 2;
+//1 only reads 15 bit seqs and 13 bit msg nos, they're cut down on the way out and widened back coming in
+pub const MIN_WIRE_VERSION: u16 = 1;
+//everything up to the version, the version range and capabilities after it are optional
+const HANDSHAKE_SIZE: usize = 16;
+const EXTENSION_SIZE: usize = 6;
+
+//capability bits, a feature is only used when both sides set it
+pub const CAP_DATAGRAM: u32 = 0x0001; //unreliable datagrams
+pub const CAP_BUNDLE: u32 = 0x0002; //small messages coalesced into one packet
+pub const CAP_PMTU: u32 = 0x0004; //path mtu probes
+pub const CAP_TIMING: u32 = 0x0008; //timing resyncs
+pub const CAPABILITIES: u32 = CAP_DATAGRAM | CAP_BUNDLE | CAP_PMTU | CAP_TIMING;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Handshake {
@@ -23,10 +36,13 @@ pub struct Handshake {
     pub src_socket_id: u16, //my socket id
     pub cookie: u16,
     pub port: u16, //my in port
+    pub version: u16,      //the newest spoken on a request, the one picked on a response
+    pub min_version: u16,  //the oldest spoken, the picked one again on a response
+    pub capabilities: u32, //what's offered on a request, what both support on a response
 }
 
 impl Handshake {
-    pub fn cookie(mss: u16, flow_control: u16, socket_id: u16, time: SystemTime) -> (u16, u16) {
+    pub fn cookie(mss: u16, flow_control: u16, socket_id: u16, time: SystemTime) -> (u16, u32) {
         let mut hash = Hash::new();
         hash.update(&mss.serialize());
         hash.update(&flow_control.serialize());
@@ -45,9 +61,9 @@ impl Handshake {
         }
 
         //use the second half to generate an isn
-        let mut isn = cookie;
+        let mut isn = cookie as u32;
         while start < large_cookie.len() {
-            isn ^= u16::deserialize(&large_cookie, &mut start);
+            isn ^= u32::deserialize(&large_cookie, &mut start);
         }
         (cookie, isn)
     }
@@ -58,10 +74,11 @@ impl Handshake {
         flow_control: u16,
         src_socket_id: u16,
         in_addr: SocketAddr,
+        offer: (u16, u32), //the newest version and the features
     ) -> Self {
         //not cryptographically secure
         let (cookie, hash_isn) = Self::cookie(mss, flow_control, src_socket_id, SystemTime::now());
-        let isn = SequenceNumber::new(hash_isn);
+        let isn = SequenceNumber::initial(hash_isn);
         let port = in_addr.port();
         Self {
             isn,
@@ -71,6 +88,17 @@ impl Handshake {
             src_socket_id,
             cookie,
             port,
+            version: offer.0,
+            min_version: MIN_WIRE_VERSION,
+            capabilities: offer.1,
+        }
+    }
+    //The highest version both speak and the features both support, none if the versions don't overlap
+    pub fn agree(&self, newest: u16, capabilities: u32) -> Option<(u16, u32)> {
+        let version = self.version.min(newest);
+        match version >= self.min_version.max(MIN_WIRE_VERSION) {
+            true => Some((version, self.capabilities & capabilities)),
+            false => None,
         }
     }
     pub fn reply(
@@ -78,11 +106,12 @@ impl Handshake {
         dst_socket_id: u16,
         info: Self,
         in_addr: SocketAddr,
+        mss: u16,
+        flow_control: u16,
+        agreed: (u16, u32),
     ) -> (SequenceNumber, ControlPacket) {
-        let isn = SequenceNumber::new(utils::hash(info.isn.0 as usize) as u16);
+        let isn = SequenceNumber::initial(utils::hash(info.isn.0 as usize) as u32);
         let req_type = ReqType::Response;
-        let mss = MAX_PACKET_SIZE;
-        let flow_control = FLOW_CONTROL;
         let cookie = info.cookie;
         let port = in_addr.port();
         let info = Self {
@@ -93,6 +122,9 @@ impl Handshake {
             src_socket_id,
             cookie,
             port,
+            version: agreed.0,
+            min_version: agreed.0,
+            capabilities: agreed.1,
         };
         (
             isn,
@@ -100,6 +132,7 @@ impl Handshake {
                 control_type: ControlType::Handshake,
                 meta: ControlMeta::Other(0),
                 stamp: SystemTime::now(),
+                timestamp: 0,
                 dst_socket_id,
                 info: ControlPacketInfo::Handshake(info),
             },
@@ -135,10 +168,29 @@ impl Serial for Handshake {
         bytes.extend_from_slice(&self.src_socket_id.serialize());
         bytes.extend_from_slice(&self.cookie.serialize());
         bytes.extend_from_slice(&self.port.serialize());
+        bytes.extend_from_slice(&self.version.serialize());
+        bytes.extend_from_slice(&self.min_version.serialize());
+        bytes.extend_from_slice(&self.capabilities.serialize());
         bytes
     }
 
     fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
+        //an older peer's handshake is too short to carry a version, its 2 byte seqs are refused on that
+        if bytes.len() < *start + HANDSHAKE_SIZE {
+            *start = bytes.len();
+            return Self {
+                isn: SequenceNumber::ZERO,
+                req_type: ReqType::Connection,
+                mss: 0,
+                flow_control: 0,
+                src_socket_id: 0,
+                cookie: 0,
+                port: 0,
+                version: 0,
+                min_version: 0,
+                capabilities: 0,
+            };
+        }
         let control = bytes[*start];
         let req_type = match control & 0x80 {
             0x80 => ReqType::Connection,
@@ -151,6 +203,12 @@ impl Serial for Handshake {
         let src_socket_id = u16::deserialize(bytes, start);
         let cookie = u16::deserialize(bytes, start);
         let port = u16::deserialize(bytes, start);
+        let version = u16::deserialize(bytes, start);
+        //a peer from before the range only speaks its one version and offers nothing
+        let (min_version, capabilities) = match bytes.len() >= *start + EXTENSION_SIZE {
+            true => (u16::deserialize(bytes, start), u32::deserialize(bytes, start)),
+            false => (version, 0),
+        };
         Self {
             isn,
             mss,
@@ -159,6 +217,9 @@ impl Serial for Handshake {
             src_socket_id,
             cookie,
             port,
+            version,
+            min_version,
+            capabilities,
         }
     }
 }
@@ -0,0 +1,29 @@
+use crate::serial::Serial;
+
+/*
+    A message that was taken and acked but thrown out of a full receive buffer before anyone read it
+    The msg no is in the meta like a drop, the sender's receipt for it turns to Shed
+*/
+#[derive(Copy,Clone,Debug)]
+pub struct Shed {
+    pub channel: u16, //message numbers are per channel
+}
+impl Shed{
+    pub fn new(channel: u16)->Self{
+        Self{
+            channel
+        }
+    }
+}
+impl Serial for Shed {
+    fn serialize(&self) -> Vec<u8> {
+        self.channel.serialize()
+    }
+
+    fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
+        let channel = u16::deserialize(bytes, start);
+        Self {
+            channel
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use crate::serial::Serial;
 
+//meta codes
+pub const SHUTDOWN_CLOSE: u16 = 0x0000; //partner is gone, nothing more either way
+pub const SHUTDOWN_WRITE: u16 = 0x0001; //partner won't send anything more but still reads
+
 #[derive(Copy,Clone,Debug)]
 pub struct Shutdown{
 
@@ -0,0 +1,59 @@
+use crate::serial::Serial;
+
+use super::handshake::ReqType;
+
+/*
+    Resyncs the timestamp epoch, both sides restart their clocks when it goes through
+    The request's own header timestamp is when it left, the response echoes it as the origin
+    with when it arrived and its header timestamp is when it left again, the usual four ntp times
+*/
+#[derive(Clone, Debug)]
+pub struct Timing {
+    pub req_type: ReqType,
+    pub origin: u32,   //the request's timestamp, zero on the request
+    pub received: u32, //when the request arrived, on the responder's clock
+}
+
+impl Timing {
+    pub fn request() -> Self {
+        Self {
+            req_type: ReqType::Connection,
+            origin: 0,
+            received: 0,
+        }
+    }
+    pub fn response(origin: u32, received: u32) -> Self {
+        Self {
+            req_type: ReqType::Response,
+            origin,
+            received,
+        }
+    }
+}
+
+impl Serial for Timing {
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = match self.req_type {
+            ReqType::Connection => 0x80u8,
+            ReqType::Response => 0x00u8,
+        }
+        .serialize();
+        bytes.extend_from_slice(&self.origin.serialize());
+        bytes.extend_from_slice(&self.received.serialize());
+        bytes
+    }
+
+    fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
+        let req_type = match u8::deserialize(bytes, start) & 0x80 {
+            0x80 => ReqType::Connection,
+            _ => ReqType::Response,
+        };
+        let origin = u32::deserialize(bytes, start);
+        let received = u32::deserialize(bytes, start);
+        Self {
+            req_type,
+            origin,
+            received,
+        }
+    }
+}
@@ -5,14 +5,22 @@ use crate::{
 };
 use std::{collections::HashMap, fmt::Debug, time::SystemTime};
 
+pub const MAX_CHANNEL: u16 = 0x3fff; //the top two bits of the channel field mark a bundle and a ttl
+const BUNDLE_FLAG: u16 = 0x8000;
+const TTL_FLAG: u16 = 0x4000;
+
 #[derive(Clone)]
 pub struct DataPacket {
     pub seq_no: SequenceNumber,
     pub msg_no: MessageNumber,
     pub element: DataPacketType,
     pub order: bool,
-    pub stamp: SystemTime,
+    pub stamp: SystemTime, //wall clock, coarse, for ttls
+    pub timestamp: u32,    //microseconds since the connection started, set as it goes out
     pub dst_socket_id: u16,
+    pub channel: u16,
+    pub bundle: bool, //several small messages with consecutive msg nos, see pack
+    pub ttl: bool,    //the message can expire, a full receiver may shed it once it's acked
     pub data: Vec<u8>,
 }
 
@@ -40,11 +48,55 @@ impl DataPacket {
             element,
             order,
             stamp,
+            timestamp: 0,
             dst_socket_id,
+            channel: 0,
+            bundle: false,
+            ttl: false,
             data,
         }
     }
+    pub fn on_channel(mut self, channel: u16) -> Self {
+        self.channel = channel;
+        self
+    }
+    pub fn bundled(mut self) -> Self {
+        self.bundle = true;
+        self
+    }
+    pub fn expiring(mut self, ttl: bool) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    pub fn narrow(mut self) -> Self {
+        self.seq_no = self.seq_no.narrow();
+        self.msg_no = self.msg_no.narrow();
+        self
+    }
+    //Each message goes in as its length then its bytes, the whole bundle is compressed once
+    pub fn pack(bundle: &mut Vec<u8>, message: &[u8]) {
+        bundle.extend_from_slice(&(message.len() as u16).serialize());
+        bundle.extend_from_slice(message);
+    }
+    pub fn unpack(bundle: &[u8]) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut start = 0;
+        while start + 2 <= bundle.len() {
+            let len = u16::deserialize(bundle, &mut start) as usize;
+            match bundle.get(start..start + len) {
+                Some(message) => out.push(message.to_vec()),
+                None => break,
+            }
+            start += len;
+        }
+        out
+    }
+    //The byte count goes first, the padding in the last byte would decode as more symbols
     pub fn compress(bytes: &[u8]) -> Vec<u8> {
+        //there's no tree to build from nothing
+        if bytes.is_empty() {
+            return 0u32.serialize();
+        }
         //Huffman encode all packets
         let mut weights = HashMap::new();
         for &byte in bytes {
@@ -57,15 +109,22 @@ impl DataPacket {
             book.encode(&mut bitter, byte)
                 .expect("Invariant failed in huffman");
         }
-        bitter.serialize()
+        let mut out = (bytes.len() as u32).serialize();
+        out.extend_from_slice(&bitter.serialize());
+        out
     }
 
     pub fn decompress(bytes: &[u8]) -> Vec<u8> {
-        let mut bitter = Bitter::deserialize(bytes, &mut 0);
+        let mut start = 0;
+        let count = u32::deserialize(bytes, &mut start) as usize;
+        if count == 0 {
+            return vec![];
+        }
+        let mut bitter = Bitter::deserialize(bytes, &mut start);
         let mut ptr = 0;
         let tree = Tree::bitter_deserial(&bitter, &mut ptr);
         bitter.forward(ptr);
-        tree.unbounded_decoder(bitter).collect()
+        tree.decoder(bitter, count).collect()
     }
 }
 
@@ -85,7 +144,17 @@ impl Serial for DataPacket {
         }
         bytes.extend_from_slice(&msg_no);
         bytes.extend_from_slice(&self.stamp.serialize());
+        bytes.extend_from_slice(&self.timestamp.serialize());
         bytes.extend_from_slice(&self.dst_socket_id.serialize());
+        let channel = match self.bundle {
+            true => self.channel | BUNDLE_FLAG,
+            false => self.channel,
+        };
+        let channel = match self.ttl {
+            true => channel | TTL_FLAG,
+            false => channel,
+        };
+        bytes.extend_from_slice(&channel.serialize());
         bytes.extend_from_slice(&self.data);
         bytes
     }
@@ -102,7 +171,12 @@ impl Serial for DataPacket {
         };
         let order = matches!(control & 0x20, 0x20);
         let stamp = SystemTime::deserialize(bytes, start);
+        let timestamp = u32::deserialize(bytes, start);
         let dst_socket_id = u16::deserialize(bytes, start);
+        let channel = u16::deserialize(bytes, start);
+        let bundle = matches!(channel & BUNDLE_FLAG, BUNDLE_FLAG);
+        let ttl = matches!(channel & TTL_FLAG, TTL_FLAG);
+        let channel = channel & MAX_CHANNEL;
 
         let data = bytes[*start..].to_vec();
         *start = bytes.len();
@@ -112,7 +186,11 @@ impl Serial for DataPacket {
             element,
             order,
             stamp,
+            timestamp,
             dst_socket_id,
+            channel,
+            bundle,
+            ttl,
             data,
         }
     }
@@ -126,7 +204,11 @@ impl Debug for DataPacket {
             .field("element", &self.element)
             .field("order", &self.order)
             .field("stamp", &self.stamp)
+            .field("timestamp", &self.timestamp)
             .field("dst_socket_id", &self.dst_socket_id)
+            .field("channel", &self.channel)
+            .field("bundle", &self.bundle)
+            .field("ttl", &self.ttl)
             .field("data.len()", &self.data.len())
             .finish()
     }
@@ -167,8 +167,8 @@ impl Serial for f64 {
     }
 }
 
-//This gives about 18 hours of lifetime before TTL shuffle happens
-//This is fixed by a 'timing packet' that tells the other side to drop all after the sync
+//Seconds in a u16 wrap every 18 hours, a stamp is read as the wrap nearest now so long connections don't shuffle
+//Finer timing goes through the header timestamp which the timing packet keeps resynced
 impl Serial for SystemTime {
     fn serialize(&self) -> Vec<u8> {
         let boot_time = START.get_or_init(|| {
@@ -190,8 +190,18 @@ impl Serial for SystemTime {
                 None => SystemTime::now(),
             }
         });
-        let seconds = u16::deserialize(bytes, start);
-        *boot_time + Duration::from_secs(seconds.into())
+        let seconds = u16::deserialize(bytes, start) as u64;
+        let cycle = u16::MAX as u64;
+        let now = match boot_time.elapsed() {
+            Ok(elapsed) => elapsed.as_secs(),
+            Err(_) => 0,
+        };
+        let base = now - now % cycle + seconds;
+        let seconds = [base.saturating_sub(cycle), base, base + cycle]
+            .into_iter()
+            .min_by_key(|seconds| seconds.abs_diff(now))
+            .unwrap_or(base);
+        *boot_time + Duration::from_secs(seconds)
     }
 }
 
@@ -1,22 +1,74 @@
 use std::{
-    io::{Error, ErrorKind},
-    net::SocketAddr,
-    sync::{Arc, RwLock},
+    collections::VecDeque,
+    io::{BufRead, Error, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
+    config::{NeonConfig, StreamMode},
     core::{
         channel::NeonChannel,
-        NeonCore,
+        NeonCore, NeonWorkers,
+    },
+    packet::{
+        control::handshake::ReqType,
+        data::{DataPacket, MAX_CHANNEL},
     },
-    packet::control::handshake::ReqType,
 };
 
+pub use crate::core::send::send_buffer::{MessageId, Priority, Receipt, NO_TTL};
+use crate::core::send::send_buffer::MessageOptions;
+
+const SEGMENT_SIZE: usize = 65536; //bytes of a write_from source per message
+const SEGMENT_WINDOW: usize = 8;   //segments written before write_from waits on the oldest ack
+const SEGMENT_MORE: u8 = 0;
+const SEGMENT_LAST: u8 = 1;
+const STREAM_WINDOW: usize = 256; //packets a byte stream writes before waiting on the oldest ack
+const FIT_TRIES: usize = 4;       //compressions spent looking for the most bytes that fill a packet
+const LINGER_POLL: Duration = Duration::from_millis(50); //a lingering drop notices a stopped core this fast
+
+//Messages that expired before delivery, counted once each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DropCounts {
+    pub sent: usize,     //our messages whose ttl ran out before an ack
+    pub sent_shed: usize, //our messages the partner acked then shed, their receipts say Shed
+    pub received: usize, //partner messages we were told to give up on
+    pub shed: usize,     //partner messages thrown out of a full receive buffer, see Overflow
+}
+
+/*
+    A stream is channel 0 of its connection, open_channel and accept_channel hand out the others
+    Channels share the connection so only the stream itself closes it
+    In StreamMode::Bytes it also works as io::Read, BufRead and Write, the inherent read and write
+    shadow those so call them through the traits (io::copy, BufReader and friends already do)
+    try_clone and into_split hand out more handles, the connection closes when the last one drops
+*/
 pub struct NeonStream {
     core: Arc<RwLock<NeonCore>>,
     socket_id: u16,
+    channel: u16,
+    workers: Arc<NeonWorkers>, //keeps the core running while the stream is alive
+    mode: StreamMode,
+    incoming: Mutex<VecDeque<u8>>,      //read but not yet handed out
+    outgoing: Mutex<Vec<u8>>,           //written but short of a full packet
+    in_flight: Mutex<VecDeque<MessageId>>, //packets sent but not known to be acked
+    handles: Arc<AtomicUsize>,          //clones and halves of this stream still alive
+}
+
+//The reading side of into_split, it can go to another thread than the writer
+pub struct NeonReadHalf {
+    stream: NeonStream,
+}
+
+//The writing side of into_split
+pub struct NeonWriteHalf {
+    stream: NeonStream,
 }
 
 impl NeonStream {
@@ -25,6 +77,7 @@ impl NeonStream {
         max_attempts: usize,
         timeout: Duration,
         other: SocketAddr,
+        config: NeonConfig,
     ) -> Result<Self, Error> {
         //Step 1: Create the channels
         let (socket_id, channel) = match NeonChannel::simplex(addr) {
@@ -36,15 +89,15 @@ impl NeonStream {
         };
 
         //Step 2: Create a core with max mss
-        let core = Arc::new(RwLock::new(NeonCore::new(channel)));
-        NeonCore::work(core.clone());
+        let core = Arc::new(RwLock::new(NeonCore::new(channel, config)));
+        let workers = Arc::new(NeonCore::work(core.clone())?);
         //Step 3: Handshake to establish connection (should timeout loop)
         match Self::handshake(max_attempts, timeout, other, core.clone(), socket_id) {
             Ok(_) => {}
             Err(err) => return Err(err),
         }
 
-        Ok(Self { core, socket_id })
+        Ok(Self::from_core(socket_id, core, workers))
     }
     pub fn duplex(
         out_addr: SocketAddr,
@@ -52,6 +105,7 @@ impl NeonStream {
         max_attempts: usize,
         timeout: Duration,
         other: SocketAddr,
+        config: NeonConfig,
     ) -> Result<Self, Error> {
         //Step 1: Create the channels
         let (socket_id, channel) = match NeonChannel::duplex(out_addr,in_addr) {
@@ -63,18 +117,62 @@ impl NeonStream {
         };
 
         //Step 2: Create a core with max mss
-        let core = Arc::new(RwLock::new(NeonCore::new(channel)));
-        NeonCore::work(core.clone());
+        let core = Arc::new(RwLock::new(NeonCore::new(channel, config)));
+        let workers = Arc::new(NeonCore::work(core.clone())?);
         //Step 3: Handshake to establish connection (should timeout loop)
         match Self::handshake(max_attempts, timeout, other, core.clone(), socket_id) {
             Ok(_) => {}
             Err(err) => return Err(err),
         }
 
-        Ok(Self { core, socket_id })
+        Ok(Self::from_core(socket_id, core, workers))
+    }
+    pub fn from_core(socket_id: u16, core: Arc<RwLock<NeonCore>>, workers: Arc<NeonWorkers>)->Self{
+        Self::on_channel(socket_id, 0, core, workers)
     }
-    pub fn from_core(socket_id: u16, core: Arc<RwLock<NeonCore>>)->Self{
-        Self { core, socket_id }
+    fn on_channel(
+        socket_id: u16,
+        channel: u16,
+        core: Arc<RwLock<NeonCore>>,
+        workers: Arc<NeonWorkers>,
+    ) -> Self {
+        let mode = match core.read() {
+            Ok(core) => core.config().mode,
+            Err(_) => StreamMode::Message,
+        };
+        Self {
+            core,
+            socket_id,
+            channel,
+            workers,
+            mode,
+            incoming: Mutex::new(VecDeque::new()),
+            outgoing: Mutex::new(Vec::new()),
+            in_flight: Mutex::new(VecDeque::new()),
+            handles: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+    //Another handle on the same channel, each keeps its own byte mode buffers
+    pub fn try_clone(&self) -> Result<NeonStream, Error> {
+        match self.core.read() {
+            Ok(core) if core.mss(self.socket_id).is_some() => {}
+            Ok(_) => return Err(Error::new(ErrorKind::NotConnected, "No connection")),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+        self.handles.fetch_add(1, Ordering::AcqRel);
+        let mut stream = self.with_channel(self.channel);
+        stream.handles = self.handles.clone();
+        Ok(stream)
+    }
+    //Anything already read but not handed out goes with the reader, an unsent tail with the writer
+    pub fn into_split(self) -> (NeonReadHalf, NeonWriteHalf) {
+        self.handles.fetch_add(1, Ordering::AcqRel);
+        let mut reader = self.with_channel(self.channel);
+        reader.handles = self.handles.clone();
+        if let (Ok(mut from), Ok(to)) = (self.incoming.lock(), reader.incoming.get_mut()) {
+            *to = std::mem::take(&mut *from);
+        }
+        (NeonReadHalf { stream: reader }, NeonWriteHalf { stream: self })
     }
 
     fn handshake(
@@ -91,6 +189,8 @@ impl NeonStream {
                     Ok(()) => {
                         return Ok(());
                     }
+                    //no point asking again
+                    Err(err) if err.kind() == ErrorKind::ConnectionRefused => return Err(err),
                     Err(err) => Err(err),
                 },
                 Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
@@ -104,20 +204,759 @@ impl NeonStream {
         }
     }
 
-    pub fn read(&self) -> Vec<u8> {
-        let socket_id = self.socket_id;
+    //Blocks until a whole message is ready, UnexpectedEof once the partner has closed and everything is read
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        self.read_until(None, |core| core.read_data(self.socket_id, self.channel))
+    }
+    //Like read but a message lost on the way is InvalidData, for readers that can't skip one
+    fn read_intact(&self) -> Result<Vec<u8>, Error> {
+        self.read_until(None, |core| core.read_intact(self.socket_id, self.channel))
+    }
+    //Like read but gives up with TimedOut
+    pub fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.read_until(Some(timeout), |core| core.read_data(self.socket_id, self.channel))
+    }
+    //Never blocks, WouldBlock if nothing is ready
+    pub fn try_read(&self) -> Result<Vec<u8>, Error> {
+        self.try_read_with(|core| core.read_data(self.socket_id, self.channel))
+    }
+    fn try_read_with<T>(&self, read: impl Fn(&NeonCore) -> Result<Option<T>, Error>) -> Result<T, Error> {
+        match self.core.read() {
+            Ok(core) => match read(&core) {
+                Ok(Some(data)) => Ok(data),
+                Ok(None) => Err(Error::new(ErrorKind::WouldBlock, "No message ready")),
+                Err(err) => Err(err),
+            },
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    /*
+        Reads the next message into buf, returns the length and whether it was truncated
+        Like a udp socket the part of a message that doesn't fit is discarded
+    */
+    pub fn read_into(&self, buf: &mut [u8]) -> Result<(usize, bool), Error> {
+        let data = self.read()?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, data.len() > buf.len()))
+    }
+    /*
+        Datagrams are sent once in with the data and never resent
+        Too big for the mss is InvalidInput, they aren't split up
+    */
+    pub fn send_datagram(&self, bytes: &[u8]) -> Result<(), Error> {
+        if !self.workers.running() {
+            return Err(Error::new(ErrorKind::NotConnected, "Core stopped"));
+        }
+        match self.core.write() {
+            Ok(mut core) => core.send_datagram(self.socket_id, self.channel, bytes),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Blocks until a datagram arrives, they don't wait on messages or each other
+    pub fn recv_datagram(&self) -> Result<Vec<u8>, Error> {
+        self.read_until(None, |core| core.read_datagram(self.socket_id, self.channel))
+    }
+    pub fn recv_datagram_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.read_until(Some(timeout), |core| core.read_datagram(self.socket_id, self.channel))
+    }
+    pub fn try_recv_datagram(&self) -> Result<Vec<u8>, Error> {
+        self.try_read_with(|core| core.read_datagram(self.socket_id, self.channel))
+    }
+    pub fn channel(&self) -> u16 {
+        self.channel
+    }
+    //Messages on the new channel get their own order, channel 0 is this stream
+    pub fn open_channel(&self, channel: u16) -> Result<NeonStream, Error> {
+        if channel == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Channel 0 is the stream"));
+        }
+        if channel > MAX_CHANNEL {
+            return Err(Error::new(ErrorKind::InvalidInput, "Channel out of range"));
+        }
+        match self.core.read() {
+            Ok(core) => core.open_channel(self.socket_id, channel)?,
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+        Ok(self.with_channel(channel))
+    }
+    //Blocks until the partner sends on a channel we haven't opened
+    pub fn accept_channel(&self) -> Result<NeonStream, Error> {
+        let channel = self.read_until(None, |core| core.next_channel(self.socket_id))?;
+        Ok(self.with_channel(channel))
+    }
+    pub fn accept_channel_timeout(&self, timeout: Duration) -> Result<NeonStream, Error> {
+        let channel = self.read_until(Some(timeout), |core| core.next_channel(self.socket_id))?;
+        Ok(self.with_channel(channel))
+    }
+    pub fn try_accept_channel(&self) -> Result<NeonStream, Error> {
+        let channel = self.try_read_with(|core| core.next_channel(self.socket_id))?;
+        Ok(self.with_channel(channel))
+    }
+    fn with_channel(&self, channel: u16) -> NeonStream {
+        Self::on_channel(self.socket_id, channel, self.core.clone(), self.workers.clone())
+    }
+    fn read_until<T>(
+        &self,
+        timeout: Option<Duration>,
+        read: impl Fn(&NeonCore) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
+        let start = SystemTime::now();
         loop {
-            if let Ok(mut core) = self.core.write() { if let Some(data) = core.read_data(socket_id) {
-                return data;
-            } }
+            //the generation has to be taken before looking so a message landing in between still wakes us
+            let (signal, generation) = match self.core.read() {
+                Ok(core) => {
+                    let signal = match core.recv_signal() {
+                        Some(signal) => signal,
+                        None => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+                    };
+                    let generation = signal.generation();
+                    if let Some(data) = read(&core)? {
+                        return Ok(data);
+                    }
+                    (signal, generation)
+                }
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            let remaining = match timeout {
+                Some(timeout) => match start.elapsed() {
+                    Ok(elapsed) if elapsed < timeout => Some(timeout - elapsed),
+                    Ok(_) => return Err(Error::new(ErrorKind::TimedOut, "No message before timeout")),
+                    Err(_) => Some(timeout),
+                },
+                None => None,
+            };
+            signal.wait(generation, remaining);
         }
     }
-    pub fn write(&self, bytes: &[u8], ttl: Duration, order: bool) -> Result<(), Error> {
+    //The id can be handed to receipt to find out what became of the message
+    //Blocks while the send buffer is full until acks make room for it, a ttl of NO_TTL never expires
+    pub fn write(&self, bytes: &[u8], ttl: Duration, order: bool) -> Result<MessageId, Error> {
+        self.write_priority(bytes, ttl, order, Priority::Normal)
+    }
+    //Higher classes go out ahead of lower ones already queued, lower ones still get a share
+    pub fn write_priority(
+        &self,
+        bytes: &[u8],
+        ttl: Duration,
+        order: bool,
+        priority: Priority,
+    ) -> Result<MessageId, Error> {
+        let options = MessageOptions {
+            ttl,
+            order,
+            channel: self.channel,
+            priority,
+        };
+        self.write_with(bytes, options, true)
+    }
+    //Never blocks, WouldBlock if the send buffer can't take it yet
+    pub fn try_write(&self, bytes: &[u8], ttl: Duration, order: bool) -> Result<MessageId, Error> {
+        let options = MessageOptions {
+            ttl,
+            order,
+            channel: self.channel,
+            priority: Priority::Normal,
+        };
+        self.write_with(bytes, options, false)
+    }
+    fn write_with(&self, bytes: &[u8], options: MessageOptions, wait: bool) -> Result<MessageId, Error> {
         let socket_id = self.socket_id;
-        //, addr: SocketAddr, data: &[u8], ttl: Duration, order: bool
+        loop {
+            //nothing would ever send it
+            if !self.workers.running() {
+                return Err(Error::new(ErrorKind::NotConnected, "Core stopped"));
+            }
+            //acks, drops and removals all notify the send signal
+            let (signal, generation) = match self.core.write() {
+                Ok(mut core) => {
+                    let signal = match core.send_signal() {
+                        Some(signal) => signal,
+                        None => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+                    };
+                    let generation = signal.generation();
+                    match core.send_data(socket_id, bytes, options) {
+                        Err(err) if wait && err.kind() == ErrorKind::WouldBlock => {}
+                        out => return out,
+                    }
+                    (signal, generation)
+                }
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            signal.wait(generation, None);
+        }
+    }
+    //Caps what the connection holds unacked, every channel on it shares the same buffer
+    pub fn set_send_limits(&self, packets: usize, bytes: usize) -> Result<(), Error> {
+        match self.core.read() {
+            Ok(core) => core.set_send_limits(self.socket_id, packets, bytes),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    /*
+        Runs on a worker thread once there's room again after a write was turned away
+        It's handed the stream to write from, it shouldn't take long since packets wait on it
+        The core only holds the stream weakly, so a hook never keeps the connection open
+    */
+    pub fn on_writable(&self, hook: impl Fn(&NeonStream) + Send + Sync + 'static) -> Result<(), Error> {
+        let core = Arc::downgrade(&self.core);
+        let workers = Arc::downgrade(&self.workers);
+        let handles = Arc::downgrade(&self.handles);
+        let (socket_id, channel) = (self.socket_id, self.channel);
+        let writable = move || {
+            if let Some(stream) = Self::revive(&core, &workers, &handles, socket_id, channel) {
+                hook(&stream);
+            }
+        };
+        match self.core.read() {
+            Ok(core) => core.on_writable(self.socket_id, Arc::new(writable)),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Another handle for a hook, only while the owner still has one
+    fn revive(
+        core: &Weak<RwLock<NeonCore>>,
+        workers: &Weak<NeonWorkers>,
+        handles: &Weak<AtomicUsize>,
+        socket_id: u16,
+        channel: u16,
+    ) -> Option<NeonStream> {
+        let (core, workers, handles) = (core.upgrade()?, workers.upgrade()?, handles.upgrade()?);
+        handles
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| (count > 0).then_some(count + 1))
+            .ok()?;
+        let mut stream = Self::on_channel(socket_id, channel, core, workers);
+        stream.handles = handles;
+        Some(stream)
+    }
+    //Never blocks, None while the message is still in flight
+    pub fn receipt(&self, id: MessageId) -> Result<Option<Receipt>, Error> {
+        match self.core.read() {
+            Ok(core) => core.receipt(self.socket_id, id),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Blocks until the message is acked, dropped or the connection is lost
+    pub fn wait_receipt(&self, id: MessageId) -> Result<Receipt, Error> {
+        self.receipt_until(id, None)
+    }
+    //Like wait_receipt but gives up with TimedOut
+    pub fn wait_receipt_timeout(&self, id: MessageId, timeout: Duration) -> Result<Receipt, Error> {
+        self.receipt_until(id, Some(timeout))
+    }
+    fn receipt_until(&self, id: MessageId, timeout: Option<Duration>) -> Result<Receipt, Error> {
+        let start = SystemTime::now();
+        loop {
+            //acks, drops and removals all notify the send signal
+            let (signal, generation) = match self.core.read() {
+                Ok(core) => {
+                    let signal = match core.send_signal() {
+                        Some(signal) => signal,
+                        None => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+                    };
+                    let generation = signal.generation();
+                    if let Some(receipt) = core.receipt(self.socket_id, id)? {
+                        return Ok(receipt);
+                    }
+                    (signal, generation)
+                }
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            let remaining = match timeout {
+                Some(timeout) => match start.elapsed() {
+                    Ok(elapsed) if elapsed < timeout => Some(timeout - elapsed),
+                    Ok(_) => return Err(Error::new(ErrorKind::TimedOut, "No receipt before timeout")),
+                    Err(_) => Some(timeout),
+                },
+                None => None,
+            };
+            signal.wait(generation, remaining);
+        }
+    }
+    /*
+        Streams a source of any size as a run of ordered segments on this channel
+        Only SEGMENT_WINDOW segments are held at once, read_to on the other side puts them back together
+        The channel is the transfer's alone, open one for it so other messages aren't taken for segments
+    */
+    pub fn write_from(&self, mut source: impl Read, ttl: Duration) -> Result<u64, Error> {
+        self.transfer_only()?;
+        let mut in_flight = VecDeque::new();
+        let mut total = 0;
+        let mut segment = vec![0u8; 1 + SEGMENT_SIZE];
+        loop {
+            //fill the whole segment so short reads don't turn into short messages
+            let mut len = 0;
+            while len < SEGMENT_SIZE {
+                match source.read(&mut segment[1 + len..]) {
+                    Ok(0) => break,
+                    Ok(read) => len += read,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            let last = len < SEGMENT_SIZE;
+            segment[0] = if last { SEGMENT_LAST } else { SEGMENT_MORE };
+            if in_flight.len() >= SEGMENT_WINDOW {
+                if let Some(id) = in_flight.pop_front() {
+                    self.settle(id)?;
+                }
+            }
+            in_flight.push_back(self.write(&segment[..1 + len], ttl, true)?);
+            total += len as u64;
+            if last {
+                break;
+            }
+        }
+        in_flight
+            .into_iter()
+            .try_for_each(|id| self.settle(id))?;
+        Ok(total)
+    }
+    fn settle(&self, id: MessageId) -> Result<(), Error> {
+        match self.wait_receipt(id)? {
+            Receipt::Acked => Ok(()),
+            Receipt::Dropped(_) => Err(Error::new(ErrorKind::TimedOut, "Expired before an ack")),
+            Receipt::Shed => Err(Error::new(ErrorKind::TimedOut, "Shed unread by the partner")),
+            Receipt::ConnectionLost => Err(Error::new(ErrorKind::ConnectionAborted, "Connection lost")),
+        }
+    }
+    /*
+        The other end of write_from, each segment goes to the sink as soon as it's in
+        A segment that expired on the way is InvalidData, what the sink has stops short of it
+    */
+    pub fn read_to(&self, mut sink: impl Write) -> Result<u64, Error> {
+        self.transfer_only()?;
+        let mut total = 0;
+        loop {
+            let segment = self.read_intact()?;
+            //the flag byte leads every segment, nothing at all is eof
+            let (flag, data) = match segment.split_first() {
+                Some(split) => split,
+                None => return Err(Error::new(ErrorKind::UnexpectedEof, "Stream ended mid transfer")),
+            };
+            sink.write_all(data)?;
+            total += data.len() as u64;
+            if *flag == SEGMENT_LAST {
+                break;
+            }
+        }
+        sink.flush()?;
+        Ok(total)
+    }
+    fn transfer_only(&self) -> Result<(), Error> {
+        match self.channel {
+            0 => Err(Error::new(ErrorKind::InvalidInput, "Transfers need a channel of their own")),
+            _ => Ok(()),
+        }
+    }
+    pub fn mode(&self) -> StreamMode {
+        self.mode
+    }
+    fn bytes_only(&self) -> Result<(), Error> {
+        match self.mode {
+            StreamMode::Bytes => Ok(()),
+            StreamMode::Message => Err(Error::new(ErrorKind::Unsupported, "Stream is in message mode")),
+        }
+    }
+    /*
+        Packs what's been written into full packets, the tail waits for more unless it's all asked for
+        Chunks never expire, like tcp a byte stream is delivered whole or the connection fails
+    */
+    fn send_outgoing(&self, everything: bool) -> Result<(), Error> {
+        match self.outgoing.lock() {
+            Ok(outgoing) if outgoing.is_empty() => return Ok(()),
+            Ok(_) => {}
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+        let mss = match self.core.read() {
+            Ok(core) => match core.mss(self.socket_id) {
+                Some(mss) => mss as usize,
+                None => return Err(Error::new(ErrorKind::NotConnected, "No connection")),
+            },
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        //chunks are cut by what they compress to, that's what has to fit the packet
+        let ready = match self.outgoing.lock() {
+            Ok(mut outgoing) => {
+                let mut ready = Vec::new();
+                while !outgoing.is_empty() {
+                    let len = Self::fit(&outgoing, mss);
+                    //all of it fits in one, so it isn't a full packet yet
+                    if len == outgoing.len() && !everything {
+                        break;
+                    }
+                    ready.push(outgoing.drain(..len).collect::<Vec<_>>());
+                }
+                ready
+            }
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        ready.iter().try_for_each(|chunk| {
+            //bounded like write_from so a fast writer can't fill the send buffer
+            self.settle_until(STREAM_WINDOW - 1)?;
+            let id = self.write(chunk, NO_TTL, true)?;
+            match self.in_flight.lock() {
+                Ok(mut in_flight) => {
+                    in_flight.push_back(id);
+                    Ok(())
+                }
+                Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            }
+        })
+    }
+    //The most of bytes that compresses into one packet, scaled by how the last try compressed
+    fn fit(bytes: &[u8], mss: usize) -> usize {
+        let mut fits = 0;
+        let mut len = bytes.len().min(mss);
+        for _ in 0..FIT_TRIES {
+            let packed = DataPacket::compress(&bytes[..len]).len();
+            let next = if packed <= mss {
+                fits = len;
+                (len * mss / packed).min(bytes.len())
+            } else {
+                len * mss / packed
+            };
+            if next == len || next <= fits {
+                break;
+            }
+            len = next;
+        }
+        //the tree is what didn't fit, halve until it does
+        while fits == 0 {
+            len = (len / 2).max(1);
+            if DataPacket::compress(&bytes[..len]).len() <= mss || len == 1 {
+                fits = len;
+            }
+        }
+        fits
+    }
+    //Waits on the oldest packets until no more than limit are in flight
+    fn settle_until(&self, limit: usize) -> Result<(), Error> {
+        loop {
+            let oldest = match self.in_flight.lock() {
+                Ok(mut in_flight) if in_flight.len() > limit => in_flight.pop_front(),
+                Ok(_) => return Ok(()),
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            if let Some(id) = oldest {
+                self.settle(id)?;
+            }
+        }
+    }
+    pub fn dropped(&self) -> Result<DropCounts, Error> {
+        match self.core.read() {
+            Ok(core) => match core.dropped(self.socket_id) {
+                Some((sent, sent_shed, received, shed)) => Ok(DropCounts {
+                    sent,
+                    sent_shed,
+                    received,
+                    shed,
+                }),
+                None => Err(Error::new(ErrorKind::NotConnected, "No connection")),
+            },
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //The wire version and the capabilities both sides agreed on in the handshake
+    pub fn negotiated(&self) -> Result<(u16, u32), Error> {
+        match self.core.read() {
+            Ok(core) => match core.negotiated(self.socket_id) {
+                Some(negotiated) => Ok(negotiated),
+                None => Err(Error::new(ErrorKind::NotConnected, "No connection")),
+            },
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Microseconds the partner's clock reads ahead of ours and the round trip it was measured over
+    pub fn clock_offset(&self) -> Result<(i32, Duration), Error> {
+        match self.core.read() {
+            Ok(core) => match core.clock_offset(self.socket_id) {
+                Some(offset) => Ok(offset),
+                None => Err(Error::new(ErrorKind::NotConnected, "No connection")),
+            },
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    /*
+        Waits up to the linger time for everything written to be acknowledged
+        then tells the partner and forgets the connection, safe to call more than once
+    */
+    pub fn close(&self) -> Result<(), Error> {
+        //the tail of a byte stream goes out first, the connection closes either way
+        let flushed = match self.mode {
+            StreamMode::Bytes => self.send_outgoing(true),
+            StreamMode::Message => Ok(()),
+        };
+        //a channel going away leaves the rest of the connection alone
+        if self.channel != 0 {
+            return flushed;
+        }
+        Self::close_on(&self.core, &self.workers, self.socket_id)?;
+        flushed
+    }
+    fn close_on(core: &RwLock<NeonCore>, workers: &NeonWorkers, socket_id: u16) -> Result<(), Error> {
+        Self::linger_on(core, workers, socket_id)?;
+        match core.write() {
+            Ok(mut core) => {
+                core.close(socket_id);
+                Ok(())
+            }
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    //Write lingers like close then lets the partner read to eof, Read makes our reads eof
+    pub fn shutdown(&self, how: Shutdown) -> Result<(), Error> {
+        match how {
+            Shutdown::Read => {}
+            Shutdown::Write | Shutdown::Both => {
+                if self.mode == StreamMode::Bytes {
+                    self.send_outgoing(true)?;
+                }
+                self.linger()?
+            }
+        }
         match self.core.write() {
-            Ok(mut core) => core.send_data(socket_id, bytes, ttl, order),
+            Ok(mut core) => core.shutdown(self.socket_id, how),
             Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
         }
     }
+    fn linger(&self) -> Result<(), Error> {
+        Self::linger_on(&self.core, &self.workers, self.socket_id)
+    }
+    fn linger_on(core: &RwLock<NeonCore>, workers: &NeonWorkers, socket_id: u16) -> Result<(), Error> {
+        let start = SystemTime::now();
+        loop {
+            //a stopped core won't send anything more
+            if !workers.running() {
+                return Ok(());
+            }
+            let (signal, generation, linger) = match core.read() {
+                Ok(core) => {
+                    let signal = match core.send_signal() {
+                        Some(signal) => signal,
+                        None => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+                    };
+                    let generation = signal.generation();
+                    match core.unacked(socket_id) {
+                        Some(0) | None => return Ok(()),
+                        Some(_) => {}
+                    }
+                    (signal, generation, core.config().linger)
+                }
+                Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+            };
+            //past the linger whatever is left is given up on
+            let remaining = match start.elapsed() {
+                Ok(elapsed) if elapsed < linger => linger - elapsed,
+                Ok(_) => return Ok(()),
+                Err(_) => linger,
+            };
+            signal.wait(generation, Some(remaining.min(LINGER_POLL)));
+        }
+    }
+}
+
+impl Read for NeonStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for NeonStream {
+    //Blocks for the next message when nothing is left, empty means eof, a hole in the bytes is InvalidData
+    fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        self.bytes_only()?;
+        let empty = match self.incoming.get_mut() {
+            Ok(incoming) => incoming.is_empty(),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        };
+        if empty {
+            let data = match self.read_intact() {
+                Ok(data) => data,
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => vec![],
+                Err(err) => return Err(err),
+            };
+            if let Ok(incoming) = self.incoming.get_mut() {
+                incoming.extend(data);
+            }
+        }
+        match self.incoming.get_mut() {
+            Ok(incoming) => Ok(incoming.as_slices().0),
+            Err(_) => Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+    }
+    fn consume(&mut self, amt: usize) {
+        if let Ok(incoming) = self.incoming.get_mut() {
+            incoming.drain(..amt.min(incoming.len()));
+        }
+    }
+}
+
+impl Write for NeonStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.bytes_only()?;
+        match self.outgoing.get_mut() {
+            Ok(outgoing) => outgoing.extend_from_slice(buf),
+            Err(_) => return Err(Error::new(ErrorKind::Interrupted, "Poisoned")),
+        }
+        self.send_outgoing(false)?;
+        Ok(buf.len())
+    }
+    //Sends the partial packet and waits until everything written is acked
+    fn flush(&mut self) -> Result<(), Error> {
+        self.bytes_only()?;
+        self.send_outgoing(true)?;
+        self.settle_until(0)
+    }
+}
+
+impl Drop for NeonStream {
+    //Only the last handle closes, the others just send what they have left
+    fn drop(&mut self) {
+        match self.handles.fetch_sub(1, Ordering::AcqRel) {
+            1 => {
+                if self.mode == StreamMode::Bytes {
+                    let _ = self.send_outgoing(true);
+                }
+                //a channel going away leaves the rest of the connection alone
+                if self.channel != 0 {
+                    return;
+                }
+                //nothing to wait on closes here, so the port is free once the last owner is gone
+                let idle = match self.core.read() {
+                    Ok(core) => matches!(core.unacked(self.socket_id), Some(0) | None),
+                    Err(_) => true,
+                };
+                if idle {
+                    let _ = Self::close_on(&self.core, &self.workers, self.socket_id);
+                    return;
+                }
+                //the linger can take seconds, a worker waits it out so dropping never blocks
+                let core = self.core.clone();
+                let workers = self.workers.clone();
+                let socket_id = self.socket_id;
+                self.workers.spawn(move || {
+                    let _ = Self::close_on(&core, &workers, socket_id);
+                });
+            }
+            _ => {
+                if self.mode == StreamMode::Bytes {
+                    let _ = self.send_outgoing(true);
+                }
+            }
+        }
+    }
+}
+
+impl NeonReadHalf {
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        self.stream.read()
+    }
+    pub fn read_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.stream.read_timeout(timeout)
+    }
+    pub fn try_read(&self) -> Result<Vec<u8>, Error> {
+        self.stream.try_read()
+    }
+    pub fn read_into(&self, buf: &mut [u8]) -> Result<(usize, bool), Error> {
+        self.stream.read_into(buf)
+    }
+    pub fn read_to(&self, sink: impl Write) -> Result<u64, Error> {
+        self.stream.read_to(sink)
+    }
+    pub fn recv_datagram(&self) -> Result<Vec<u8>, Error> {
+        self.stream.recv_datagram()
+    }
+    pub fn recv_datagram_timeout(&self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.stream.recv_datagram_timeout(timeout)
+    }
+    pub fn try_recv_datagram(&self) -> Result<Vec<u8>, Error> {
+        self.stream.try_recv_datagram()
+    }
+    pub fn channel(&self) -> u16 {
+        self.stream.channel()
+    }
+    pub fn mode(&self) -> StreamMode {
+        self.stream.mode()
+    }
+    pub fn dropped(&self) -> Result<DropCounts, Error> {
+        self.stream.dropped()
+    }
+}
+
+impl Read for NeonReadHalf {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        Read::read(&mut self.stream, buf)
+    }
+}
+
+impl BufRead for NeonReadHalf {
+    fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        self.stream.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.stream.consume(amt)
+    }
+}
+
+impl NeonWriteHalf {
+    pub fn write(&self, bytes: &[u8], ttl: Duration, order: bool) -> Result<MessageId, Error> {
+        self.stream.write(bytes, ttl, order)
+    }
+    pub fn write_priority(
+        &self,
+        bytes: &[u8],
+        ttl: Duration,
+        order: bool,
+        priority: Priority,
+    ) -> Result<MessageId, Error> {
+        self.stream.write_priority(bytes, ttl, order, priority)
+    }
+    pub fn try_write(&self, bytes: &[u8], ttl: Duration, order: bool) -> Result<MessageId, Error> {
+        self.stream.try_write(bytes, ttl, order)
+    }
+    pub fn write_from(&self, source: impl Read, ttl: Duration) -> Result<u64, Error> {
+        self.stream.write_from(source, ttl)
+    }
+    pub fn send_datagram(&self, bytes: &[u8]) -> Result<(), Error> {
+        self.stream.send_datagram(bytes)
+    }
+    pub fn receipt(&self, id: MessageId) -> Result<Option<Receipt>, Error> {
+        self.stream.receipt(id)
+    }
+    pub fn wait_receipt(&self, id: MessageId) -> Result<Receipt, Error> {
+        self.stream.wait_receipt(id)
+    }
+    pub fn wait_receipt_timeout(&self, id: MessageId, timeout: Duration) -> Result<Receipt, Error> {
+        self.stream.wait_receipt_timeout(id, timeout)
+    }
+    pub fn set_send_limits(&self, packets: usize, bytes: usize) -> Result<(), Error> {
+        self.stream.set_send_limits(packets, bytes)
+    }
+    pub fn on_writable(&self, hook: impl Fn(&NeonStream) + Send + Sync + 'static) -> Result<(), Error> {
+        self.stream.on_writable(hook)
+    }
+    pub fn channel(&self) -> u16 {
+        self.stream.channel()
+    }
+    pub fn mode(&self) -> StreamMode {
+        self.stream.mode()
+    }
+    //Lingers then lets the partner read to eof, the read half keeps working
+    pub fn shutdown(&self) -> Result<(), Error> {
+        self.stream.shutdown(Shutdown::Write)
+    }
+}
+
+impl Write for NeonWriteHalf {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        Write::write(&mut self.stream, buf)
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        Write::flush(&mut self.stream)
+    }
 }
@@ -2,17 +2,29 @@
 
 //#[cfg(test)]
 pub mod single {
+    use crate::config::{NeonConfig, Scheduling, StreamMode, CAP_PMTU};
     use crate::core::channel::MAX_PACKET_SIZE;
     use crate::listener::NeonListener;
-    use crate::stream::NeonStream;
-    use std::{net::SocketAddr, thread, time::Duration};
+    use crate::packet::control::err::ERR_VERSION;
+    use crate::packet::control::handshake::{ReqType, CAPABILITIES, WIRE_VERSION};
+    use crate::packet::control::{ControlMeta, ControlPacket, ControlPacketInfo, ControlType};
+    use crate::packet::Packet;
+    use crate::serial::Serial;
+    use crate::stream::{NeonStream, Priority, Receipt};
+    use std::{
+        io::{self, BufRead, ErrorKind, Read, Write},
+        net::{Shutdown, SocketAddr, UdpSocket},
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
 
     //Start a server and exit when the first connection spawns
     pub fn handshake() {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept();
             thread::sleep(Duration::from_micros(1000));
@@ -21,7 +33,7 @@ pub mod single {
         //connect to it with a client
         let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
         let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::simplex(bind, 4, Duration::from_millis(100), target);
+        let client = NeonStream::simplex(bind, 4, Duration::from_millis(100), target, NeonConfig::default());
         thread::sleep(Duration::from_micros(1000));
         match  &client {
             Ok(_) => {},
@@ -36,11 +48,11 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
             thread::sleep(Duration::from_millis(100));
-            let data = stream.read();
+            let data = stream.read().unwrap();
 
             data.iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
@@ -51,7 +63,7 @@ pub mod single {
         //connect to it with a client
         let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
         let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
         thread::sleep(Duration::from_millis(100));
         assert!(handle.join().is_ok())
@@ -61,10 +73,10 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE)
                 .flat_map(|i| (i % 32).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -79,7 +91,7 @@ pub mod single {
         let data = (0..MAX_PACKET_SIZE)
             .flat_map(|i| (i % 32).to_le_bytes())
             .collect::<Vec<_>>();
-        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(&data, Duration::from_millis(100), true);
         thread::sleep(Duration::from_millis(1000));
         assert!(handle.join().is_ok())
@@ -88,10 +100,10 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE * 2)
                 .flat_map(|i| (i % 128).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -106,7 +118,7 @@ pub mod single {
         let data = (0..MAX_PACKET_SIZE * 2)
             .flat_map(|i| (i % 128).to_le_bytes())
             .collect::<Vec<_>>();
-        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(&data, Duration::from_millis(100), true);
         thread::sleep(Duration::from_millis(100));
         assert!(handle.join().is_ok())
@@ -116,10 +128,10 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             data.iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
                 .for_each(|(a, b)| {
@@ -134,9 +146,9 @@ pub mod single {
         //connect to it with a client
         let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
         let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
-        let reply = client.read();
+        let reply = client.read().unwrap();
         reply
             .iter()
             .zip([1, 1, 2, 3, 5, 8, 13, 21].iter())
@@ -150,10 +162,10 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE)
                 .flat_map(|i| (i % 32).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -172,7 +184,7 @@ pub mod single {
         //connect to it with a client
         let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
         let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(
             &(0..MAX_PACKET_SIZE)
                 .flat_map(|i| (i % 32).to_le_bytes())
@@ -180,7 +192,7 @@ pub mod single {
             Duration::from_millis(100),
             true,
         );
-        let reply = client.read();
+        let reply = client.read().unwrap();
         reply
             .iter()
             .zip(
@@ -200,10 +212,10 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE * 5)
                 .flat_map(|i| (i % 128).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -222,7 +234,7 @@ pub mod single {
         //connect to it with a client
         let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
         let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(
             &(0..MAX_PACKET_SIZE * 5)
                 .flat_map(|i| (i % 128).to_le_bytes())
@@ -230,7 +242,7 @@ pub mod single {
             Duration::from_millis(100),
             true,
         );
-        let reply = client.read();
+        let reply = client.read().unwrap();
         reply
             .iter()
             .zip(
@@ -250,7 +262,7 @@ pub mod single {
         //start a server in a new thread
         let handle_server = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream_one = server.accept();
             let stream_two = server.accept();
@@ -261,7 +273,7 @@ pub mod single {
         let handle_client_one = thread::spawn(|| {
             let bind = "127.0.0.1:8000".parse::<SocketAddr>().unwrap();
             let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target);
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default());
             assert!(client.is_ok());
 
             thread::sleep(Duration::from_millis(100));
@@ -269,7 +281,7 @@ pub mod single {
         let handle_client_two = thread::spawn(|| {
             let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
             let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target);
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default());
             assert!(client.is_ok());
             thread::sleep(Duration::from_millis(100));
         });
@@ -282,19 +294,19 @@ pub mod single {
         //start a server in a new thread
         let handle_server = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream_one = server.accept().unwrap();
             let stream_two = server.accept().unwrap();
 
-            let data_one = stream_one.read();
+            let data_one = stream_one.read().unwrap();
             data_one
                 .iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
                 .for_each(|(a, b)| {
                     assert!(a == b);
                 });
-            let data_two = stream_two.read();
+            let data_two = stream_two.read().unwrap();
             data_two
                 .iter()
                 .zip([1, 1, 2, 3, 5, 8, 13, 21].iter())
@@ -306,7 +318,7 @@ pub mod single {
         let handle_client_one = thread::spawn(|| {
             let bind = "127.0.0.1:8000".parse::<SocketAddr>().unwrap();
             let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
             thread::sleep(Duration::from_millis(100));
         });
@@ -315,7 +327,7 @@ pub mod single {
         let handle_client_two = thread::spawn(|| {
             let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
             let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(
                 &[1, 1, 2, 3, 5, 8, 13, 21],
                 Duration::from_millis(100),
@@ -333,12 +345,12 @@ pub mod single {
         //start a server in a new thread
         let handle_server = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream_one = server.accept().unwrap();
             let stream_two = server.accept().unwrap();
 
-            let data_one = stream_one.read();
+            let data_one = stream_one.read().unwrap();
             dbg!(&data_one);
             data_one
                 .iter()
@@ -346,7 +358,7 @@ pub mod single {
                 .for_each(|(a, b)| {
                     assert!(a == b);
                 });
-            let data_two = stream_two.read();
+            let data_two = stream_two.read().unwrap();
             data_two
                 .iter()
                 .zip([1, 1, 2, 3, 5, 8, 13, 21].iter())
@@ -367,9 +379,9 @@ pub mod single {
         let handle_client_one = thread::spawn(|| {
             let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
             let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
-            let reply = client.read();
+            let reply = client.read().unwrap();
             reply
                 .iter()
                 .zip([0, 2, 4, 8, 16, 32, 64, 128].iter())
@@ -381,13 +393,13 @@ pub mod single {
         let handle_client_two = thread::spawn(|| {
             let bind = "127.0.0.1:8193".parse::<SocketAddr>().unwrap();
             let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(
                 &[1, 1, 2, 3, 5, 8, 13, 21],
                 Duration::from_millis(100),
                 true,
             );
-            let reply = client.read();
+            let reply = client.read().unwrap();
             reply
                 .iter()
                 .zip([1, 3, 5, 9, 17, 33, 64, 129].iter())
@@ -405,7 +417,7 @@ pub mod single {
         //start a server in a new thread
         let handle = thread::spawn(|| {
             let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::simplex(addr).unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept();
             //idle here for a while
@@ -415,16 +427,775 @@ pub mod single {
         //connect to it with a client
         let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
         let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
-        let _client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target);
+        let _client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default());
         //idle here for a while
         thread::sleep(Duration::from_secs(3));
         assert!(handle.join().is_ok())
     }
+
+    //The epoch is resynced many times over while messages keep flowing both ways
+    pub fn resync() {
+        let config = NeonConfig::new()
+            .keep_alive_interval(Duration::from_millis(50))
+            .timing_interval(Duration::from_millis(200));
+        let handle = thread::spawn(move || {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, config).unwrap();
+            let stream = server.accept().unwrap();
+            (0..20).for_each(|_| {
+                let data = stream.read().unwrap();
+                stream.write(&data, Duration::from_secs(5), true).unwrap();
+            });
+            let (offset, delay) = stream.clock_offset().unwrap();
+            assert!(delay < Duration::from_secs(1));
+            assert!(offset.unsigned_abs() < 1_000_000);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        (0..20u8).for_each(|i| {
+            client.write(&[i; 64], Duration::from_secs(5), true).unwrap();
+            assert!(client.read_timeout(Duration::from_secs(5)).unwrap() == vec![i; 64]);
+            thread::sleep(Duration::from_millis(100));
+        });
+        //a round trip has been measured against some resync
+        let (offset, delay) = client.clock_offset().unwrap();
+        assert!(delay > Duration::ZERO);
+        assert!(delay < Duration::from_secs(1));
+        assert!(offset.unsigned_abs() < 1_000_000);
+        assert!(handle.join().is_ok())
+    }
+
+    //Features only one side offers stay off and a peer with no version in common is told why
+    pub fn capabilities() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            assert!(stream.negotiated().unwrap() == (WIRE_VERSION, CAP_PMTU));
+            let err = stream.send_datagram(&[1, 2, 3]).unwrap_err();
+            assert!(err.kind() == ErrorKind::Unsupported);
+            //a handshake from before our oldest version
+            let raw = UdpSocket::bind("127.0.0.1:9100").unwrap();
+            raw.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let local = raw.local_addr().unwrap();
+            let mut packet =
+                ControlPacket::handshake(u16::MAX, ReqType::Connection, 1024, 64, 1, local, (WIRE_VERSION, CAPABILITIES));
+            if let ControlPacketInfo::Handshake(info) = &mut packet.info {
+                info.version = 0;
+                info.min_version = 0;
+            }
+            raw.send_to(&Packet::Control(packet).serialize(), addr).unwrap();
+            let mut bytes = [0u8; MAX_PACKET_SIZE as usize];
+            let (count, _) = raw.recv_from(&mut bytes).unwrap();
+            match Packet::deserialize(&bytes[..count], &mut 0) {
+                Packet::Control(packet) => {
+                    assert!(packet.control_type == ControlType::Err);
+                    assert!(matches!(packet.meta, ControlMeta::Other(ERR_VERSION)));
+                }
+                Packet::Data(_) => panic!("Not a refusal"),
+            }
+            assert!(stream.read().unwrap() == vec![1, 2, 3]);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let config = NeonConfig::default().capabilities(CAP_PMTU);
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        assert!(client.negotiated().unwrap() == (WIRE_VERSION, CAP_PMTU));
+        let err = client.send_datagram(&[1, 2, 3]).unwrap_err();
+        assert!(err.kind() == ErrorKind::Unsupported);
+        client.write(&[1, 2, 3], Duration::from_secs(5), true).unwrap();
+        assert!(handle.join().is_ok())
+    }
+
+    //A client held to version 1 gets 15 bit seqs and 13 bit msg nos both ways, widened again on arrival
+    pub fn narrow_version() {
+        let data = (0..MAX_PACKET_SIZE * 64)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect::<Vec<_>>();
+        let check = data.clone();
+        let handle = thread::spawn(move || {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            assert!(stream.negotiated().unwrap().0 == 1);
+            for i in 0..16u8 {
+                assert!(stream.read().unwrap() == [i; 8]);
+            }
+            assert!(stream.read().unwrap() == check);
+            stream.write(&check, Duration::from_secs(5), true).unwrap();
+            assert!(stream.read().unwrap() == [0xff]);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let config = NeonConfig::default().wire_version(1);
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        assert!(client.negotiated().unwrap().0 == 1);
+        for i in 0..16u8 {
+            client.write(&[i; 8], Duration::from_secs(5), true).unwrap();
+        }
+        client.write(&data, Duration::from_secs(5), true).unwrap();
+        assert!(client.read().unwrap() == data);
+        client.write(&[0xff], Duration::from_secs(5), true).unwrap();
+        assert!(handle.join().is_ok())
+    }
+
+    //Both sides agree on a larger mss and a faster ack interval
+    pub fn custom_config() {
+        let config = NeonConfig::new()
+            .mss(512)
+            .ack_interval(Duration::from_millis(5))
+            .congestion_window(32, 64);
+        let handle = thread::spawn(move || {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, config).unwrap();
+            let stream = server.accept().unwrap();
+            let data = stream.read().unwrap();
+            let check_data = (0..MAX_PACKET_SIZE * 8)
+                .flat_map(|i| (i % 128).to_le_bytes())
+                .collect::<Vec<_>>();
+            data.iter().zip(check_data.iter()).for_each(|(a, b)| {
+                assert!(a == b);
+            });
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let data = (0..MAX_PACKET_SIZE * 8)
+            .flat_map(|i| (i % 128).to_le_bytes())
+            .collect::<Vec<_>>();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        let _ = client.write(&data, Duration::from_millis(1000), true);
+        assert!(handle.join().is_ok())
+    }
+    pub fn path_mtu() {
+        //the state thread probes faster so the search is done before the write
+        let config = NeonConfig::new().keep_alive_interval(Duration::from_millis(50));
+        let handle = thread::spawn(move || {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, config).unwrap();
+            let stream = server.accept().unwrap();
+            let data = stream.read().unwrap();
+            let check_data = (0..MAX_PACKET_SIZE * 64)
+                .flat_map(|i| (i % 128).to_le_bytes())
+                .collect::<Vec<_>>();
+            data.iter().zip(check_data.iter()).for_each(|(a, b)| {
+                assert!(a == b);
+            });
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let data = (0..MAX_PACKET_SIZE * 64)
+            .flat_map(|i| (i % 128).to_le_bytes())
+            .collect::<Vec<_>>();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        let _ = client.write(&data, Duration::from_millis(1000), true);
+        assert!(handle.join().is_ok())
+    }
+    pub fn read_modes() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            //nothing has been sent yet
+            let err = stream.try_read().unwrap_err();
+            assert!(err.kind() == ErrorKind::WouldBlock);
+            let err = stream.read_timeout(Duration::from_millis(50)).unwrap_err();
+            assert!(err.kind() == ErrorKind::TimedOut);
+            //the client sends after the timeout
+            let mut buf = [0u8; 4];
+            let (len, truncated) = stream.read_into(&mut buf).unwrap();
+            assert!(len == 4 && truncated);
+            assert!(buf == [1, 2, 3, 4]);
+            let mut buf = [0u8; 16];
+            let (len, truncated) = stream.read_into(&mut buf).unwrap();
+            assert!(len >= 2 && !truncated);
+            assert!(buf[..2] == [1, 2]);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
+        let _ = client.write(&[1, 2], Duration::from_millis(100), true);
+        assert!(handle.join().is_ok())
+    }
+    pub fn expiry() {
+        let (read, wait_read) = mpsc::channel::<()>();
+        let (checked, wait_checked) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            //the expired message is dropped so the one behind it isn't stuck
+            let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [4, 5, 6]);
+            assert!(stream.dropped().unwrap().received == 1);
+            //closing would take the client's send side with it
+            let _ = read.send(());
+            let _ = wait_checked.recv_timeout(Duration::from_secs(2));
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.write(&[1, 2, 3], Duration::ZERO, true).unwrap();
+        client.write(&[4, 5, 6], Duration::from_secs(5), true).unwrap();
+        let _ = wait_read.recv_timeout(Duration::from_secs(4));
+        let data = client.dropped();
+        let _ = checked.send(());
+        assert!(handle.join().is_ok());
+        assert!(data.unwrap().sent == 1);
+    }
+    pub fn receipts() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [1, 2, 3]);
+            let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [7, 8, 9]);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let acked = client.write(&[1, 2, 3], Duration::from_secs(5), true).unwrap();
+        let dropped = client.write(&[4, 5, 6], Duration::ZERO, true).unwrap();
+        let _ = client.write(&[7, 8, 9], Duration::from_secs(5), true).unwrap();
+        let receipt = client.wait_receipt_timeout(acked, Duration::from_secs(2)).unwrap();
+        assert!(receipt == Receipt::Acked);
+        let receipt = client.wait_receipt_timeout(dropped, Duration::from_secs(2)).unwrap();
+        assert!(receipt == Receipt::Dropped(Duration::ZERO));
+        assert!(handle.join().is_ok());
+    }
+    pub fn datagrams() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            //datagrams come out of their own queue
+            let data = stream.recv_datagram_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [4, 5, 6]);
+            let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [1, 2, 3]);
+            let err = stream.try_recv_datagram().unwrap_err();
+            assert!(err.kind() == ErrorKind::WouldBlock);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.write(&[1, 2, 3], Duration::from_secs(5), true).unwrap();
+        client.send_datagram(&[4, 5, 6]).unwrap();
+        //too big to go in one packet
+        let big = (0..4096).map(|i| i as u8).collect::<Vec<_>>();
+        let err = client.send_datagram(&big).unwrap_err();
+        assert!(err.kind() == ErrorKind::InvalidInput);
+        assert!(handle.join().is_ok());
+    }
+    pub fn channels() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            let control = stream.accept_channel_timeout(Duration::from_secs(2)).unwrap();
+            assert!(control.channel() == 5);
+            let data = control.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [4, 5, 6]);
+            let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+            data.iter().zip((0..20000).map(|i| (i % 251) as u8)).for_each(|(a, b)| {
+                assert!(*a == b);
+            });
+            //the control channel still has its own order
+            let data = control.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [7, 8, 9]);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(matches!(client.open_channel(0), Err(err) if err.kind() == ErrorKind::InvalidInput));
+        let control = client.open_channel(5).unwrap();
+        let bulk = (0..20000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        client.write(&bulk, Duration::from_secs(5), true).unwrap();
+        control.write(&[4, 5, 6], Duration::from_secs(5), true).unwrap();
+        control.write(&[7, 8, 9], Duration::from_secs(5), true).unwrap();
+        //dropping a channel leaves the stream open
+        drop(control);
+        assert!(handle.join().is_ok());
+    }
+    pub fn priorities() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            //the command was written last but doesn't wait behind the transfer
+            let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+            assert!(data[..3] == [4, 5, 6]);
+            let data = stream.read_timeout(Duration::from_secs(25)).unwrap();
+            data.iter().zip((0..200000).map(|i| (i % 251) as u8)).for_each(|(a, b)| {
+                assert!(*a == b);
+            });
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let bulk = (0..200000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        client.write_priority(&bulk, Duration::from_secs(10), false, Priority::Low).unwrap();
+        client.write_priority(&[4, 5, 6], Duration::from_secs(10), false, Priority::High).unwrap();
+        assert!(handle.join().is_ok());
+    }
+    pub fn deadlines() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            let mut firsts = (0..3)
+                .map(|_| stream.read_timeout(Duration::from_secs(2)).unwrap()[0])
+                .collect::<Vec<_>>();
+            firsts.sort();
+            assert!(firsts == [1, 4, 7]);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let config = NeonConfig::default().scheduling(Scheduling::Deadline);
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.write(&[1, 2, 3], Duration::from_secs(5), false).unwrap();
+        client.write(&[4, 5, 6], Duration::from_secs(2), false).unwrap();
+        client.write(&[7, 8, 9], Duration::from_secs(1), false).unwrap();
+        assert!(handle.join().is_ok());
+    }
+    pub fn coalescing() {
+        let (checked, wait_checked) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            //bundles come apart into the messages that were written, in order
+            (0..500u16).for_each(|i| {
+                let data = stream.read_timeout(Duration::from_secs(2)).unwrap();
+                assert!(data == i.to_be_bytes());
+            });
+            //stay up until the last acks are in
+            wait_checked.recv().unwrap();
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let config = NeonConfig::default().coalesce(Duration::from_millis(5));
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let ids = (0..500u16)
+            .map(|i| client.write(&i.to_be_bytes(), Duration::from_secs(5), true).unwrap())
+            .collect::<Vec<_>>();
+        //every message in a bundle gets its own receipt
+        ids.into_iter().for_each(|id| {
+            let receipt = client.wait_receipt_timeout(id, Duration::from_secs(2)).unwrap();
+            assert!(receipt == Receipt::Acked);
+        });
+        checked.send(()).unwrap();
+        assert!(handle.join().is_ok());
+    }
+    pub fn backpressure() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            //everything that was let in arrives, in order, up to the last marker
+            let mut next = 0u16;
+            loop {
+                let data = stream.read_timeout(Duration::from_secs(5)).unwrap();
+                if data == [0xff] {
+                    break;
+                }
+                assert!(data[..2] == next.to_be_bytes());
+                next += 1;
+            }
+            assert!(next > 100);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.set_send_limits(4, 4096).unwrap();
+        let (writable, wait_writable) = mpsc::channel::<()>();
+        let writable = std::sync::Mutex::new(writable);
+        client.on_writable(move |_| {
+            let _ = writable.lock().unwrap().send(());
+        }).unwrap();
+        //one that could never fit is refused outright
+        let big = (0..8192).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let err = client.try_write(&big, Duration::from_secs(5), true).unwrap_err();
+        assert!(err.kind() == ErrorKind::InvalidInput);
+        let mut next = 0u16;
+        let err = loop {
+            let mut data = next.to_be_bytes().to_vec();
+            data.extend_from_slice(&[0; 1000]);
+            match client.try_write(&data, Duration::from_secs(5), true) {
+                Ok(_) => next += 1,
+                Err(err) => break err,
+            }
+        };
+        assert!(err.kind() == ErrorKind::WouldBlock);
+        wait_writable.recv_timeout(Duration::from_secs(2)).unwrap();
+        //blocking writes wait their turn instead of failing
+        (0..100).for_each(|_| {
+            let mut data = next.to_be_bytes().to_vec();
+            data.extend_from_slice(&[0; 1000]);
+            client.write(&data, Duration::from_secs(5), true).unwrap();
+            next += 1;
+        });
+        client.write(&[0xff], Duration::from_secs(5), true).unwrap();
+        assert!(handle.join().is_ok());
+    }
+    //A hook that writes is handed the stream, so it doesn't keep the connection alive
+    pub fn writable_hook() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            while stream.read_timeout(Duration::from_secs(2)).is_ok() {}
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        client.set_send_limits(4, 4096).unwrap();
+        let (wrote, wait_wrote) = mpsc::channel::<()>();
+        let wrote = std::sync::Mutex::new(wrote);
+        client.on_writable(move |stream| {
+            if stream.try_write(&[0xfe; 1000], Duration::from_secs(5), true).is_ok() {
+                let _ = wrote.lock().unwrap().send(());
+            }
+        }).unwrap();
+        while client.try_write(&[0; 1000], Duration::from_secs(5), true).is_ok() {}
+        wait_wrote.recv_timeout(Duration::from_secs(2)).unwrap();
+        //no close, the last handle going is enough to free the port
+        drop(client);
+        let start = std::time::SystemTime::now();
+        while UdpSocket::bind(bind).is_err() {
+            assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(handle.join().is_ok());
+    }
+    pub fn slow_reader() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let config = NeonConfig::default().buffers(8192, 32);
+            let mut server = NeonListener::simplex(addr, config).unwrap();
+            let stream = server.accept().unwrap();
+            //the sender has to wait on the window rather than overrun the buffer
+            thread::sleep(Duration::from_millis(500));
+            (0..200u16).for_each(|i| {
+                let data = stream.read_timeout(Duration::from_secs(5)).unwrap();
+                assert!(data[..2] == i.to_be_bytes());
+            });
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        (0..200u16).for_each(|i| {
+            let mut data = i.to_be_bytes().to_vec();
+            data.extend((0..1000).map(|j| (j * 7 % 251) as u8));
+            client.write(&data, Duration::from_secs(10), true).unwrap();
+        });
+        assert!(handle.join().is_ok());
+    }
+    //Dropping doesn't wait on the linger, the core still delivers what was written
+    pub fn drop_linger() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let config = NeonConfig::default().buffers(8192, 32);
+            let mut server = NeonListener::simplex(addr, config).unwrap();
+            let stream = server.accept().unwrap();
+            thread::sleep(Duration::from_millis(500));
+            (0..40u16).for_each(|i| {
+                let data = stream.read_timeout(Duration::from_secs(5)).unwrap();
+                assert!(data[..2] == i.to_be_bytes());
+            });
+            let eof = stream.read_timeout(Duration::from_secs(2)).unwrap_err();
+            assert!(eof.kind() == ErrorKind::UnexpectedEof);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        (0..40u16).for_each(|i| {
+            let mut data = i.to_be_bytes().to_vec();
+            data.extend([7; 1000]);
+            client.write(&data, Duration::from_secs(10), true).unwrap();
+        });
+        //the reader is asleep so most of that is unacked
+        let start = std::time::SystemTime::now();
+        drop(client);
+        assert!(start.elapsed().unwrap() < Duration::from_millis(250));
+        assert!(handle.join().is_ok());
+    }
+    pub fn split() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let (reader, writer) = server.accept().unwrap().into_split();
+            //echo on one thread while the other reads
+            let (tx, rx) = std::sync::mpsc::channel::<Vec<u8>>();
+            let echo = thread::spawn(move || {
+                rx.iter().for_each(|data| {
+                    writer.write(&data, Duration::from_secs(5), true).unwrap();
+                });
+            });
+            (0..20).for_each(|_| tx.send(reader.read().unwrap()).unwrap());
+            drop(tx);
+            echo.join().unwrap();
+            //the writer is gone but the stream lives on in the reader
+            let eof = reader.read_timeout(Duration::from_secs(2)).unwrap_err();
+            assert!(eof.kind() == ErrorKind::UnexpectedEof);
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        //two writers on their own threads, each one's messages stay in its order
+        let writers = (0..2u8)
+            .map(|id| {
+                let clone = client.try_clone().unwrap();
+                thread::spawn(move || {
+                    (0..10u8).for_each(|i| {
+                        clone.write(&[id, i], Duration::from_secs(5), true).unwrap();
+                    });
+                })
+            })
+            .collect::<Vec<_>>();
+        writers.into_iter().for_each(|writer| writer.join().unwrap());
+        let mut next = [0u8; 2];
+        (0..20).for_each(|_| {
+            let data = client.read_timeout(Duration::from_secs(5)).unwrap();
+            assert!(data[1] == next[data[0] as usize]);
+            next[data[0] as usize] += 1;
+        });
+        assert!(next == [10, 10]);
+        //the clones are gone, dropping the last handle closes the connection
+        drop(client);
+        assert!(handle.join().is_ok());
+    }
+    pub fn streaming() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            let transfer = stream.accept_channel_timeout(Duration::from_secs(5)).unwrap();
+            let mut sink = Vec::new();
+            let total = transfer.read_to(&mut sink).unwrap();
+            assert!(total == 600000);
+            //the sink gets exactly what went in, segment boundaries and all
+            assert!(sink == (0..600000).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let source = (0..600000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        //the stream's own messages would be taken for segments
+        let refused = client.write_from(&source[..], Duration::from_secs(10));
+        assert!(matches!(refused, Err(err) if err.kind() == ErrorKind::InvalidInput));
+        let transfer = client.open_channel(3).unwrap();
+        let total = transfer.write_from(&source[..], Duration::from_secs(10)).unwrap();
+        assert!(total == 600000);
+        assert!(handle.join().is_ok());
+    }
+    pub fn bytes() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let config = NeonConfig::default().mode(StreamMode::Bytes);
+            let mut server = NeonListener::simplex(addr, config).unwrap();
+            let mut stream = server.accept().unwrap();
+            //lines split and joined however the packets fell
+            (0..100).for_each(|i| {
+                let mut line = String::new();
+                stream.read_line(&mut line).unwrap();
+                assert!(line == format!("line {}\n", i));
+            });
+            let mut bulk = vec![0u8; 100000];
+            stream.read_exact(&mut bulk).unwrap();
+            assert!(bulk == (0..100000).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            assert!(rest.is_empty());
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let config = NeonConfig::default().mode(StreamMode::Bytes);
+        let mut client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, config).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        (0..100).for_each(|i| {
+            writeln!(client, "line {}", i).unwrap();
+        });
+        let bulk = (0..100000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        io::copy(&mut &bulk[..], &mut client).unwrap();
+        //everything is acked once flush returns
+        client.flush().unwrap();
+        client.close().unwrap();
+        assert!(handle.join().is_ok());
+    }
+    pub fn message_mode_io() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let mut stream = server.accept().unwrap();
+            let mut buf = [0u8; 8];
+            assert!(matches!(Read::read(&mut stream, &mut buf), Err(err) if err.kind() == ErrorKind::Unsupported));
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let mut client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        assert!(client.mode() == StreamMode::Message);
+        assert!(matches!(Write::write(&mut client, &[1, 2, 3]), Err(err) if err.kind() == ErrorKind::Unsupported));
+        assert!(handle.join().is_ok());
+    }
+    pub fn close() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            let data = stream.read().unwrap();
+            data.iter()
+                .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
+                .for_each(|(a, b)| {
+                    assert!(a == b);
+                });
+            //the client closed so the next read is eof rather than blocking
+            let eof = stream.read_timeout(Duration::from_secs(2)).unwrap_err();
+            assert!(eof.kind() == ErrorKind::UnexpectedEof);
+            assert!(stream.write(&[1, 2], Duration::from_millis(100), true).is_err());
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
+        //lingers until the write is acked
+        client.close().unwrap();
+        assert!(client.write(&[1, 2], Duration::from_millis(100), true).is_err());
+        assert!(handle.join().is_ok())
+    }
+    pub fn half_close() {
+        let handle = thread::spawn(|| {
+            let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+            let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+            let stream = server.accept().unwrap();
+            let data = stream.read().unwrap();
+            assert!(!data.is_empty());
+            let eof = stream.read_timeout(Duration::from_secs(2)).unwrap_err();
+            assert!(eof.kind() == ErrorKind::UnexpectedEof);
+            //the client can still hear us
+            stream.write(&[8, 7, 6, 5, 4, 3, 2, 1], Duration::from_millis(100), true).unwrap();
+            stream.close().unwrap();
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
+        let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
+        client.shutdown(Shutdown::Write).unwrap();
+        assert!(client.write(&[1, 2], Duration::from_millis(100), true).is_err());
+        let reply = client.read_timeout(Duration::from_secs(2)).unwrap();
+        reply.iter()
+            .zip([8, 7, 6, 5, 4, 3, 2, 1].iter())
+            .for_each(|(a, b)| {
+                assert!(a == b);
+            });
+        assert!(handle.join().is_ok())
+    }
+    pub fn rebind() {
+        let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        for _ in 0..3 {
+            let handle = thread::spawn(move || {
+                let mut server = NeonListener::simplex(addr, NeonConfig::default()).unwrap();
+                let stream = server.accept().unwrap();
+                let data = stream.read().unwrap();
+                assert!(!data.is_empty());
+                server.close().unwrap();
+                //nothing left to accept on a closed listener
+                assert!(server.accept().is_err());
+            });
+            let client = NeonStream::simplex(bind, 3, Duration::from_millis(100), addr, NeonConfig::default()).unwrap();
+            let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
+            assert!(handle.join().is_ok());
+            //dropping the last owner frees the port for the next round
+            drop(client);
+        }
+    }
+    //Only the address we're connecting to can refuse us
+    pub fn spoofed_refusal() {
+        let handle = thread::spawn(|| {
+            let target = UdpSocket::bind("127.0.0.1:8128").unwrap();
+            let spoofer = UdpSocket::bind("127.0.0.1:9100").unwrap();
+            target.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let mut bytes = [0u8; MAX_PACKET_SIZE as usize];
+            let handshake = |target: &UdpSocket, bytes: &mut [u8]| {
+                let (count, from) = target.recv_from(bytes).unwrap();
+                match Packet::deserialize(&bytes[..count], &mut 0) {
+                    Packet::Control(ControlPacket { info: ControlPacketInfo::Handshake(info), .. }) => {
+                        (info.src_socket_id, SocketAddr::new(from.ip(), info.port))
+                    }
+                    _ => panic!("Not a handshake"),
+                }
+            };
+            let (socket_id, client) = handshake(&target, &mut bytes);
+            let refusal = Packet::Control(ControlPacket::error(socket_id, ERR_VERSION)).serialize();
+            spoofer.send_to(&refusal, client).unwrap();
+            //the client is still asking so the spoof went ignored
+            let (socket_id, client) = handshake(&target, &mut bytes);
+            let refusal = Packet::Control(ControlPacket::error(socket_id, ERR_VERSION)).serialize();
+            target.send_to(&refusal, client).unwrap();
+        });
+        let bind = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let target = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let err = NeonStream::simplex(bind, 10, Duration::from_millis(100), target, NeonConfig::default()).err().unwrap();
+        assert!(err.kind() == ErrorKind::ConnectionRefused);
+        assert!(handle.join().is_ok());
+    }
+    pub fn accept_modes() {
+        let addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
+        let mut server = NeonListener::simplex(addr, NeonConfig::new().backlog(1)).unwrap();
+        let err = server.try_accept().err().unwrap();
+        assert!(err.kind() == ErrorKind::WouldBlock);
+        let err = server.accept_timeout(Duration::from_millis(50)).err().unwrap();
+        assert!(err.kind() == ErrorKind::TimedOut);
+        //the first client fills the backlog so the second is turned away
+        let first_addr = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let second_addr = "127.0.0.1:9002".parse::<SocketAddr>().unwrap();
+        let first = NeonStream::simplex(first_addr, 3, Duration::from_millis(100), addr, NeonConfig::default()).unwrap();
+        let err = NeonStream::simplex(second_addr, 3, Duration::from_millis(100), addr, NeonConfig::default()).err().unwrap();
+        assert!(err.kind() == ErrorKind::ConnectionRefused);
+        let stream = server.accept_timeout(Duration::from_secs(1)).unwrap();
+        let _ = first.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
+        let data = stream.read().unwrap();
+        data.iter()
+            .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
+            .for_each(|(a, b)| {
+                assert!(a == b);
+            });
+        //accepting made room
+        let second = NeonStream::simplex(second_addr, 3, Duration::from_millis(100), addr, NeonConfig::default()).unwrap();
+        let stream = server.incoming().next().unwrap().unwrap();
+        let _ = second.write(&[8, 7, 6, 5, 4, 3, 2, 1], Duration::from_millis(100), true);
+        let data = stream.read().unwrap();
+        data.iter()
+            .zip([8, 7, 6, 5, 4, 3, 2, 1].iter())
+            .for_each(|(a, b)| {
+                assert!(a == b);
+            });
+        server.close().unwrap();
+        assert!(server.incoming().next().is_none());
+    }
 }
 #[allow(dead_code)]
 
 //#[cfg(test)]
 pub mod duplex {
+    use crate::config::NeonConfig;
     use crate::core::channel::MAX_PACKET_SIZE;
     use crate::listener::NeonListener;
     use crate::stream::NeonStream;
@@ -436,7 +1207,7 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept();
             thread::sleep(Duration::from_micros(1000));
@@ -447,7 +1218,7 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target);
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default());
         thread::sleep(Duration::from_micros(1000));
         match  &client {
             Ok(_) => {},
@@ -463,11 +1234,11 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
             thread::sleep(Duration::from_millis(100));
-            let data = stream.read();
+            let data = stream.read().unwrap();
 
             data.iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
@@ -480,7 +1251,7 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
         thread::sleep(Duration::from_millis(100));
         assert!(handle.join().is_ok())
@@ -491,10 +1262,10 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE)
                 .flat_map(|i| (i % 32).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -511,7 +1282,7 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();        let _ = client.write(&data, Duration::from_millis(100), true);
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();        let _ = client.write(&data, Duration::from_millis(100), true);
         thread::sleep(Duration::from_millis(1000));
         assert!(handle.join().is_ok())
     }
@@ -520,10 +1291,10 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE * 2)
                 .flat_map(|i| (i % 128).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -540,7 +1311,7 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();        let _ = client.write(&data, Duration::from_millis(100), true);
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();        let _ = client.write(&data, Duration::from_millis(100), true);
         thread::sleep(Duration::from_millis(100));
         assert!(handle.join().is_ok())
     }
@@ -550,10 +1321,10 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             data.iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
                 .for_each(|(a, b)| {
@@ -570,9 +1341,9 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
-        let reply = client.read();
+        let reply = client.read().unwrap();
         reply
             .iter()
             .zip([1, 1, 2, 3, 5, 8, 13, 21].iter())
@@ -587,10 +1358,10 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE)
                 .flat_map(|i| (i % 32).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -611,7 +1382,7 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(
             &(0..MAX_PACKET_SIZE)
                 .flat_map(|i| (i % 32).to_le_bytes())
@@ -619,7 +1390,7 @@ pub mod duplex {
             Duration::from_millis(100),
             true,
         );
-        let reply = client.read();
+        let reply = client.read().unwrap();
         reply
             .iter()
             .zip(
@@ -640,10 +1411,10 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept().unwrap();
-            let data = stream.read();
+            let data = stream.read().unwrap();
             let check_data = (0..MAX_PACKET_SIZE * 5)
                 .flat_map(|i| (i % 128).to_le_bytes())
                 .collect::<Vec<_>>();
@@ -664,7 +1435,7 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+        let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         let _ = client.write(
             &(0..MAX_PACKET_SIZE * 5)
                 .flat_map(|i| (i % 128).to_le_bytes())
@@ -672,7 +1443,7 @@ pub mod duplex {
             Duration::from_millis(100),
             true,
         );
-        let reply = client.read();
+        let reply = client.read().unwrap();
         reply
             .iter()
             .zip(
@@ -693,7 +1464,7 @@ pub mod duplex {
         let handle_server = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream_one = server.accept();
             let stream_two = server.accept();
@@ -706,7 +1477,7 @@ pub mod duplex {
             let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
     
             let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target);
+            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default());
             assert!(client.is_ok());
 
             thread::sleep(Duration::from_millis(100));
@@ -716,7 +1487,7 @@ pub mod duplex {
             let in_addr = "127.0.0.1:9003".parse::<SocketAddr>().unwrap();
     
             let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target);
+            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default());
             assert!(client.is_ok());
             thread::sleep(Duration::from_millis(100));
         });
@@ -730,19 +1501,19 @@ pub mod duplex {
         let handle_server = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream_one = server.accept().unwrap();
             let stream_two = server.accept().unwrap();
 
-            let data_one = stream_one.read();
+            let data_one = stream_one.read().unwrap();
             data_one
                 .iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
                 .for_each(|(a, b)| {
                     assert!(a == b);
                 });
-            let data_two = stream_two.read();
+            let data_two = stream_two.read().unwrap();
             data_two
                 .iter()
                 .zip([1, 1, 2, 3, 5, 8, 13, 21].iter())
@@ -756,7 +1527,7 @@ pub mod duplex {
             let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
     
             let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
             thread::sleep(Duration::from_millis(100));
         });
@@ -767,7 +1538,7 @@ pub mod duplex {
             let in_addr = "127.0.0.1:9003".parse::<SocketAddr>().unwrap();
     
             let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(
                 &[1, 1, 2, 3, 5, 8, 13, 21],
                 Duration::from_millis(100),
@@ -786,19 +1557,19 @@ pub mod duplex {
         let handle_server = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream_one = server.accept().unwrap();
             let stream_two = server.accept().unwrap();
 
-            let data_one = stream_one.read();
+            let data_one = stream_one.read().unwrap();
             data_one
                 .iter()
                 .zip([1, 2, 3, 4, 5, 6, 7, 8].iter())
                 .for_each(|(a, b)| {
                     assert!(a == b);
                 });
-            let data_two = stream_two.read();
+            let data_two = stream_two.read().unwrap();
             data_two
                 .iter()
                 .zip([1, 1, 2, 3, 5, 8, 13, 21].iter())
@@ -821,9 +1592,9 @@ pub mod duplex {
             let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
     
             let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(&[1, 2, 3, 4, 5, 6, 7, 8], Duration::from_millis(100), true);
-            let reply = client.read();
+            let reply = client.read().unwrap();
             reply
                 .iter()
                 .zip([0, 2, 4, 8, 16, 32, 64, 128].iter())
@@ -837,13 +1608,13 @@ pub mod duplex {
             let in_addr = "127.0.0.1:9003".parse::<SocketAddr>().unwrap();
     
             let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+            let client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
             let _ = client.write(
                 &[1, 1, 2, 3, 5, 8, 13, 21],
                 Duration::from_millis(100),
                 true,
             );
-            let reply = client.read();
+            let reply = client.read().unwrap();
             reply
                 .iter()
                 .zip([1, 3, 5, 9, 17, 33, 64, 129].iter())
@@ -862,7 +1633,7 @@ pub mod duplex {
         let handle = thread::spawn(|| {
             let out_addr = "127.0.0.1:8128".parse::<SocketAddr>().unwrap();
             let in_addr = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-            let mut server = NeonListener::duplex(out_addr,in_addr).unwrap();
+            let mut server = NeonListener::duplex(out_addr,in_addr, NeonConfig::default()).unwrap();
             //drop when a stream get's popped
             let stream = server.accept();
             //idle here for a while
@@ -874,9 +1645,786 @@ pub mod duplex {
         let in_addr = "127.0.0.1:9001".parse::<SocketAddr>().unwrap();
 
         let target = "127.0.0.1:8129".parse::<SocketAddr>().unwrap();
-        let _client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target).unwrap();
+        let _client = NeonStream::duplex(out_addr,in_addr, 4, Duration::from_millis(100), target, NeonConfig::default()).unwrap();
         //idle here for a while
         thread::sleep(Duration::from_secs(3));
         assert!(handle.join().is_ok())
     }
 }
+
+//Drives the buffers directly for orderings the network won't produce on demand
+pub mod buffer {
+    use crate::config::{NeonConfig, Overflow};
+    use crate::congestion::CongestionController;
+    use crate::core::channel::MAX_PACKET_SIZE;
+    use crate::core::loss_list::LossBuffer;
+    use crate::core::recv::recv_buffer::RecvBuffer;
+    use crate::core::send::send_buffer::{MessageOptions, Priority, Receipt, Scheduling, SendBuffer, NO_TTL};
+    use crate::core::send::send_list::SendList;
+    use crate::packet::control::handshake::{
+        Handshake, ReqType, CAPABILITIES, CAP_DATAGRAM, CAP_TIMING, MIN_WIRE_VERSION, WIRE_VERSION,
+    };
+    use crate::packet::control::loss::Loss;
+    use crate::packet::control::timing::Timing;
+    use crate::packet::control::{ControlMeta, ControlPacket, ControlPacketInfo, ControlType};
+    use crate::packet::data::{DataPacket, DataPacketType};
+    use crate::packet::Packet;
+    use crate::serial::Serial;
+    use crate::utils::{self, MessageNumber, SequenceNumber, SequenceRange};
+    use std::time::{Duration, SystemTime};
+
+    fn options(ttl: Duration) -> MessageOptions {
+        MessageOptions {
+            ttl,
+            order: true,
+            channel: 0,
+            priority: Priority::Normal,
+        }
+    }
+    fn range(start: u32, stop: u32) -> SequenceRange {
+        SequenceRange {
+            start: SequenceNumber::new(start),
+            stop: SequenceNumber::new(stop),
+        }
+    }
+    //message numbers counted from where every channel starts
+    fn msg(count: u32) -> MessageNumber {
+        let mut msg_no = MessageNumber::FIRST;
+        msg_no.add(count);
+        msg_no
+    }
+    fn solo(seq_no: u32, msg_no: u32, order: bool, data: &[u8]) -> DataPacket {
+        part(seq_no, msg_no, DataPacketType::Solo, order, data)
+    }
+    fn part(seq_no: u32, msg_no: u32, kind: DataPacketType, order: bool, data: &[u8]) -> DataPacket {
+        DataPacket::new(
+            SequenceNumber::new(seq_no),
+            msg(msg_no),
+            kind,
+            order,
+            SystemTime::now(),
+            0,
+            DataPacket::compress(data),
+        )
+    }
+
+    //An unordered message is released while the ordered one before it is missing
+    pub fn unordered() {
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.add(solo(2, 1, false, &[4, 5, 6]));
+        buffer.add(solo(3, 2, true, &[7, 8, 9]));
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [4, 5, 6]);
+        //the ordered message still waits on the gap
+        assert!(buffer.pop(0).is_none());
+        buffer.add(solo(1, 0, true, &[1, 2, 3]));
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [1, 2, 3]);
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [7, 8, 9]);
+        //a resend of the unordered message isn't handed out twice
+        buffer.add(solo(2, 1, false, &[4, 5, 6]));
+        assert!(buffer.pop(0).is_none());
+    }
+
+    //A dropped partial message is thrown away and stops blocking the ones behind it
+    pub fn dropped() {
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.add(part(1, 0, DataPacketType::First, true, &[1, 2, 3]));
+        buffer.add(solo(3, 1, true, &[4, 5, 6]));
+        assert!(buffer.pop(0).is_none());
+        let range = SequenceRange {
+            start: SequenceNumber::new(1),
+            stop: SequenceNumber::new(2),
+        };
+        assert!(buffer.drop_msg(0, msg(0), &[range]));
+        //seq 3 was already in so the gap closing takes it along
+        assert!(buffer.last_seq() == SequenceNumber::new(3));
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [4, 5, 6]);
+        assert!(buffer.dropped() == 1);
+        //a repeated drop or a drop for something delivered isn't counted
+        assert!(!buffer.drop_msg(0, msg(0), &[range]));
+        let range = SequenceRange {
+            start: SequenceNumber::new(3),
+            stop: SequenceNumber::new(3),
+        };
+        assert!(!buffer.drop_msg(0, msg(1), &[range]));
+        assert!(buffer.dropped() == 1);
+    }
+
+    //A message that had another cut into its seqs only gives up its own, the one in between is still waited on
+    pub fn interleaved_drop() {
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        let mut high = options(Duration::from_millis(100));
+        high.priority = Priority::High;
+        let mut low = options(Duration::from_secs(5));
+        low.priority = Priority::Low;
+        low.channel = 2;
+        let data = (0..400).map(|i| utils::hash(i) as u8).collect::<Vec<_>>();
+        buffer.add(&data, high, 0, 64).unwrap();
+        buffer.add(&[1, 2, 3], low, 0, 64).unwrap();
+        let mut high_seqs = Vec::new();
+        let mut low_seq = None;
+        while let Some(packet) = buffer.read() {
+            match packet.channel {
+                0 => high_seqs.push(packet.seq_no),
+                _ => low_seq = Some(packet.seq_no),
+            }
+        }
+        let low_seq = low_seq.unwrap();
+        //low went out in the middle of high
+        assert!(high_seqs.iter().any(|seq| seq.serial_lt(low_seq)) && high_seqs.iter().any(|seq| low_seq.serial_lt(*seq)));
+        std::thread::sleep(Duration::from_millis(150));
+        let drops = buffer.release_drops(Duration::ZERO);
+        assert!(drops.len() == 1);
+        let (_, msg_no, ranges) = &drops[0];
+        assert!(*msg_no == msg(0));
+        assert!(*ranges == SequenceRange::runs(&high_seqs));
+        assert!(ranges.iter().all(|range| !range.contains(low_seq)));
+        //high on 1-4 and 6-13 with low on 5, the drop doesn't take 5 along
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.add(part(1, 0, DataPacketType::First, true, &[1, 2]));
+        buffer.add(part(2, 0, DataPacketType::Middle, true, &[3, 4]));
+        buffer.add(part(7, 0, DataPacketType::Middle, true, &[5, 6]));
+        assert!(buffer.drop_msg(0, msg(0), &[range(1, 4), range(6, 13)]));
+        assert!(buffer.last_seq() == SequenceNumber::new(4));
+        buffer.add(solo(5, 1, true, &[7, 8, 9]));
+        assert!(buffer.last_seq() == SequenceNumber::new(13));
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [7, 8, 9]);
+        //a message missing one of its own isn't finished by the seqs around it coming in
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.add(part(1, 0, DataPacketType::First, false, &[1, 2]));
+        buffer.add(part(3, 0, DataPacketType::Last, false, &[3, 4]));
+        buffer.add(solo(4, 1, false, &[7, 8, 9]));
+        assert!(buffer.pop(0).unwrap()[..3] == [7, 8, 9]);
+        //seq 2 is still out, it could be this message's
+        assert!(buffer.pop(0).is_none());
+        buffer.add(solo(2, 2, false, &[7, 8, 9]));
+        assert!(buffer.pop(0).is_some());
+        assert!(buffer.pop(0).is_some());
+    }
+
+    //A reader that can't skip a message is told about each dropped one once it gets there
+    pub fn gaps() {
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.add(solo(1, 0, true, &[1, 2, 3]));
+        buffer.add(solo(3, 2, true, &[7, 8, 9]));
+        assert!(buffer.drop_msg(0, msg(1), &[range(2, 2)]));
+        assert!(buffer.pop_intact(0).unwrap().unwrap()[..3] == [1, 2, 3]);
+        assert!(buffer.pop_intact(0) == Err(msg(1)));
+        assert!(buffer.pop_intact(0).unwrap().unwrap()[..3] == [7, 8, 9]);
+        assert!(buffer.pop_intact(0) == Ok(None));
+        //a plain read steps over it
+        buffer.add(solo(5, 4, true, &[4, 5, 6]));
+        assert!(buffer.drop_msg(0, msg(3), &[range(4, 4)]));
+        assert!(buffer.pop(0).unwrap()[..3] == [4, 5, 6]);
+        assert!(buffer.pop_intact(0) == Ok(None));
+    }
+
+    //Receipts follow a message through ack, expiry and a lost connection
+    pub fn receipts() {
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        let (_, acked) = buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        assert!(acked.msg_no() == msg(0));
+        assert!(buffer.receipt(acked).unwrap().is_none());
+        let packet = buffer.read().unwrap();
+        buffer.ack(packet.seq_no);
+        assert!(buffer.receipt(acked).unwrap() == Some(Receipt::Acked));
+        //polling again gives the same answer
+        assert!(buffer.receipt(acked).unwrap() == Some(Receipt::Acked));
+        let (_, dropped) = buffer.add(&[4, 5, 6], options(Duration::ZERO), 0, 1024).unwrap();
+        assert!(buffer.release_drops(Duration::ZERO).len() == 1);
+        assert!(buffer.receipt(dropped).unwrap() == Some(Receipt::Dropped(Duration::ZERO)));
+        let (_, lost) = buffer.add(&[7, 8, 9], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        let mut receipts = buffer.into_receipts();
+        receipts.lose();
+        assert!(receipts.get(lost).unwrap() == Some(Receipt::ConnectionLost));
+        assert!(receipts.get(acked).unwrap() == Some(Receipt::Acked));
+
+        //the partner threw an acked message out unread
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        let (_, shed) = buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        let packet = buffer.read().unwrap();
+        assert!(packet.ttl);
+        buffer.ack(packet.seq_no);
+        buffer.on_shed(0, shed.msg_no());
+        assert!(buffer.receipt(shed).unwrap() == Some(Receipt::Shed));
+        assert!(buffer.partner_shed() == 1);
+        let (_, kept) = buffer.add(&[4, 5, 6], options(NO_TTL), 0, 1024).unwrap();
+        assert!(!buffer.read().unwrap().ttl);
+        assert!(buffer.receipt(kept).unwrap().is_none());
+    }
+
+    //Fresh messages go out by weighted priority and pick up their seqs as they go
+    pub fn priorities() {
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        //each class on its own channel so the packets say where they came from
+        let classes = [(Priority::Low, 3, 2), (Priority::High, 6, 0), (Priority::Normal, 2, 1)];
+        classes.iter().for_each(|(priority, count, channel)| {
+            (0..*count).for_each(|_| {
+                let mut options = options(Duration::from_secs(5));
+                options.priority = *priority;
+                options.channel = *channel;
+                buffer.add(&[1, 2, 3], options, 0, 1024).unwrap();
+            });
+        });
+        let mut seq_no = SequenceNumber::new(0);
+        let order = (0..11)
+            .map(|_| {
+                let packet = buffer.read().unwrap();
+                seq_no.inc();
+                assert!(packet.seq_no == seq_no);
+                packet.channel
+            })
+            .collect::<Vec<_>>();
+        assert!(order == [0, 0, 0, 0, 1, 1, 2, 0, 0, 2, 2]);
+        assert!(buffer.read().is_none());
+        //a message cut in half by a higher one still completes on the other side
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.add(part(1, 0, DataPacketType::First, false, &[1, 2]));
+        buffer.add(solo(2, 1, false, &[7, 8, 9]));
+        buffer.add(part(3, 0, DataPacketType::Last, false, &[3, 4]));
+        assert!(buffer.pop(0).is_some());
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [7, 8, 9]);
+    }
+
+    //Deadline mode sends the soonest ttl first and gives up on what the pacing can't deliver in time
+    pub fn deadlines() {
+        let config = NeonConfig::default().scheduling(Scheduling::Deadline);
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &config);
+        buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        let (_, urgent) = buffer.add(&[4, 5, 6], options(Duration::from_secs(1)), 0, 1024).unwrap();
+        let packet = buffer.read().unwrap();
+        assert!(packet.msg_no == urgent.msg_no());
+        assert!(buffer.read().unwrap().msg_no == msg(0));
+        //two fit in the ttl at this rate, the rest would arrive stale
+        buffer.pace(Duration::from_millis(100));
+        let ids = (0..5)
+            .map(|_| {
+                let (_, id) = buffer.add(&[7, 8, 9], options(Duration::from_millis(250)), 0, 1024).unwrap();
+                id
+            })
+            .collect::<Vec<_>>();
+        assert!(buffer.read().is_some());
+        assert!(buffer.read().is_some());
+        assert!(buffer.read().is_none());
+        assert!(buffer.dropped() == 3);
+        assert!(buffer.receipt(ids[4]).unwrap() == Some(Receipt::Dropped(Duration::from_millis(250))));
+        //no ttl goes behind anything that has one and is never given up on
+        assert!(buffer.release_drops(Duration::ZERO).len() == 3);
+        let (_, lasting) = buffer.add(&[1, 2, 3], options(NO_TTL), 0, 1024).unwrap();
+        let (_, timed) = buffer.add(&[4, 5, 6], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        assert!(buffer.read().unwrap().msg_no == timed.msg_no());
+        assert!(buffer.read().unwrap().msg_no == lasting.msg_no());
+        //nothing new to give up on, the earlier ones aren't due again yet
+        assert!(buffer.release_drops(Duration::from_secs(5)).is_empty());
+        assert!(buffer.receipt(lasting).unwrap().is_none());
+        //a tie goes out in the order it was written, however much is queued
+        buffer.pace(Duration::ZERO);
+        buffer.window(1000);
+        let ids = (0..500)
+            .map(|_| {
+                let (_, id) = buffer.add(&[7, 8, 9], options(Duration::from_secs(5)), 0, 1024).unwrap();
+                id
+            })
+            .collect::<Vec<_>>();
+        ids.iter().for_each(|id| assert!(buffer.read().unwrap().msg_no == id.msg_no()));
+        //a slower pace lays out again what's already queued
+        let ids = (0..2)
+            .map(|_| {
+                let (_, id) = buffer.add(&[7, 8, 9], options(Duration::from_millis(250)), 0, 1024).unwrap();
+                id
+            })
+            .collect::<Vec<_>>();
+        assert!(buffer.read().unwrap().msg_no == ids[0].msg_no());
+        buffer.pace(Duration::from_millis(300));
+        assert!(buffer.read().is_none());
+        assert!(buffer.receipt(ids[1]).unwrap() == Some(Receipt::Dropped(Duration::from_millis(250))));
+    }
+
+    //Writes past either limit are turned away until an ack makes room
+    pub fn limits() {
+        let config = NeonConfig::default().buffers(3, 8192).send_bytes(64);
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &config);
+        //the limit is on what's held, which is compressed
+        let big = (0..100).map(|i| (i * 37 % 251) as u8).collect::<Vec<_>>();
+        let err = buffer.add(&big, options(Duration::from_secs(5)), 0, 1024).unwrap_err();
+        assert!(err.kind() == std::io::ErrorKind::InvalidInput);
+        (0..3).for_each(|_| {
+            buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        });
+        assert!(!buffer.writable());
+        let err = buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap_err();
+        assert!(err.kind() == std::io::ErrorKind::WouldBlock);
+        assert!(!buffer.writable());
+        let packet = buffer.read().unwrap();
+        buffer.ack(packet.seq_no);
+        //told once, then it's quiet until the next refusal
+        assert!(buffer.writable());
+        assert!(!buffer.writable());
+        //a smaller byte limit holds off writes that the packet limit would allow
+        buffer.limit(8, 16);
+        let err = buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap_err();
+        assert!(err.kind() == std::io::ErrorKind::WouldBlock);
+    }
+
+    //No more in flight than the window, a read it turned away is owed back when it opens
+    pub fn window() {
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.window(2);
+        (0..4).for_each(|_| {
+            buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        });
+        let first = buffer.read().unwrap();
+        assert!(buffer.read().is_some());
+        assert!(buffer.read().is_none());
+        assert!(buffer.window(2) == 0);
+        buffer.ack(first.seq_no);
+        assert!(buffer.window(2) == 1);
+        assert!(buffer.read().is_some());
+        //a shut window still lets one through to find out when it opens
+        let mut buffer = SendBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        buffer.window(0);
+        buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        buffer.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        assert!(buffer.read().is_some());
+        assert!(buffer.read().is_none());
+
+        //the receiver advertises what's left and owes an ack until it says so
+        let config = NeonConfig::default().buffers(8192, 4);
+        let mut recv = RecvBuffer::new(SequenceNumber::new(0), &config);
+        recv.add(solo(1, 0, true, &[1, 2, 3]));
+        recv.add(solo(3, 2, true, &[7, 8, 9]));
+        assert!(recv.free() == 2);
+        assert!(recv.owes_ack());
+        //seq 3 already came, filling the gap acks past it
+        recv.add(solo(2, 1, true, &[4, 5, 6]));
+        assert!(recv.next_ack() == SequenceNumber::new(3));
+        recv.sent_ack(SequenceNumber::new(3));
+        assert!(recv.owes_ack());
+        (0..3).for_each(|_| {
+            recv.pop(0).unwrap();
+        });
+        recv.sent_ack(SequenceNumber::new(3));
+        assert!(!recv.owes_ack());
+    }
+
+    //A full buffer turns new messages away unacked, or sheds the stale ones when asked to
+    pub fn overflow() {
+        let config = NeonConfig::default().recv_limits(4 * MAX_PACKET_SIZE as usize, 2);
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &config);
+        assert!(buffer.free() == 4);
+        buffer.add(solo(1, 0, true, &[1, 2, 3]));
+        buffer.add(solo(2, 1, true, &[4, 5, 6]));
+        buffer.add(solo(3, 2, true, &[7, 8, 9]));
+        //the third isn't acked so the sender sends it again
+        assert!(buffer.size() == 2);
+        assert!(buffer.last_seq() == SequenceNumber::new(2));
+        assert!(buffer.pop(0).unwrap()[..3] == [1, 2, 3]);
+        buffer.add(solo(3, 2, true, &[7, 8, 9]));
+        assert!(buffer.last_seq() == SequenceNumber::new(3));
+        assert!(buffer.pop(0).unwrap()[..3] == [4, 5, 6]);
+        assert!(buffer.pop(0).unwrap()[..3] == [7, 8, 9]);
+
+        let config = config.overflow(Overflow::Shed(Duration::ZERO));
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &config);
+        buffer.add(solo(1, 0, true, &[1, 2, 3]).expiring(true));
+        buffer.add(solo(2, 1, true, &[4, 5, 6]).expiring(true));
+        buffer.add(solo(3, 2, true, &[7, 8, 9]).expiring(true));
+        //the oldest made room and the reader moves on past it
+        assert!(buffer.shed() == 1);
+        assert!(buffer.last_seq() == SequenceNumber::new(3));
+        assert!(buffer.packets() == 2);
+        //the sender is told once
+        assert!(buffer.take_shed() == [(0, msg(0))]);
+        assert!(buffer.take_shed().is_empty());
+        assert!(buffer.pop(0).unwrap()[..3] == [4, 5, 6]);
+        assert!(buffer.pop(0).unwrap()[..3] == [7, 8, 9]);
+        assert!(buffer.packets() == 0 && buffer.bytes() == 0);
+        //a resend of the shed message doesn't come back
+        buffer.add(solo(1, 0, true, &[1, 2, 3]).expiring(true));
+        assert!(buffer.pop(0).is_none());
+
+        //messages without a ttl were acked as kept, so they're never shed and the new one is refused
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &config);
+        buffer.add(solo(1, 0, true, &[1, 2, 3]));
+        buffer.add(solo(2, 1, true, &[4, 5, 6]).expiring(true));
+        buffer.add(solo(3, 2, true, &[7, 8, 9]));
+        assert!(buffer.take_shed() == [(0, msg(1))]);
+        assert!(buffer.add(solo(4, 3, true, &[1, 2, 3])).is_none());
+        assert!(buffer.last_seq() == SequenceNumber::new(3));
+        assert!(buffer.shed() == 1);
+
+        //nothing is old enough to shed, so it's refused like before
+        let config = config.overflow(Overflow::Shed(Duration::from_secs(60)));
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &config);
+        buffer.add(solo(1, 0, true, &[1, 2, 3]).expiring(true));
+        buffer.add(solo(2, 1, true, &[4, 5, 6]).expiring(true));
+        buffer.add(solo(3, 2, true, &[7, 8, 9]).expiring(true));
+        assert!(buffer.shed() == 0);
+        assert!(buffer.size() == 2);
+
+        //the rest of a message gets past a full buffer, but one message never holds more than all of it
+        let config = NeonConfig::default().buffers(64, 4);
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &config);
+        buffer.add(part(1, 0, DataPacketType::First, true, &[1]));
+        (2..5).for_each(|seq| {
+            buffer.add(part(seq, 0, DataPacketType::Middle, true, &[1]));
+        });
+        assert!(buffer.packets() == 4);
+        assert!(buffer.add(part(5, 0, DataPacketType::Last, true, &[1])).is_none());
+        assert!(buffer.last_seq() == SequenceNumber::new(4));
+    }
+
+    //Slow start counts from the first ack, and a loss slows the rate once per round of sends
+    pub fn congestion() {
+        let config = NeonConfig::default().congestion_window(16, 64);
+        let mut congestion = CongestionController::new(&config);
+        //far into the space, the first ack isn't taken as everything since seq zero
+        congestion.on_send(SequenceNumber::new(0x7000_0000));
+        congestion.on_send(SequenceNumber::new(0x7000_0004));
+        //acks closer together than the rate control interval are skipped
+        std::thread::sleep(Duration::from_millis(15));
+        congestion.on_ack(SequenceNumber::new(0x7000_0004));
+        assert!(congestion.window() == 21);
+        //a stale one adds nothing
+        std::thread::sleep(Duration::from_millis(15));
+        congestion.on_ack(SequenceNumber::new(0x7000_0002));
+        assert!(congestion.window() == 21);
+
+        let mut congestion = CongestionController::new(&config);
+        //the first loss only ends slow start
+        congestion.on_loss(SequenceNumber::new(1));
+        let period = congestion.next_time();
+        congestion.on_send(SequenceNumber::new(10));
+        //nothing has been cut yet so this one is
+        congestion.on_loss(SequenceNumber::new(5));
+        assert!(congestion.next_time() > period);
+        let period = congestion.next_time();
+        //sent before that cut, it's the same round
+        congestion.on_loss(SequenceNumber::new(8));
+        assert!(congestion.next_time() == period);
+        congestion.on_send(SequenceNumber::new(20));
+        congestion.on_loss(SequenceNumber::new(12));
+        assert!(congestion.next_time() > period);
+    }
+
+    //Ranges, the loss list and both buffers carry on across the wrap
+    pub fn wrapping() {
+        let last = SequenceNumber::MAX_SEQ_NO;
+        assert!(last.serial_lt(SequenceNumber::ZERO) && SequenceNumber::ZERO.serial_lt(SequenceNumber::new(1)));
+        assert!(msg(0).serial_lt(msg(1)) && MessageNumber::new(0x1fff_ffff).serial_lt(MessageNumber::ZERO));
+        //a third of the space apart each comes before the next all the way round, so Ord stays the plain value
+        let thirds = [SequenceNumber::ZERO, SequenceNumber::new(0x2aaa_aaab), SequenceNumber::new(0x5555_5556)];
+        assert!(thirds[0].serial_lt(thirds[1]) && thirds[1].serial_lt(thirds[2]) && thirds[2].serial_lt(thirds[0]));
+        assert!(thirds[0] < thirds[1] && thirds[1] < thirds[2]);
+        //sorting goes by the distance from the earliest, not the value
+        let runs = SequenceRange::runs(&[SequenceNumber::new(1), last, SequenceNumber::ZERO]);
+        assert!(runs == vec![SequenceRange { start: last, stop: SequenceNumber::new(1) }]);
+        let mut start = last;
+        start.dec();
+        let range = SequenceRange {
+            start,
+            stop: SequenceNumber::new(1),
+        };
+        assert!(range.contains(last) && range.contains(SequenceNumber::ZERO));
+        assert!(!range.contains(SequenceNumber::new(2)));
+        assert!(start.length(&SequenceNumber::new(1)) == 4);
+
+        //a hole at zero splits the range in two, the older half comes out first
+        let mut loss = LossBuffer::new();
+        loss.insert(range);
+        loss.remove(SequenceNumber::ZERO);
+        assert!(loss.pop().unwrap().stop == last);
+        assert!(loss.pop().unwrap().start == SequenceNumber::new(1));
+
+        //acks past the wrap clear what was sent before it
+        let mut send = SendBuffer::new(start, &NeonConfig::default());
+        (0..4).for_each(|_| {
+            send.add(&[1, 2, 3], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        });
+        let seqs = (0..4).map(|_| send.read().unwrap().seq_no).collect::<Vec<_>>();
+        //the first seq is the one after the isn
+        assert!(seqs == [last, SequenceNumber::ZERO, SequenceNumber::new(1), SequenceNumber::new(2)]);
+        send.ack(SequenceNumber::ZERO);
+        assert!(send.size() == 2);
+
+        //a gap over the wrap is reported and filled like any other
+        let mut recv = RecvBuffer::new(start, &NeonConfig::default());
+        recv.add(solo(last.0, 0, true, &[1, 2, 3]));
+        let skip = recv.add(solo(1, 2, true, &[7, 8, 9])).unwrap();
+        assert!(skip.start == SequenceNumber::ZERO && skip.stop == SequenceNumber::ZERO);
+        recv.add(solo(0, 1, true, &[4, 5, 6]));
+        assert!(recv.last_seq() == SequenceNumber::new(1));
+        assert!(recv.pop(0).unwrap()[..3] == [1, 2, 3]);
+        assert!(recv.pop(0).unwrap()[..3] == [4, 5, 6]);
+        assert!(recv.pop(0).unwrap()[..3] == [7, 8, 9]);
+    }
+
+    //Seqs get 31 bits and msg nos 29 on the wire, with the flags still on top
+    pub fn wide_numbers() {
+        let packet = part(0x7fff_fffe, 0x1fff_fffe, DataPacketType::Last, true, &[1, 2, 3]);
+        let mut start = 0;
+        let out = DataPacket::deserialize(&packet.serialize(), &mut start);
+        assert!(out.seq_no == SequenceNumber::new(0x7fff_fffe));
+        assert!(out.msg_no == msg(0x1fff_fffe));
+        assert!(out.element == DataPacketType::Last);
+        assert!(out.order);
+        //past the old 15 bit space nothing folds back
+        let mut seq_no = SequenceNumber::new(0x7fff);
+        seq_no.inc();
+        assert!(seq_no == SequenceNumber::new(0x8000));
+        let mut seq_no = SequenceNumber::MAX_SEQ_NO;
+        seq_no.inc();
+        assert!(seq_no == SequenceNumber::ZERO);
+
+        //loss packets carry whole ranges of them
+        let ranges = vec![range(5, 9), range(0x7fff_fff0, 0x7fff_ffff)];
+        let mut start = 0;
+        let out = Loss::deserialize(&Loss::new(ranges.clone()).serialize(), &mut start);
+        assert!(out.loss_range == ranges);
+
+        //the handshake says which wire version it speaks
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let handshake = Handshake::new(ReqType::Connection, 1024, 64, 7, addr, (WIRE_VERSION, CAPABILITIES));
+        let bytes = handshake.serialize();
+        let mut start = 0;
+        let out = Handshake::deserialize(&bytes, &mut start);
+        assert!(out.version == WIRE_VERSION);
+        assert!(out.isn == handshake.isn);
+        assert!(out.src_socket_id == 7);
+        //one from before the version field can't be mistaken for any we speak
+        let mut start = 0;
+        let out = Handshake::deserialize(&bytes[..12], &mut start);
+        assert!(out.version < MIN_WIRE_VERSION);
+    }
+
+    //A version 1 partner only gets the low bits, they're widened to whichever number is closest
+    pub fn narrow_numbers() {
+        let near = SequenceNumber::new(0x1234_7ff0);
+        let narrowed = SequenceNumber::new(0x1234_8005).narrow();
+        assert!(narrowed == SequenceNumber::new(0x0005));
+        assert!(narrowed.widen(near) == SequenceNumber::new(0x1234_8005));
+        assert!(SequenceNumber::new(0x7fe0).widen(near) == SequenceNumber::new(0x1234_7fe0));
+        //across the wrap of the whole space too
+        let near = SequenceNumber::new(0x7fff_fffe);
+        assert!(SequenceNumber::new(3).widen(near) == SequenceNumber::new(3));
+        assert!(SequenceNumber::new(0x7ffd).widen(SequenceNumber::new(1)) == SequenceNumber::new(0x7fff_fffd));
+        let near = MessageNumber::new(0x1fff_fff0);
+        assert!(MessageNumber::new(0x1fff_fff8).narrow() == MessageNumber::new(0x1ff8));
+        assert!(MessageNumber::new(2).widen(near) == MessageNumber::new(2));
+        assert!(MessageNumber::new(0x1ff0).widen(MessageNumber::new(0x2003)) == MessageNumber::new(0x1ff0));
+
+        //the data header and acks, losses and drops are cut, the handshake's isn isn't
+        let packet = part(0x1234_8005, 0x2003, DataPacketType::Solo, true, &[1, 2, 3]);
+        match Packet::Data(packet).narrow() {
+            Packet::Data(out) => {
+                assert!(out.seq_no == SequenceNumber::new(0x0005));
+                assert!(out.msg_no == msg(0x2003).narrow());
+                assert!(out.element == DataPacketType::Solo);
+            }
+            Packet::Control(_) => panic!("Not data"),
+        }
+        let ack = ControlPacket::ack(7, SequenceNumber::new(0x1_8001), SequenceNumber::new(0x1_8002), 1, 1, 1, 1, 1);
+        match ack.narrow() {
+            ControlPacket {
+                meta: ControlMeta::Seq(ack_no),
+                info: ControlPacketInfo::Ack(info),
+                ..
+            } => {
+                assert!(ack_no == SequenceNumber::new(1));
+                assert!(info.seq_no == SequenceNumber::new(2));
+            }
+            _ => panic!("Not an ack"),
+        }
+        let loss = ControlPacket::loss(7, vec![range(0x1_8001, 0x1_8003)]);
+        match loss.narrow().info {
+            ControlPacketInfo::Loss(info) => assert!(info.loss_range == vec![range(1, 3)]),
+            _ => panic!("Not a loss"),
+        }
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let handshake = ControlPacket::handshake(7, ReqType::Connection, 1024, 64, 7, addr, (1, CAPABILITIES));
+        let isn = match &handshake.info {
+            ControlPacketInfo::Handshake(info) => info.isn,
+            _ => unreachable!(),
+        };
+        match handshake.narrow().info {
+            ControlPacketInfo::Handshake(info) => assert!(info.isn == isn && info.version == 1),
+            _ => panic!("Not a handshake"),
+        }
+    }
+
+    //Both headers carry microseconds from the connection start, stamped as they go out
+    pub fn timestamps() {
+        let mut packet = part(3, 1, DataPacketType::Solo, false, &[1, 2, 3]);
+        packet.timestamp = 0xdead_beef;
+        let mut start = 0;
+        let out = DataPacket::deserialize(&packet.serialize(), &mut start);
+        assert!(out.timestamp == 0xdead_beef);
+        assert!(DataPacket::decompress(&out.data) == vec![1, 2, 3]);
+        let mut packet = Packet::Control(ControlPacket::keep_alive(7));
+        packet.set_timestamp(0x0102_0304);
+        let mut start = 0;
+        let out = Packet::deserialize(&packet.serialize(), &mut start);
+        assert!(out.timestamp() == 0x0102_0304);
+        assert!(out.socket_id() == 7);
+
+        let config = NeonConfig::default();
+        let epoch = SystemTime::now() - Duration::from_millis(50);
+        let mut list = SendList::new();
+        list.register_connection(1, SequenceNumber::new(0), &config, 64, epoch);
+        for data in [[1, 2, 3], [4, 5, 6]] {
+            list.insert(1, &data, options(Duration::from_secs(5)), 0, 1024).unwrap();
+        }
+        let first = list.pop(1).unwrap().timestamp();
+        let second = list.pop(1).unwrap().timestamp();
+        assert!(first >= 50_000);
+        assert!(second >= first);
+        assert!(second <= utils::timestamp(epoch));
+    }
+
+    //Each side picks the highest version both speak and the features both offer
+    pub fn versions() {
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let mut handshake =
+            Handshake::new(ReqType::Connection, 1024, 64, 7, addr, (WIRE_VERSION, CAP_DATAGRAM | CAP_TIMING));
+        let mut start = 0;
+        let out = Handshake::deserialize(&handshake.serialize(), &mut start);
+        assert!(out.min_version == MIN_WIRE_VERSION);
+        assert!(out.capabilities == CAP_DATAGRAM | CAP_TIMING);
+        assert!(out.agree(WIRE_VERSION, CAP_TIMING) == Some((WIRE_VERSION, CAP_TIMING)));
+        //either side can hold the connection to the narrow numbers
+        assert!(out.agree(1, CAP_TIMING) == Some((1, CAP_TIMING)));
+        handshake.version = 1;
+        assert!(handshake.agree(WIRE_VERSION, CAPABILITIES) == Some((1, CAP_DATAGRAM | CAP_TIMING)));
+        //a newer peer that still speaks ours comes down to it
+        handshake.version = WIRE_VERSION + 3;
+        assert!(handshake.agree(WIRE_VERSION, CAPABILITIES) == Some((WIRE_VERSION, CAP_DATAGRAM | CAP_TIMING)));
+        //one that has moved past ours has nothing in common
+        handshake.min_version = WIRE_VERSION + 1;
+        assert!(handshake.agree(WIRE_VERSION, CAPABILITIES).is_none());
+        //a response picking a version newer than we offered is refused too
+        handshake.version = WIRE_VERSION;
+        handshake.min_version = WIRE_VERSION;
+        assert!(handshake.agree(1, CAPABILITIES).is_none());
+        //and one from before the range offers nothing
+        handshake.min_version = MIN_WIRE_VERSION;
+        let mut start = 0;
+        let out = Handshake::deserialize(&handshake.serialize()[..16], &mut start);
+        assert!(out.agree(WIRE_VERSION, CAPABILITIES) == Some((WIRE_VERSION, 0)));
+    }
+
+    //A timing reply carries the request's time and when it arrived on top of its own
+    pub fn timing() {
+        let mut packet = Packet::Control(ControlPacket::timing(7, Timing::response(0xfff0_0000, 12)));
+        packet.set_timestamp(40);
+        let mut start = 0;
+        let out = match Packet::deserialize(&packet.serialize(), &mut start) {
+            Packet::Control(out) => out,
+            Packet::Data(_) => panic!("Not a control packet"),
+        };
+        assert!(out.control_type == ControlType::Timing);
+        assert!(out.timestamp == 40);
+        match out.info {
+            ControlPacketInfo::Timing(info) => {
+                assert!(matches!(info.req_type, ReqType::Response));
+                assert!(info.origin == 0xfff0_0000);
+                assert!(info.received == 12);
+            }
+            _ => panic!("Not a timing packet"),
+        }
+
+        //a shed report names the message the same way a drop does
+        let packet = Packet::Control(ControlPacket::shed(7, 3, msg(9)));
+        let mut start = 0;
+        match Packet::deserialize(&packet.serialize(), &mut start) {
+            Packet::Control(ControlPacket { meta: ControlMeta::Message(msg_no), info: ControlPacketInfo::Shed(info), .. }) => {
+                assert!(msg_no == msg(9));
+                assert!(info.channel == 3);
+            }
+            _ => panic!("Not a shed packet"),
+        }
+    }
+
+    //Small messages share a packet and come back out one by one
+    pub fn coalesced() {
+        let config = NeonConfig::default().coalesce(Duration::ZERO);
+        let mut send = SendBuffer::new(SequenceNumber::new(0), &config);
+        let ids = [[1, 2, 3], [4, 5, 6], [7, 8, 9]]
+            .iter()
+            .map(|data| {
+                let (ready, id) = send.add(data, options(Duration::from_secs(5)), 0, 1024).unwrap();
+                assert!(ready == 0);
+                id
+            })
+            .collect::<Vec<_>>();
+        //anything too big to share flushes the bundle ahead of it
+        let (ready, _) = send.add(&[0; 600], options(Duration::from_secs(5)), 0, 1024).unwrap();
+        assert!(ready == 2);
+        let packet = send.read().unwrap();
+        assert!(packet.bundle && packet.msg_no == msg(0));
+        assert!(send.read().unwrap().msg_no == msg(3));
+        assert!(send.read().is_none());
+        //one ack settles everything in the bundle
+        send.ack(packet.seq_no);
+        ids.iter().for_each(|id| assert!(send.receipt(*id).unwrap() == Some(Receipt::Acked)));
+
+        let mut recv = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        recv.add(packet.clone());
+        assert!(recv.pop(0).unwrap() == [1, 2, 3]);
+        assert!(recv.pop(0).unwrap() == [4, 5, 6]);
+        //a resend doesn't bring back what was already read
+        recv.add(packet.clone());
+        assert!(recv.pop(0).unwrap() == [7, 8, 9]);
+        assert!(recv.pop(0).is_none());
+
+        //split up the messages count their share of the wire bytes, not what they decompress to
+        let mut recv = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        recv.add(packet.clone());
+        assert!(recv.size() == 3 && recv.bytes() == packet.data.len());
+
+        //without room for its messages a bundle waits whole behind what's ahead of it
+        let config = NeonConfig::default().recv_limits(1 << 20, 2);
+        let mut recv = RecvBuffer::new(SequenceNumber::new(0), &config);
+        let mut ahead = solo(1, 0, true, &[4, 5, 6]);
+        ahead.channel = 3;
+        recv.add(ahead);
+        let mut bundle = packet;
+        bundle.seq_no = SequenceNumber::new(2);
+        recv.add(bundle);
+        assert!(recv.size() == 2);
+        assert!(recv.pop(0).is_none());
+        assert!(recv.pop(3).unwrap() == [4, 5, 6]);
+        assert!(recv.pop(0).unwrap() == [1, 2, 3]);
+        assert!(recv.size() == 2);
+        assert!(recv.pop(0).unwrap() == [4, 5, 6]);
+        assert!(recv.pop(0).unwrap() == [7, 8, 9]);
+        assert!(recv.bytes() == 0);
+    }
+
+    //A gap on one channel doesn't hold up another
+    pub fn channels() {
+        let mut buffer = RecvBuffer::new(SequenceNumber::new(0), &NeonConfig::default());
+        let mut packet = solo(2, 0, true, &[4, 5, 6]);
+        packet.channel = 3;
+        buffer.add(packet);
+        //channel 0 is waiting on seq 1 but channel 3 has its own numbering
+        assert!(buffer.pop(0).is_none());
+        let data = buffer.pop(3).unwrap();
+        assert!(data[..3] == [4, 5, 6]);
+        assert!(buffer.next_channel() == Some(3));
+        assert!(buffer.next_channel().is_none());
+        //opening it ourselves first means it isn't announced
+        buffer.open_channel(4);
+        let mut packet = solo(3, 0, true, &[7, 8, 9]);
+        packet.channel = 4;
+        buffer.add(packet);
+        assert!(buffer.next_channel().is_none());
+        buffer.add(solo(1, 0, true, &[1, 2, 3]));
+        let data = buffer.pop(0).unwrap();
+        assert!(data[..3] == [1, 2, 3]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::serial::Serial;
+use std::cmp::Ordering;
 use std::hash::Hash;
 use std::ops::{AddAssign, SubAssign};
 use std::{
@@ -6,44 +7,95 @@ use std::{
     ops::{Add, Sub},
 };
 use std::fmt::Debug;
+use std::time::SystemTime;
 
+/*
+    Both numbers wrap so they're compared RFC 1982 style with serial_cmp, a is before b when b is
+    less than half the space ahead of it. That only holds for numbers within half the space of each
+    other and isn't transitive past it, so Ord is the plain value for sets and maps and anything
+    sorted by where it sits in a window goes by its diff from the window's start
+*/
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct SequenceNumber(pub u16);
+pub struct SequenceNumber(pub u32);
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct MessageNumber(pub u16);
+pub struct MessageNumber(pub u32);
 
+//31 bits so the top bit of a packet stays free to mark control packets
 impl SequenceNumber {
-    const MAX: u16 = 0x7fff;
+    const MAX: u32 = 0x7fff_ffff;
+    const HALF: u32 = 0x4000_0000;
+    const NARROW: u32 = 0x7fff;
     pub const MAX_SEQ_NO: Self = SequenceNumber(Self::MAX);
-    pub fn new(base: u16) -> Self {
+    pub const ZERO: Self = Self(0);
+    pub fn new(base: u32) -> Self {
         Self(base & Self::MAX)
     }
+    //Where a connection starts, the near_wrap feature puts it a little short of the wrap so tests cross it
+    pub fn initial(base: u32) -> Self {
+        #[cfg(feature = "near_wrap")]
+        {
+            Self::new(Self::MAX - (base & 0xff))
+        }
+        #[cfg(not(feature = "near_wrap"))]
+        {
+            Self::new(base)
+        }
+    }
     pub fn inc(&mut self) {
         self.0 = (Wrapping(self.0).add(Wrapping(1))).0 & Self::MAX;
     }
     pub fn dec(&mut self) {
         self.0 = (Wrapping(self.0).sub(Wrapping(1))).0 & Self::MAX;
     }
-    pub fn diff(&self, other: Self) -> u16 {
+    pub fn diff(&self, other: Self) -> u32 {
         (Wrapping(self.0).sub(Wrapping(other.0))).0 & Self::MAX
     }
-    pub fn length(&self, other: &Self) -> u16 {
-        if self.0 <= other.0 {
-            other.0 - self.0 + 1
-        } else {
-            other.0 - self.0 + Self::MAX + 2
+    pub fn length(&self, other: &Self) -> u32 {
+        other.diff(*self) + 1
+    }
+    pub fn serial_cmp(&self, other: Self) -> Ordering {
+        match other.diff(*self) {
+            0 => Ordering::Equal,
+            ahead if ahead < Self::HALF => Ordering::Less,
+            _ => Ordering::Greater,
         }
     }
+    pub fn serial_lt(&self, other: Self) -> bool {
+        self.serial_cmp(other) == Ordering::Less
+    }
+    pub fn serial_le(&self, other: Self) -> bool {
+        self.serial_cmp(other) != Ordering::Greater
+    }
+    //Where a handful of nearby numbers start, the rest sort by their diff from it
+    pub fn earliest(seqs: impl Iterator<Item = Self>) -> Self {
+        seqs.reduce(|earliest, seq| match seq.serial_lt(earliest) {
+            true => seq,
+            false => earliest,
+        })
+        .unwrap_or(Self::ZERO)
+    }
     pub fn probe_start(&self) -> bool {
         self.0 ^ 0xf == 0x0
     }
     pub fn probe_stop(&self) -> bool {
         self.0 ^ 0xf == 0x1
     }
+    //The low 15 bits, all a version 1 partner has room for
+    pub fn narrow(&self) -> Self {
+        Self(self.0 & Self::NARROW)
+    }
+    //The full number a narrowed one stands for, whichever is closest to near
+    pub fn widen(&self, near: Self) -> Self {
+        let ahead = self.0.wrapping_sub(near.0) & Self::NARROW;
+        match ahead <= Self::NARROW / 2 {
+            true => Self::new(near.0.wrapping_add(ahead)),
+            false => Self::new(near.0.wrapping_sub(Self::NARROW + 1 - ahead)),
+        }
+    }
 }
 impl Add for SequenceNumber{
-    type Output = u16;
+    type Output = u32;
 
     fn add(self, other: Self) -> Self::Output {
         (Wrapping(self.0).add(Wrapping(other.0))).0 & Self::MAX
@@ -63,7 +115,7 @@ impl Debug for SequenceNumber{
     }
 }
 impl Sub for SequenceNumber{
-    type Output = u16;
+    type Output = u32;
     fn sub(self, other: Self) -> Self::Output {
         (Wrapping(self.0).sub(Wrapping(other.0))).0 & Self::MAX
     }
@@ -81,7 +133,7 @@ impl Serial for SequenceNumber {
     }
 
     fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
-        let out = u16::deserialize(bytes, start) & Self::MAX;
+        let out = u32::deserialize(bytes, start) & Self::MAX;
         Self(out)
     }
 }
@@ -93,13 +145,23 @@ pub struct SequenceRange {
 }
 
 impl SequenceRange {
+    pub fn narrow(&self) -> Self {
+        Self {
+            start: self.start.narrow(),
+            stop: self.stop.narrow(),
+        }
+    }
+    pub fn widen(&self, near: SequenceNumber) -> Self {
+        Self {
+            start: self.start.widen(near),
+            stop: self.stop.widen(near),
+        }
+    }
     pub fn contains(&self, number: SequenceNumber) -> bool {
-        self.start <= number && self.stop >= number
+        self.start.serial_le(number) && number.serial_le(self.stop)
     }
     pub fn overlaps(&self, other: Self)->bool{
-        (self.start <= other.start && self.stop >= other.start)
-                || (self.start <= other.stop && self.stop >= other.stop)
-                || (self.start >= other.start && self.stop <= other.stop)
+        self.contains(other.start) || self.contains(other.stop) || other.contains(self.start)
     }
     pub fn combine_sequences(
         insert_range: SequenceRange,
@@ -109,15 +171,18 @@ impl SequenceRange {
             range.overlaps(insert_range)
         });
         overlap.push(insert_range);
-        //sort the overlaps
-        overlap.sort_unstable_by(|a, b| a.start.cmp(&b.start));
+        //sort the overlaps from the earliest start
+        let base = SequenceNumber::earliest(overlap.iter().map(|range| range.start));
+        overlap.sort_unstable_by_key(|range| range.start.diff(base));
 
         //merge the overlaps
         let mut merged_ranges: Vec<SequenceRange> = Vec::new();
         for range in overlap {
             if let Some(last_range) = merged_ranges.last_mut() {
-                if range.start <= last_range.stop {
-                    last_range.stop = last_range.stop.max(range.stop);
+                if range.start.serial_le(last_range.stop) {
+                    if last_range.stop.serial_lt(range.stop) {
+                        last_range.stop = range.stop;
+                    }
                 } else {
                     merged_ranges.push(range)
                 }
@@ -129,6 +194,33 @@ impl SequenceRange {
         safe.extend_from_slice(&merged_ranges);
         safe
     }
+    //the seqs as the fewest back to back ranges, the gaps are left out
+    pub fn runs(seqs: &[SequenceNumber]) -> Vec<SequenceRange> {
+        let mut seqs = seqs.to_vec();
+        let base = SequenceNumber::earliest(seqs.iter().copied());
+        seqs.sort_unstable_by_key(|seq| seq.diff(base));
+        seqs.dedup();
+        let mut runs: Vec<SequenceRange> = Vec::new();
+        seqs.into_iter().for_each(|seq| {
+            if let Some(run) = runs.last_mut() {
+                let mut next = run.stop;
+                next.inc();
+                if next == seq {
+                    run.stop = seq;
+                    return;
+                }
+            }
+            runs.push(SequenceRange { start: seq, stop: seq });
+        });
+        runs
+    }
+    pub fn for_each_seq<F: FnMut(SequenceNumber)>(&self, mut f: F) {
+        let mut seq = self.start;
+        while seq.serial_le(self.stop) {
+            f(seq);
+            seq.inc();
+        }
+    }
 }
 
 impl Serial for SequenceRange {
@@ -145,10 +237,18 @@ impl Serial for SequenceRange {
     }
 }
 
+//29 bits, the top 3 of the field carry the packet's place in the message and ordering
 impl MessageNumber {
-    const MAX: u16 = 0x1fff;
+    const MAX: u32 = 0x1fff_ffff;
+    const HALF: u32 = 0x1000_0000;
+    const NARROW: u32 = 0x1fff;
     pub const ZERO: Self = Self(0);
-    pub fn new(base: u16) -> Self {
+    //Every channel counts its messages from here, near_wrap starts them a few short of the wrap
+    #[cfg(not(feature = "near_wrap"))]
+    pub const FIRST: Self = Self(0);
+    #[cfg(feature = "near_wrap")]
+    pub const FIRST: Self = Self(Self::MAX - 0x10);
+    pub fn new(base: u32) -> Self {
         Self(base & Self::MAX)
     }
     pub fn inc(&mut self) {
@@ -157,28 +257,57 @@ impl MessageNumber {
     pub fn dec(&mut self) {
         self.0 = (Wrapping(self.0).sub(Wrapping(1))).0 & Self::MAX;
     }
-    pub fn add(&mut self, other: u16) {
+    pub fn add(&mut self, other: u32) {
         self.0 = (Wrapping(self.0).add(Wrapping(other))).0 & Self::MAX;
     }
-    pub fn length(&self, other: &Self) -> u16 {
-        if self.0 <= other.0 {
-            other.0 - self.0 + 1
-        } else {
-            other.0 - self.0 + Self::MAX + 2
+    pub fn diff(&self, other: Self) -> u32 {
+        (Wrapping(self.0).sub(Wrapping(other.0))).0 & Self::MAX
+    }
+    pub fn length(&self, other: &Self) -> u32 {
+        other.diff(*self) + 1
+    }
+    pub fn serial_cmp(&self, other: Self) -> Ordering {
+        match other.diff(*self) {
+            0 => Ordering::Equal,
+            ahead if ahead < Self::HALF => Ordering::Less,
+            _ => Ordering::Greater,
+        }
+    }
+    //true if this is in the half of the number space that comes before other
+    pub fn serial_lt(&self, other: Self) -> bool {
+        self.serial_cmp(other) == Ordering::Less
+    }
+    //The low 13 bits, all a version 1 partner has room for
+    pub fn narrow(&self) -> Self {
+        Self(self.0 & Self::NARROW)
+    }
+    pub fn widen(&self, near: Self) -> Self {
+        let ahead = self.0.wrapping_sub(near.0) & Self::NARROW;
+        match ahead <= Self::NARROW / 2 {
+            true => Self::new(near.0.wrapping_add(ahead)),
+            false => Self::new(near.0.wrapping_sub(Self::NARROW + 1 - ahead)),
         }
     }
 }
 
+
 impl Serial for MessageNumber {
     fn serialize(&self) -> Vec<u8> {
         self.0.serialize()
     }
 
     fn deserialize(bytes: &[u8], start: &mut usize) -> Self {
-        let out = u16::deserialize(bytes, start) & Self::MAX;
+        let out = u32::deserialize(bytes, start) & Self::MAX;
         Self(out)
     }
 }
+//Microseconds since the epoch, it wraps after about 71 minutes so only differences mean anything
+pub fn timestamp(epoch: SystemTime) -> u32 {
+    match epoch.elapsed() {
+        Ok(elapsed) => elapsed.as_micros() as u32,
+        Err(_) => 0,
+    }
+}
 pub fn hash(value: usize)->usize{
     let state = Wrapping(value)*Wrapping(747796405)+Wrapping(2891336453);
     let word = ((state>>((state>>28)+Wrapping(4)).0)^state)*Wrapping(277803737);